@@ -0,0 +1,265 @@
+#[macro_use]
+extern crate log;
+extern crate env_logger;
+extern crate rustc_serialize;
+extern crate docopt;
+
+#[macro_use]
+extern crate whisper;
+
+use docopt::Docopt;
+use whisper::{WhisperCache, Schema, SchemaRegistry, WriteBehindCache, WriteBehindConfig, WriteAheadLog, InfluxTemplate, TagOrder};
+use whisper::carbon::{self, Protocol, Shutdown};
+use whisper::statsd::{self, StatsdConfig};
+use whisper::collectd::{self, Decoder, TypesDb};
+use whisper::finder::MetricIndex;
+use whisper::web::{self, GraphiteWeb};
+use std::net::{TcpListener, UdpSocket};
+use std::process;
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::time::Duration;
+
+static USAGE: &'static str = "
+Whisper carbon-cache: receives metrics and writes them to whisper files
+
+Usage:
+    whisper-carbon [options]
+    whisper-carbon (-h | --help)
+
+Options:
+    -h --help                  Show this screen.
+    --storage-dir <dir>        Directory holding the whisper tree [default: /opt/graphite/storage/whisper]
+    --schemas <file>           storage-schemas.conf used to create new files
+    --retentions <specs>       Retentions for every new file when no --schemas is given [default: 60s:1d]
+    --max-open-files <count>   Whisper files kept open at once [default: 1000]
+    --cache-write-strategy <strategy>
+                               Order metrics are written in: max, sorted or naive [default: sorted]
+    --max-cache-size <points>  Points held in memory before new ones are dropped, unlimited if absent
+    --max-updates-per-second <count>
+                               Batch writes allowed per second [default: 500]
+    --wal-dir <dir>            Log received points here so a crash loses nothing, disabled if absent
+    --wal-segment-size <bytes>
+                               Size at which a new log segment is started [default: 67108864]
+    --wal-sync-interval <ms>   Milliseconds logged points may wait to be fsynced, 0 for every batch [default: 0]
+    --interface <addr>         Address the receivers listen on [default: 0.0.0.0]
+    --line-port <port>         TCP port for the plaintext protocol [default: 2003]
+    --udp-port <port>          UDP port for the plaintext protocol, disabled if absent
+    --pickle-port <port>       TCP port for the pickle protocol, usually 2004, disabled if absent
+    --opentsdb-port <port>     TCP port for OpenTSDB put lines and carbon plaintext, disabled if absent
+    --opentsdb-tag-order <keys>
+                               Tags appended to put metric names first, like host,cpu. Others follow by key.
+    --influx-port <port>       TCP port for InfluxDB line protocol, disabled if absent
+    --influx-template <template>
+                               How line protocol is named, like measurement.host.field [default: measurement.field]
+    --statsd-port <port>       UDP port for StatsD metrics, disabled if absent
+    --statsd-flush-interval <seconds>
+                               Seconds between writes of aggregated StatsD metrics [default: 10]
+    --statsd-percentiles <list>
+                               Timer percentiles to write, like 90,99.9 [default: 90]
+    --collectd-port <port>     UDP port for collectd's binary network protocol, usually 25826, disabled if absent
+    --collectd-prefix <prefix>
+                               First nodes of every collectd metric name [default: collectd]
+    --collectd-types-db <file>
+                               collectd's types.db, naming the values of multi-value types
+    --http-port <port>         Serve the graphite-web render and find API on this port, disabled if absent
+    --index-snapshot <file>    Load the metric index from here instead of scanning, and save it on exit
+";
+
+docopt_args! {
+    struct Args {
+        flag_storage_dir: String,
+        flag_schemas: Option<String>,
+        flag_retentions: String,
+        flag_max_open_files: usize,
+        flag_cache_write_strategy: String,
+        flag_max_cache_size: Option<usize>,
+        flag_max_updates_per_second: u32,
+        flag_wal_dir: Option<String>,
+        flag_wal_segment_size: u64,
+        flag_wal_sync_interval: u64,
+        flag_interface: String,
+        flag_line_port: u16,
+        flag_udp_port: Option<u16>,
+        flag_pickle_port: Option<u16>,
+        flag_opentsdb_port: Option<u16>,
+        flag_opentsdb_tag_order: Option<String>,
+        flag_influx_port: Option<u16>,
+        flag_influx_template: String,
+        flag_statsd_port: Option<u16>,
+        flag_statsd_flush_interval: u32,
+        flag_statsd_percentiles: String,
+        flag_collectd_port: Option<u16>,
+        flag_collectd_prefix: String,
+        flag_collectd_types_db: Option<String>,
+        flag_http_port: Option<u16>,
+        flag_index_snapshot: Option<String>
+    }
+}
+
+pub fn main(){
+    env_logger::init().unwrap();
+    let args: Args = Docopt::new(USAGE)
+                            .and_then(|d| d.decode())
+                            .unwrap_or_else(|e| e.exit());
+
+    let schemas = match args.flag_schemas {
+        Some(ref path) => SchemaRegistry::from_config_file(path),
+        None => {
+            let specs = args.flag_retentions.split(',').map(|spec| spec.trim().to_string()).collect();
+            Schema::new_from_retention_specs(specs).map(SchemaRegistry::new)
+        }
+    }.unwrap_or_else(|e| fail(e));
+
+    let mut cache = WhisperCache::with_registry(&args.flag_storage_dir, args.flag_max_open_files, schemas);
+
+    // Only worth building when something will use it
+    let index = if args.flag_http_port.is_some() || args.flag_index_snapshot.is_some() {
+        let index = MetricIndex::load_or_scan(args.flag_index_snapshot.as_ref(), &args.flag_storage_dir)
+            .unwrap_or_else(|e| fail(e));
+        cache.set_index(index.clone());
+        Some(index)
+    } else {
+        None
+    };
+    let config = WriteBehindConfig {
+        strategy: args.flag_cache_write_strategy.parse().unwrap_or_else(|e: String| fail(e)),
+        max_cache_size: args.flag_max_cache_size,
+        max_updates_per_second: Some(args.flag_max_updates_per_second)
+    };
+    let write_behind = match args.flag_wal_dir {
+        Some(ref dir) => {
+            let mut wal = WriteAheadLog::open(dir, args.flag_wal_segment_size).unwrap_or_else(|e| fail(e));
+            wal.set_sync_interval(Duration::from_millis(args.flag_wal_sync_interval));
+            let replayed = wal.replay_into(&mut cache).unwrap_or_else(|e| fail(e));
+            info!("replayed {} points from the write-ahead log", replayed);
+            WriteBehindCache::with_wal(config, wal)
+        },
+        None => WriteBehindCache::new(config)
+    };
+    let writer = write_behind.spawn_writer(cache);
+
+    let shutdown = Shutdown::new();
+    shutdown.on_signals();
+
+    let (sink, points) = mpsc::channel();
+    let mut listeners = vec![];
+
+    let interface = args.flag_interface.as_str();
+    let tcp = TcpListener::bind((interface, args.flag_line_port)).unwrap_or_else(|e| fail(e));
+    info!("plaintext receiver listening on {}:{}/tcp", interface, args.flag_line_port);
+    listeners.push(carbon::spawn_tcp_listener(tcp, Protocol::Plaintext, sink.clone(), shutdown.clone()).unwrap_or_else(|e| fail(e)));
+
+    if let Some(port) = args.flag_pickle_port {
+        let pickle = TcpListener::bind((interface, port)).unwrap_or_else(|e| fail(e));
+        info!("pickle receiver listening on {}:{}/tcp", interface, port);
+        listeners.push(carbon::spawn_tcp_listener(pickle, Protocol::Pickle, sink.clone(), shutdown.clone()).unwrap_or_else(|e| fail(e)));
+    }
+
+    if let Some(port) = args.flag_opentsdb_port {
+        let order = args.flag_opentsdb_tag_order.as_ref()
+            .map(|order| TagOrder::new(order).unwrap_or_else(|e| fail(e)))
+            .unwrap_or_default();
+        let opentsdb = TcpListener::bind((interface, port)).unwrap_or_else(|e| fail(e));
+        info!("put receiver listening on {}:{}/tcp", interface, port);
+        listeners.push(carbon::spawn_tcp_listener(opentsdb, Protocol::OpenTsdb(order), sink.clone(), shutdown.clone()).unwrap_or_else(|e| fail(e)));
+    }
+
+    if let Some(port) = args.flag_influx_port {
+        let template = InfluxTemplate::new(&args.flag_influx_template).unwrap_or_else(|e| fail(e));
+        let influx = TcpListener::bind((interface, port)).unwrap_or_else(|e| fail(e));
+        info!("line protocol receiver listening on {}:{}/tcp", interface, port);
+        listeners.push(carbon::spawn_tcp_listener(influx, Protocol::Influx(template), sink.clone(), shutdown.clone()).unwrap_or_else(|e| fail(e)));
+    }
+
+    if let Some(port) = args.flag_udp_port {
+        let udp = UdpSocket::bind((interface, port)).unwrap_or_else(|e| fail(e));
+        info!("plaintext receiver listening on {}:{}/udp", interface, port);
+        listeners.push(carbon::spawn_udp_listener(udp, sink.clone(), shutdown.clone()).unwrap_or_else(|e| fail(e)));
+    }
+
+    if let Some(port) = args.flag_statsd_port {
+        if args.flag_statsd_flush_interval == 0 {
+            fail::<_, ()>("--statsd-flush-interval must be at least 1 second");
+        }
+        let config = StatsdConfig {
+            flush_interval: args.flag_statsd_flush_interval,
+            percent_thresholds: statsd::parse_percent_thresholds(&args.flag_statsd_percentiles).unwrap_or_else(|e| fail(e))
+        };
+        let udp = UdpSocket::bind((interface, port)).unwrap_or_else(|e| fail(e));
+        info!("statsd listening on {}:{}/udp", interface, port);
+        listeners.push(statsd::spawn_udp_listener(udp, config, sink.clone(), shutdown.clone()).unwrap_or_else(|e| fail(e)));
+    }
+
+    if let Some(port) = args.flag_collectd_port {
+        let types = match args.flag_collectd_types_db {
+            Some(ref path) => TypesDb::from_file(path).unwrap_or_else(|e| fail(e)),
+            None => TypesDb::default()
+        };
+        let udp = UdpSocket::bind((interface, port)).unwrap_or_else(|e| fail(e));
+        info!("collectd receiver listening on {}:{}/udp", interface, port);
+        listeners.push(collectd::spawn_udp_listener(udp, Decoder::new(&args.flag_collectd_prefix, types), sink.clone(), shutdown.clone())
+            .unwrap_or_else(|e| fail(e)));
+    }
+
+    let mut server = None;
+    if let (Some(port), Some(index)) = (args.flag_http_port, index.as_ref()) {
+        let graphite = GraphiteWeb::with_index(args.flag_storage_dir.clone(), index.clone());
+        let http = TcpListener::bind((interface, port)).unwrap_or_else(|e| fail(e));
+        info!("render api listening on {}:{}", interface, port);
+        server = Some(web::http::serve(http, Arc::new(move |request| graphite.handle(request)), shutdown.clone())
+            .unwrap_or_else(|e| fail(e)));
+    }
+
+    // Only the listeners hold senders now, so this returns once they have all hung up
+    drop(sink);
+    carbon::store_points(points, &write_behind);
+
+    for listener in listeners {
+        listener.join().unwrap_or_else(|_| warn!("listener thread panicked"));
+    }
+
+    info!("writing {} queued points", write_behind.size());
+    write_behind.close();
+    writer.join().unwrap_or_else(|_| fail("writer thread panicked"));
+    info!("all points written");
+
+    if let Some(server) = server {
+        server.join().unwrap_or_else(|_| fail("server thread panicked"));
+    }
+    if let (Some(snapshot), Some(index)) = (args.flag_index_snapshot.as_ref(), index.as_ref()) {
+        index.save(snapshot).unwrap_or_else(|e| fail(e));
+        info!("saved metric index to {}", snapshot);
+    }
+    info!("exiting");
+}
+
+fn fail<E: std::fmt::Display, T>(why: E) -> T {
+    error!("{}", why);
+    process::exit(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(argv: &[&str]) -> Result<Args, docopt::Error> {
+        Docopt::new(USAGE).and_then(|d| d.argv(argv.iter().cloned()).decode())
+    }
+
+    #[test]
+    fn test_usage() {
+        let args = parse(&["whisper-carbon"]).unwrap();
+        assert_eq!(args.flag_line_port, 2003);
+        assert_eq!(args.flag_max_open_files, 1000);
+        assert_eq!(args.flag_cache_write_strategy, "sorted");
+        assert_eq!(args.flag_statsd_port, None);
+        assert_eq!(args.flag_pickle_port, None);
+
+        let args = parse(&["whisper-carbon", "--statsd-port", "8125", "--pickle-port", "2004", "--schemas", "storage-schemas.conf"]).unwrap();
+        assert_eq!(args.flag_statsd_port, Some(8125));
+        assert_eq!(args.flag_pickle_port, Some(2004));
+        assert_eq!(args.flag_schemas, Some("storage-schemas.conf".to_string()));
+        assert!(parse(&["whisper-carbon", "--line-port", "-1"]).is_err());
+    }
+}
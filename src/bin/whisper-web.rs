@@ -0,0 +1,115 @@
+#[macro_use]
+extern crate log;
+extern crate env_logger;
+extern crate rustc_serialize;
+extern crate docopt;
+
+#[macro_use]
+extern crate whisper;
+
+use docopt::Docopt;
+use whisper::carbon::Shutdown;
+use whisper::finder::MetricIndex;
+use whisper::web::{ self, GraphiteWeb };
+use std::net::TcpListener;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::{ Duration, Instant };
+
+static USAGE: &'static str = "
+Whisper web: serves a whisper tree over graphite-web's HTTP render API
+
+Usage:
+    whisper-web [options]
+    whisper-web (-h | --help)
+
+Options:
+    -h --help                  Show this screen.
+    --storage-dir <dir>        Directory holding the whisper tree [default: /opt/graphite/storage/whisper]
+    --interface <addr>         Address to listen on [default: 0.0.0.0]
+    --port <port>              TCP port for HTTP requests [default: 8080]
+    --index-snapshot <file>    Load the metric index from here instead of scanning, and save it on exit
+    --rescan-interval <secs>   Rescan the tree for new metrics this often, 0 to never rescan [default: 300]
+";
+
+docopt_args! {
+    struct Args {
+        flag_storage_dir: String,
+        flag_interface: String,
+        flag_port: u16,
+        flag_index_snapshot: Option<String>,
+        flag_rescan_interval: u64
+    }
+}
+
+pub fn main(){
+    env_logger::init().unwrap();
+    let args: Args = Docopt::new(USAGE)
+                            .and_then(|d| d.decode())
+                            .unwrap_or_else(|e| e.exit());
+
+    let shutdown = Shutdown::new();
+    shutdown.on_signals();
+
+    let index = MetricIndex::load_or_scan(args.flag_index_snapshot.as_ref(), &args.flag_storage_dir)
+        .unwrap_or_else(|e| fail(e));
+    let rescanner = spawn_rescanner(index.clone(), args.flag_storage_dir.clone(),
+        Duration::from_secs(args.flag_rescan_interval), shutdown.clone());
+
+    let graphite = GraphiteWeb::with_index(args.flag_storage_dir.clone(), index.clone());
+    let listener = TcpListener::bind((args.flag_interface.as_str(), args.flag_port)).unwrap_or_else(|e| fail(e));
+    info!("serving render requests on {}:{}", args.flag_interface, args.flag_port);
+
+    let server = web::http::serve(listener, Arc::new(move |request| graphite.handle(request)), shutdown)
+        .unwrap_or_else(|e| fail(e));
+    server.join().unwrap_or_else(|_| fail("server thread panicked"));
+    rescanner.join().unwrap_or_else(|_| fail("rescan thread panicked"));
+
+    if let Some(ref snapshot) = args.flag_index_snapshot {
+        index.save(snapshot).unwrap_or_else(|e| fail(e));
+        info!("saved metric index to {}", snapshot);
+    }
+    info!("exiting");
+}
+
+// Other processes create the files, so look for new ones every so often
+fn spawn_rescanner(index: MetricIndex, base_path: String, interval: Duration, shutdown: Shutdown) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut last_scan = Instant::now();
+        while !shutdown.is_requested() {
+            if interval > Duration::from_secs(0) && last_scan.elapsed() >= interval {
+                index.rescan(&base_path).unwrap_or_else(|e| warn!("unable to rescan {}: {}", base_path, e));
+                last_scan = Instant::now();
+            }
+            thread::sleep(Duration::from_millis(250));
+        }
+    })
+}
+
+fn fail<E: std::fmt::Display, T>(why: E) -> T {
+    error!("{}", why);
+    process::exit(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(argv: &[&str]) -> Result<Args, docopt::Error> {
+        Docopt::new(USAGE).and_then(|d| d.argv(argv.iter().cloned()).decode())
+    }
+
+    #[test]
+    fn test_usage() {
+        let args = parse(&["whisper-web"]).unwrap();
+        assert_eq!(args.flag_storage_dir, "/opt/graphite/storage/whisper");
+        assert_eq!(args.flag_port, 8080);
+        assert_eq!(args.flag_index_snapshot, None);
+
+        let args = parse(&["whisper-web", "--port", "9090", "--index-snapshot", "/tmp/index"]).unwrap();
+        assert_eq!(args.flag_port, 9090);
+        assert_eq!(args.flag_index_snapshot, Some("/tmp/index".to_string()));
+        assert!(parse(&["whisper-web", "--port", "http"]).is_err());
+    }
+}
@@ -5,43 +5,104 @@ extern crate rustc_serialize;
 extern crate docopt;
 extern crate time;
 
+#[macro_use]
 extern crate whisper;
 
 use docopt::Docopt;
-use whisper::{WhisperFile, Point, Schema, AggregationType};
+use rustc_serialize::json::{ Json, ToJson };
+use whisper::{WhisperFile, Point, Schema, AggregationType, Allocation, InfluxTemplate};
+use whisper::{ import, export, query, web };
+use whisper::finder::MetricIndex;
+use std::fs;
+use std::io::{ self, Read, Write };
 use std::path::Path;
+use std::process;
 
 static USAGE: &'static str = "
 Whisper is the fast file manipulator
 
 Usage:
-    whisper info <file>
-    whisper dump <file>
-    whisper update <file> <timestamp> <value>
+    whisper info [--json] <file> [<field>]
+    whisper dump [--pretty] [--time-format <format>] [--json] [--archive <index>] <file>
+    whisper fetch [--from <time>] [--until <time>] [--json | --csv | --influx] [--pretty] [--time-format <format>] [--drop <values>] [--template <template>] [--name <metric>] <file>
+    whisper update <file> <update>...
     whisper mark <file> <value>
     whisper thrash <file> <value> <times>
-    whisper create <file> <timespec>...
+    whisper create [--xff <x_files_factor>] [--aggregation-method <method>] [--overwrite] [--sparse | --fallocate] <file> <timespec>...
+    whisper create --estimate [--metrics <count>] <timespec>...
+    whisper export [--format <format>] [--archive <index>] [--labels <template>] [--template <template>] [--name <metric>] <path>
+    whisper import [--format <format>] [--create <retentions>] [--xff <x_files_factor>] [--aggregation-method <method>] [--sparse | --fallocate] <file> [<input>]
 
 Options:
-    --xff <x_files_factor>
-    --aggregation_method <method>
+    --json                          Print JSON instead of text
+    --csv                           Print comma-separated timestamp,value lines
+    --influx                        Print InfluxDB line protocol
+    --from <time>                   Start of the range, a graphite time expression [default: -24h]
+    --until <time>                  End of the range, a graphite time expression [default: now]
+    --pretty                        Show human-readable UTC timestamps instead of epoch seconds
+    --archive <index>               Only dump this archive, or export this one instead of the first
+    --time-format <format>          strftime format for --pretty timestamps
+    --drop <values>                 Leave out nulls, zeroes or empty (both) values
+    --xff <x_files_factor>          Fraction of points that must be known to roll up [default: 0.5]
+    --aggregation-method <method>   average, sum, last, max or min [default: average]
+    --overwrite                     Replace the file if it already exists
+    --sparse                        Don't allocate the file's blocks up front
+    --fallocate                     Allocate the file's blocks with fallocate instead of writing zeroes
+    --estimate                      Print the disk space a schema needs instead of creating a file
+    --metrics <count>               Number of metrics to estimate for
+    --format <format>               Records to import: csv (the default), json or carbon.
+                                    Or the format to export: openmetrics (the default) or influx.
+    --labels <template>             Turn metric path segments in to labels, like servers.{host}.*
+    --template <template>           Split metric names in to a line protocol measurement, tags and
+                                    field, like measurement.host.field
+    --name <metric>                 Metric name for a single file instead of its file name
+    --create <retentions>           Create the file with these retentions, like 10s:6h,1m:7d, if it doesn't exist
 ";
 
-#[derive(RustcDecodable, Debug)]
-struct Args {
-    cmd_info: bool,
-    cmd_dump: bool,
-    cmd_update: bool,
-    cmd_mark: bool,
-    cmd_thrash: bool,
-    cmd_create: bool,
+docopt_args! {
+    struct Args {
+        cmd_info: bool,
+        cmd_dump: bool,
+        cmd_fetch: bool,
+        cmd_update: bool,
+        cmd_mark: bool,
+        cmd_thrash: bool,
+        cmd_create: bool,
+        cmd_import: bool,
+        cmd_export: bool,
+
+        arg_file: String,
+        arg_field: Option<String>,
+        arg_input: Option<String>,
+        arg_path: String,
+        arg_update: Vec<String>,
+        arg_value: String,
+        arg_times: String,
 
-    arg_file: String,
-    arg_timestamp: String,
-    arg_value: String,
-    arg_times: String,
+        arg_timespec: Vec<String>,
 
-    arg_timespec: Vec<String>
+        flag_json: bool,
+        flag_csv: bool,
+        flag_influx: bool,
+        flag_from: String,
+        flag_until: String,
+        flag_pretty: bool,
+        flag_time_format: Option<String>,
+        flag_drop: Option<String>,
+        flag_archive: Option<usize>,
+        flag_xff: f32,
+        flag_aggregation_method: String,
+        flag_overwrite: bool,
+        flag_sparse: bool,
+        flag_fallocate: bool,
+        flag_estimate: bool,
+        flag_metrics: Option<u64>,
+        flag_format: Option<String>,
+        flag_labels: Option<String>,
+        flag_template: Option<String>,
+        flag_name: Option<String>,
+        flag_create: Option<String>
+    }
 }
 
 
@@ -59,9 +120,11 @@ pub fn main(){
     let current_time = time::get_time().sec as u64;
 
     if args.cmd_info {
-        cmd_info(path);
+        cmd_info(args, path);
     } else if args.cmd_dump {
-        cmd_dump(path);
+        cmd_dump(args, path);
+    } else if args.cmd_fetch {
+        cmd_fetch(args, path, current_time);
     } else if args.cmd_update {
         cmd_update(args, path, current_time);
     } else if args.cmd_mark {
@@ -70,34 +133,180 @@ pub fn main(){
         cmd_thrash(args, path, current_time);
     } else if args.cmd_create {
         cmd_create(args, path);
+    } else if args.cmd_import {
+        cmd_import(args, path, current_time);
+    } else if args.cmd_export {
+        cmd_export(args, current_time);
     } else {
         println!("Must specify command.");
     }
 }
 
-fn cmd_info<P>(path: P)
+fn cmd_info<P>(args: Args, path: P)
   where P: AsRef<Path> {
-    let whisper_file = WhisperFile::open(path);
-    // TODO: used to simpler of Display, not Debug
-    println!("{:?}", whisper_file);
+    let info = WhisperFile::open(path).unwrap_or_else(|e| fail(e)).info();
+    let json = info.to_json();
+
+    match args.arg_field {
+        // One value, for scripts
+        Some(ref field) => match json.find(field) {
+            Some(&Json::String(ref value)) => println!("{}", value),
+            Some(value) => println!("{}", value),
+            None => {
+                let fields: Vec<&str> = json.as_object().unwrap().keys().map(|key| key.as_str()).collect();
+                fail::<_, ()>(format!("Unknown field \"{}\". Valid fields are {}", field, fields.join(",")));
+            }
+        },
+        None if args.flag_json => println!("{}", json.pretty()),
+        None => print!("{}", info)
+    }
 }
 
-fn cmd_dump<P>(path: P)
+// whisper-dump.py: the header, each archive's header, then every slot of
+// every archive in the order it is stored
+fn cmd_dump<P>(args: Args, path: P)
   where P: AsRef<Path> {
-    let whisper_file = WhisperFile::open(path);
-    println!("{:?}", whisper_file);
+    let file = WhisperFile::open(path).unwrap_or_else(|e| fail(e));
+    let info = file.info();
+    let indexes: Vec<usize> = match args.flag_archive {
+        Some(index) if index < file.archives.len() => vec![index],
+        Some(index) => fail(format!("Archive {} does not exist, the file has {} archives", index, file.archives.len())),
+        None => (0..file.archives.len()).collect()
+    };
+    let archive_points: Vec<Vec<Point>> = indexes.iter()
+        .map(|&index| file.archives[index].all_points().unwrap_or_else(|e| fail(e)))
+        .collect();
+
+    if args.flag_json {
+        let mut json = info.to_json();
+        if let Json::Object(ref mut object) = json {
+            let archives = indexes.iter().zip(archive_points.iter()).map(|(&index, points)| {
+                let mut archive = info.archives[index].to_json();
+                if let Json::Object(ref mut archive) = archive {
+                    archive.insert("index".to_string(), Json::U64(index as u64));
+                    archive.insert("data".to_string(), Json::Array(points.iter().map(|point| {
+                        let value = if point.1.is_finite() { Json::F64(point.1) } else { Json::Null };
+                        Json::Array(vec![Json::U64(point.0 as u64), value])
+                    }).collect()));
+                }
+                archive
+            }).collect();
+            object.insert("archives".to_string(), Json::Array(archives));
+        }
+        println!("{}", json);
+        return;
+    }
+
+    println!("Meta data:");
+    println!("  aggregation method: {}", info.aggregation_method);
+    println!("  max retention: {}", info.max_retention);
+    println!("  xFilesFactor: {}", format_g(info.x_files_factor as f64, 6));
+    println!("");
+    for &index in &indexes {
+        let archive = &info.archives[index];
+        println!("Archive {} info:", index);
+        println!("  offset: {}", archive.offset);
+        println!("  seconds per point: {}", archive.seconds_per_point);
+        println!("  points: {}", archive.points);
+        println!("  retention: {}", archive.retention);
+        println!("  size: {}", archive.size);
+        println!("");
+    }
+
+    for (&index, points) in indexes.iter().zip(archive_points.iter()) {
+        println!("Archive {} data:", index);
+        for (slot, point) in points.iter().enumerate() {
+            println!("{}: {}, {:>10}", slot, format_timestamp(&args, point.0), format_g(point.1, 35));
+        }
+        println!("");
+    }
+}
+
+// whisper-fetch.py, with graphite time expressions for the range
+fn cmd_fetch<P>(args: Args, path: P, current_time: u64)
+  where P: AsRef<Path> {
+    let now = current_time as u32;
+    let from = query::parse_time(&args.flag_from, now).unwrap_or_else(|e| fail(e));
+    let until = query::parse_time(&args.flag_until, now).unwrap_or_else(|e| fail(e));
+    let keep: fn(&Option<f64>) -> bool = match args.flag_drop.as_ref().map(|drop| drop.as_str()) {
+        None => |_| true,
+        Some("nulls") => |value| value.is_some(),
+        Some("zeroes") => |value| *value != Some(0.0),
+        Some("empty") => |value| value.is_some() && *value != Some(0.0),
+        Some(drop) => fail(format!("--drop must be nulls, zeroes or empty, not {}", drop))
+    };
+
+    let name = metric_name(&args, path.as_ref());
+    let file = WhisperFile::open(path).unwrap_or_else(|e| fail(e));
+    let series = match file.fetch(from, Some(until)).unwrap_or_else(|e| fail(e)) {
+        Some(series) => series,
+        // Nothing in the file is that recent or that old
+        None => {
+            if args.flag_json {
+                println!("null");
+            }
+            return;
+        }
+    };
+
+    if args.flag_json {
+        // Exactly what whisper-fetch.py prints, which drops values without their timestamps
+        let values: Vec<String> = series.values.iter().filter(|value| keep(value)).map(|value| match *value {
+            Some(value) => format!("{:?}", value),
+            None => "null".to_string()
+        }).collect();
+        println!("{{\n    \"start\" : {},\n    \"end\" : {},\n    \"step\" : {},\n    \"values\" : [{}]\n  }}",
+            series.from, series.until, series.step, values.join(", "));
+        return;
+    }
+
+    if args.flag_influx {
+        // Line protocol has no nulls
+        let points: Vec<Point> = series.timestamps().into_iter().zip(series.values.iter())
+            .filter_map(|(timestamp, value)| value.map(|value| Point(timestamp, value)))
+            .filter(|point| keep(&Some(point.1)))
+            .collect();
+        for line in export::format_lines(&name, &points, influx_template(&args).as_ref()) {
+            println!("{}", line);
+        }
+        return;
+    }
+
+    for (timestamp, value) in series.timestamps().into_iter().zip(series.values.iter()).filter(|&(_, value)| keep(value)) {
+        let timestamp = format_timestamp(&args, timestamp);
+
+        if args.flag_csv {
+            println!("{},{}", timestamp, value.map(|value| value.to_string()).unwrap_or_default());
+        } else {
+            println!("{}\t{}", timestamp, value.map(|value| format!("{:.6}", value)).unwrap_or_else(|| "None".to_string()));
+        }
+    }
 }
 
-#[allow(unused_variables)] /*TODO: Remove once we reenable writing current_time*/
+// whisper-update.py: `timestamp:value` arguments, or `-` to read one update
+// per line from stdin. `<timestamp> <value>` still works for a single point.
 fn cmd_update<P>(args: Args, path: P, current_time: u64)
   where P: AsRef<Path> {
-    WhisperFile::open(path).map(|mut file| {
-      let point = Point(args.arg_timestamp.parse::<u32>().unwrap(),
-                        args.arg_value.parse::<f64>().unwrap());
-      debug!("Updating TS: {} with value: {}", point.0, point.1);
+    let now = current_time as u32;
+    let updates = &args.arg_update;
+    let points = if updates.len() == 1 && updates[0] == "-" {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input).unwrap_or_else(|e| fail(e));
+        import::parse_updates(&input, now).unwrap_or_else(|e| fail(e))
+    } else if updates.len() == 2 && !updates.iter().any(|update| update.contains(':')) {
+        vec![import::parse_update(&updates.join(" "), now).unwrap_or_else(|e| fail(e))]
+    } else {
+        updates.iter().map(|update| import::parse_update(update, now).unwrap_or_else(|e| fail(e))).collect()
+    };
 
-      file.write(/*current_time, TODO: reenable */ &point);
-    }).unwrap_or_else(|e| println!("Unable to open whisper file: {}", e))
+    let mut file = WhisperFile::open(path).unwrap_or_else(|e| fail(format!("Unable to open whisper file: {}", e)));
+    if points.len() == 1 {
+        debug!("Updating TS: {} with value: {}", points[0].0, points[0].1);
+        file.write(&points[0]);
+    } else {
+        file.write_many(&points);
+    }
+    file.flush().unwrap_or_else(|e| fail(e));
 }
 
 fn cmd_mark<P>(args: Args, path: P, current_time: u64)
@@ -124,11 +333,243 @@ fn cmd_thrash<P>(args: Args, path: P, current_time: u64)
 
 fn cmd_create<P>(args: Args, path: P)
   where P: AsRef<Path> {
-    let schema = Schema::new_from_retention_specs(args.arg_timespec).unwrap();
-    let new_result = WhisperFile::new(path, &schema, AggregationType::Average, 0.5);
-    match new_result {
-    	// TODO change to Display
-        Ok(whisper_file) => println!("Success! {:?}", whisper_file),
-        Err(why) => println!("Failed: {:?}", why)
+    let schema = Schema::new_from_retention_specs(args.arg_timespec.clone()).unwrap_or_else(|e| fail(e));
+    if args.flag_estimate {
+        return estimate(&schema, args.flag_metrics);
+    }
+
+    let path = path.as_ref();
+    if path.exists() {
+        if !args.flag_overwrite {
+            fail::<_, ()>(format!("File {} already exists!", path.display()));
+        }
+        println!("Overwriting existing file: {}", path.display());
+        fs::remove_file(path).unwrap_or_else(|e| fail(e));
+    }
+
+    create_file(&args, path, &schema);
+    let size = fs::metadata(path).map(|metadata| metadata.len()).unwrap_or_else(|e| fail(e));
+    println!("Created: {} ({} bytes)", path.display(), size);
+}
+
+// Creates a file with the --xff, --aggregation-method and allocation options
+fn create_file(args: &Args, path: &Path, schema: &Schema) -> WhisperFile {
+    let aggregation = args.flag_aggregation_method.parse::<AggregationType>().unwrap_or_else(|e| fail(e));
+    if args.flag_xff < 0.0 || args.flag_xff > 1.0 {
+        fail::<_, ()>(format!("xff must be between 0 and 1, not {}", args.flag_xff));
+    }
+    let allocation = if args.flag_sparse {
+        Allocation::Sparse
+    } else if args.flag_fallocate {
+        Allocation::Fallocate
+    } else {
+        Allocation::Zeroed
+    };
+
+    WhisperFile::new_with_allocation(path, schema, aggregation, args.flag_xff, allocation).unwrap_or_else(|e| fail(e))
+}
+
+// Loads history exported from elsewhere, oldest first, with batch updates
+fn cmd_import<P>(args: Args, path: P, current_time: u64)
+  where P: AsRef<Path> {
+    let path = path.as_ref();
+    let format = args.flag_format.as_ref().map(|format| format.as_str()).unwrap_or("csv")
+        .parse::<import::Format>().unwrap_or_else(|e| fail(e));
+
+    let mut input = String::new();
+    match args.arg_input.as_ref().map(|input| input.as_str()) {
+        None | Some("-") => io::stdin().read_to_string(&mut input),
+        Some(input_path) => fs::File::open(input_path).and_then(|mut file| file.read_to_string(&mut input))
+    }.unwrap_or_else(|e| fail(e));
+    let mut points = import::parse_points(format, &input).unwrap_or_else(|e| fail(e));
+    points.sort_by_key(|point| point.0);
+
+    let mut file = if path.exists() {
+        WhisperFile::open(path).unwrap_or_else(|e| fail(e))
+    } else {
+        match args.flag_create {
+            Some(ref retentions) => {
+                let schema = Schema::new_from_retention_specs(vec![retentions.clone()]).unwrap_or_else(|e| fail(e));
+                println!("Created: {}", path.display());
+                create_file(&args, path, &schema)
+            },
+            None => fail(format!("File {} does not exist, pass --create <retentions> to create it", path.display()))
+        }
+    };
+
+    let now = current_time as u32;
+    let oldest = now.saturating_sub(file.info().max_retention);
+    let too_old = points.iter().filter(|point| point.0 <= oldest).count();
+    let too_new = points.iter().filter(|point| point.0 > now).count();
+    if too_old > 0 {
+        println!("Skipped {} points older than the file's max retention (before {})", too_old, oldest + 1);
+    }
+    if too_new > 0 {
+        println!("Skipped {} points from the future", too_new);
+    }
+
+    file.write_many(&points);
+    file.flush().unwrap_or_else(|e| fail(e));
+    println!("Imported {} points in to {}", points.len() - too_old - too_new, path.display());
+}
+
+// Writes one archive of a file, or of every file under a directory, to stdout
+fn cmd_export(args: Args, current_time: u64) {
+    let now = current_time as u32;
+    let path = Path::new(&args.arg_path);
+    let influx = match args.flag_format.as_ref().map(|format| format.as_str()) {
+        None | Some("openmetrics") => false,
+        Some("influx") => true,
+        Some(format) => fail(format!("unknown export format `{}`, expected openmetrics or influx", format))
+    };
+    let archive = args.flag_archive.unwrap_or(0);
+
+    let mut series = vec![];
+    if path.is_dir() {
+        let index = MetricIndex::scan(path).unwrap_or_else(|e| fail(e));
+        for metric in index.metrics() {
+            let file_path = web::metric_path(path, &metric).unwrap_or_else(|| fail(format!("invalid metric name `{}`", metric)));
+            match WhisperFile::open(&file_path).and_then(|file| export::archive_points(&file, archive, now)) {
+                Ok(points) => series.push((metric, points)),
+                Err(e) => { let _ = writeln!(io::stderr(), "Skipping {}: {}", file_path.display(), e); }
+            }
+        }
+    } else {
+        let file = WhisperFile::open(path).unwrap_or_else(|e| fail(e));
+        series.push((metric_name(&args, path), export::archive_points(&file, archive, now).unwrap_or_else(|e| fail(e))));
+    }
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    if influx {
+        let template = influx_template(&args);
+        for (name, points) in series {
+            for line in export::format_lines(&name, &points, template.as_ref()) {
+                writeln!(stdout, "{}", line).unwrap_or_else(|e| fail(e));
+            }
+        }
+    } else {
+        let template = args.flag_labels.as_ref().map(|template| export::LabelTemplate::new(template).unwrap_or_else(|e| fail(e)));
+        let mut encoder = export::OpenMetricsEncoder::new(template);
+        for (name, points) in series {
+            encoder.add(&name, points).unwrap_or_else(|e| fail(e));
+        }
+        encoder.write_to(&mut stdout).unwrap_or_else(|e| fail(e));
+    }
+}
+
+// --name, or the file's name without its extension
+fn metric_name(args: &Args, path: &Path) -> String {
+    args.flag_name.clone()
+        .or_else(|| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| fail(format!("{} has no file name", path.display())))
+}
+
+fn influx_template(args: &Args) -> Option<InfluxTemplate> {
+    args.flag_template.as_ref().map(|template| InfluxTemplate::new(template).unwrap_or_else(|e| fail(e)))
+}
+
+// whisper-create.py's --estimate report
+fn estimate(schema: &Schema, metrics: Option<u64>) {
+    for (index, policy) in schema.retention_policies.iter().enumerate() {
+        println!("Archive {}: {} points of {}s precision", index, policy.points(), policy.precision);
+    }
+
+    let size = schema.size_on_disk() as u64;
+    let disk_size = (size + 4095) / 4096 * 4096;
+    println!("\nEstimated Whisper DB Size: {} ({} bytes on disk with 4k blocks)\n", byte_format(size), disk_size);
+    match metrics {
+        Some(count) => println!("Estimated storage requirement for {} metrics: {}", count, byte_format(count * disk_size)),
+        None => for &thousands in [1, 5, 10, 50, 100, 500].iter() {
+            println!("Estimated storage requirement for {}k metrics: {}", thousands, byte_format(thousands * 1000 * disk_size));
+        }
+    }
+}
+
+// Epoch seconds, or with --pretty a UTC ctime or --time-format string
+fn format_timestamp(args: &Args, timestamp: u32) -> String {
+    if !args.flag_pretty {
+        return timestamp.to_string();
+    }
+    let tm = time::at_utc(time::Timespec::new(timestamp as i64, 0));
+    match args.flag_time_format {
+        Some(ref format) => time::strftime(format, &tm).unwrap_or_else(|e| fail(e)),
+        None => tm.asctime().to_string()
+    }
+}
+
+// python's `%.<precision>g`
+fn format_g(number: f64, precision: usize) -> String {
+    if number == 0.0 || !number.is_finite() {
+        return match number {
+            number if number.is_nan() => "nan".to_string(),
+            number if number.is_infinite() => (if number > 0.0 { "inf" } else { "-inf" }).to_string(),
+            number if number.is_sign_negative() => "-0".to_string(),
+            _ => "0".to_string()
+        };
+    }
+
+    // Round first, so the exponent is the one %g would see
+    let scientific = format!("{:.*e}", precision - 1, number);
+    let split = scientific.find('e').unwrap();
+    let exponent: i32 = scientific[split + 1 ..].parse().unwrap();
+    if exponent < -4 || exponent >= precision as i32 {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", trim_zeros(&scientific[..split]), sign, exponent.abs())
+    } else {
+        let decimals = (precision as i32 - 1 - exponent) as usize;
+        trim_zeros(&format!("{:.*}", decimals, number)).to_string()
+    }
+}
+
+fn trim_zeros(number: &str) -> &str {
+    if number.contains('.') {
+        number.trim_right_matches('0').trim_right_matches('.')
+    } else {
+        number
+    }
+}
+
+fn byte_format(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for unit in ["bytes", "KB", "MB"].iter() {
+        if size < 1024.0 {
+            return format!("{:.3}{}", size, unit);
+        }
+        size = size / 1024.0;
+    }
+    format!("{:.3}GB", size)
+}
+
+fn fail<E: std::fmt::Display, T>(why: E) -> T {
+    let _ = writeln!(io::stderr(), "{}", why.to_string().trim_right());
+    process::exit(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(argv: &[&str]) -> Result<Args, docopt::Error> {
+        Docopt::new(USAGE).and_then(|d| d.argv(argv.iter().cloned()).decode())
+    }
+
+    #[test]
+    fn test_usage() {
+        let args = parse(&["whisper", "update", "a.wsp", "60:1", "120:2"]).unwrap();
+        assert!(args.cmd_update && !args.cmd_info);
+        assert_eq!(args.arg_file, "a.wsp");
+        assert_eq!(args.arg_update, vec!["60:1".to_string(), "120:2".to_string()]);
+
+        let args = parse(&["whisper", "create", "--xff", "0.1", "a.wsp", "60s:1d"]).unwrap();
+        assert!(args.cmd_create);
+        assert_eq!(args.flag_xff, 0.1);
+        assert_eq!(args.flag_aggregation_method, "average");
+        assert_eq!(args.arg_timespec, vec!["60s:1d".to_string()]);
+
+        let args = parse(&["whisper", "dump", "--archive", "1", "a.wsp"]).unwrap();
+        assert_eq!(args.flag_archive, Some(1));
+        assert_eq!(args.flag_from, "-24h");
+        assert!(parse(&["whisper", "frobnicate", "a.wsp"]).is_err());
     }
 }
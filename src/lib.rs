@@ -7,6 +7,7 @@ extern crate libc;
 #[cfg(test)] extern crate test;
 extern crate lru_cache;
 extern crate time;
+extern crate rustc_serialize;
 
 #[macro_use]
 extern crate log;
@@ -14,4 +15,14 @@ extern crate log;
 mod whisper;
 
 pub use whisper::errors;
-pub use self::whisper::{WhisperFile, AggregationType, Point, Schema, WhisperCache, NamedPoint};
+pub use whisper::carbon;
+pub use whisper::statsd;
+pub use whisper::collectd;
+pub use whisper::web;
+pub use whisper::finder;
+pub use whisper::query;
+pub use whisper::import;
+pub use whisper::export;
+pub use self::whisper::{WhisperFile, AggregationType, Allocation, Point, Schema, SchemaRegistry, SchemaRule, WhisperCache, NamedPoint, SeriesData};
+pub use self::whisper::{FileInfo, ArchiveInfo};
+pub use self::whisper::{WriteBehindCache, WriteBehindConfig, DrainStrategy, WriteAheadLog, InfluxTemplate, TagOrder};
@@ -0,0 +1,34 @@
+// Command line arguments for the binaries, decoded by docopt.
+//
+// docopt 0.6 decodes in to anything `rustc_serialize::Decodable`, which used
+// to come from `#[derive(RustcDecodable)]`. Compilers no longer provide that
+// derive, so `docopt_args!` declares the struct and writes the impl the derive
+// would have. The calling crate needs `extern crate rustc_serialize`.
+//
+//     docopt_args! {
+//         struct Args {
+//             flag_port: u16,
+//             arg_file: Option<String>
+//         }
+//     }
+
+#[macro_export]
+macro_rules! docopt_args {
+    (struct $name:ident { $($field:ident : $ty:ty),* $(,)* }) => {
+        #[derive(Debug)]
+        struct $name {
+            $($field: $ty),*
+        }
+
+        impl ::rustc_serialize::Decodable for $name {
+            fn decode<D: ::rustc_serialize::Decoder>(decoder: &mut D) -> Result<$name, D::Error> {
+                let fields = [$(stringify!($field)),*];
+                decoder.read_struct(stringify!($name), fields.len(), |decoder| {
+                    Ok($name {
+                        $($field: try!(decoder.read_struct_field(stringify!($field), 0, ::rustc_serialize::Decodable::decode))),*
+                    })
+                })
+            }
+        }
+    }
+}
@@ -0,0 +1,238 @@
+// InfluxDB line protocol: `measurement,tag=v,... field=v,... [timestamp]`.
+//
+// Whisper has no tags, so an `InfluxTemplate` decides how the measurement,
+// tags and field key are laid out in the dotted metric name, and how a
+// metric name is split back up when exporting.
+
+use super::NamedPoint;
+
+const NANOS_PER_SECOND : i64 = 1_000_000_000;
+
+// Dotted segments naming where each part of a line goes. `measurement` and
+// `field` must each appear once; any other segment is a tag key.
+#[derive(Debug, PartialEq, Clone)]
+pub struct InfluxTemplate {
+    segments: Vec<InfluxSegment>
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum InfluxSegment {
+    Measurement,
+    Field,
+    Tag(String)
+}
+
+impl InfluxTemplate {
+    pub fn new(template: &str) -> Result<InfluxTemplate, String> {
+        let mut segments = vec![];
+        for segment in template.split('.') {
+            let segment = match segment {
+                "" => return Err(format!("template `{}` has an empty segment", template)),
+                "measurement" => InfluxSegment::Measurement,
+                "field" => InfluxSegment::Field,
+                tag => InfluxSegment::Tag(tag.to_string())
+            };
+            if segments.contains(&segment) {
+                return Err(format!("template `{}` repeats a segment", template));
+            }
+            segments.push(segment);
+        }
+
+        if !segments.contains(&InfluxSegment::Measurement) || !segments.contains(&InfluxSegment::Field) {
+            return Err(format!("template `{}` must contain `measurement` and `field`", template));
+        }
+        Ok(InfluxTemplate { segments: segments })
+    }
+
+    // The metric name for one field of a line. Tags the line doesn't have
+    // are left out of the name, and tags the template doesn't mention are ignored.
+    pub fn metric_name(&self, measurement: &str, tags: &[(String, String)], field: &str) -> String {
+        let nodes: Vec<String> = self.segments.iter().filter_map(|segment| match *segment {
+            InfluxSegment::Measurement => Some(sanitize_node(measurement)),
+            InfluxSegment::Field => Some(sanitize_node(field)),
+            InfluxSegment::Tag(ref key) => tags.iter()
+                .find(|&&(ref tag, _)| tag == key)
+                .map(|&(_, ref value)| sanitize_node(value))
+        }).collect();
+        nodes.join(".")
+    }
+
+    // The measurement, tags and field key for `metric_name`, or None when
+    // it doesn't have one node per segment
+    pub fn split(&self, metric_name: &str) -> Option<(String, Vec<(String, String)>, String)> {
+        let nodes: Vec<&str> = metric_name.split('.').collect();
+        if nodes.len() != self.segments.len() {
+            return None;
+        }
+
+        let (mut measurement, mut field, mut tags) = (String::new(), String::new(), vec![]);
+        for (node, segment) in nodes.into_iter().zip(self.segments.iter()) {
+            match *segment {
+                InfluxSegment::Measurement => measurement = node.to_string(),
+                InfluxSegment::Field => field = node.to_string(),
+                InfluxSegment::Tag(ref key) => tags.push((key.clone(), node.to_string()))
+            }
+        }
+        Some((measurement, tags, field))
+    }
+}
+
+impl Default for InfluxTemplate {
+    fn default() -> InfluxTemplate {
+        InfluxTemplate { segments: vec![InfluxSegment::Measurement, InfluxSegment::Field] }
+    }
+}
+
+// A dot would add a level to the metric's path
+fn sanitize_node(node: &str) -> String {
+    node.chars().map(|c| if c == '.' || c.is_whitespace() { '_' } else { c }).collect()
+}
+
+// One point per numeric field. String fields can't be stored and are
+// skipped, and a line without a timestamp is taken to be from `now`.
+pub fn parse_line(line: &str, template: &InfluxTemplate, now: u32) -> Result<Vec<NamedPoint>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(vec![]);
+    }
+
+    let sections: Vec<&str> = split_unescaped(line, ' ', true).into_iter().filter(|section| !section.is_empty()).collect();
+    if sections.len() < 2 || sections.len() > 3 {
+        return Err(format!("line protocol `{}` does not have 2 or 3 parts", line));
+    }
+
+    let keys = split_unescaped(sections[0], ',', false);
+    let measurement = unescape(keys[0]);
+    if measurement.is_empty() {
+        return Err(format!("line protocol `{}` has no measurement", line));
+    }
+    let tags = try!(keys[1..].iter().map(|tag| {
+        let (key, value) = try!(split_pair(tag).ok_or_else(|| format!("tag `{}` is not a key=value pair", tag)));
+        Ok((unescape(key), unescape(value)))
+    }).collect::<Result<Vec<(String, String)>, String>>());
+
+    let timestamp = match sections.get(2) {
+        Some(timestamp) => try!(parse_timestamp(timestamp)),
+        None => now
+    };
+
+    let mut named_points = vec![];
+    for field in split_unescaped(sections[1], ',', true) {
+        let (key, value) = try!(split_pair(field).ok_or_else(|| format!("field `{}` is not a key=value pair", field)));
+        if let Some(value) = try!(parse_field_value(value)) {
+            let name = template.metric_name(&measurement, &tags, &unescape(key));
+            if !NamedPoint::is_valid_name(&name) {
+                return Err(format!("line protocol `{}` gives the invalid metric name `{}`", line, name));
+            }
+            named_points.push(NamedPoint::new(name, timestamp, value));
+        }
+    }
+    Ok(named_points)
+}
+
+// Nanoseconds, the protocol's default precision
+fn parse_timestamp(timestamp: &str) -> Result<u32, String> {
+    match timestamp.parse::<i64>() {
+        Ok(nanos) if nanos >= 0 && nanos / NANOS_PER_SECOND <= ::std::u32::MAX as i64 => Ok((nanos / NANOS_PER_SECOND) as u32),
+        _ => Err(format!("timestamp `{}` is not an epoch time in nanoseconds", timestamp))
+    }
+}
+
+// Floats, `i` suffixed integers, `u` suffixed unsigned integers and booleans.
+// Strings are valid but not numbers, so give None.
+fn parse_field_value(value: &str) -> Result<Option<f64>, String> {
+    let number = match value {
+        _ if value.starts_with('"') => return Ok(None),
+        "t" | "T" | "true" | "True" | "TRUE" => Ok(1.0),
+        "f" | "F" | "false" | "False" | "FALSE" => Ok(0.0),
+        _ if value.ends_with('i') => value[.. value.len() - 1].parse::<i64>().map(|number| number as f64).map_err(|_| ()),
+        _ if value.ends_with('u') => value[.. value.len() - 1].parse::<u64>().map(|number| number as f64).map_err(|_| ()),
+        _ => value.parse::<f64>().map_err(|_| ())
+    };
+    number.map(Some).map_err(|_| format!("field value `{}` is not a number, string or boolean", value))
+}
+
+// Splits on `separator` where it isn't escaped with a backslash or,
+// when `quotes` is set, inside a double quoted string field
+fn split_unescaped(text: &str, separator: char, quotes: bool) -> Vec<&str> {
+    let mut parts = vec![];
+    let (mut start, mut escaped, mut quoted) = (0, false, false);
+    for (index, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '"' && quotes {
+            quoted = !quoted;
+        } else if c == separator && !quoted {
+            parts.push(&text[start..index]);
+            start = index + 1;
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+fn split_pair(pair: &str) -> Option<(&str, &str)> {
+    let parts = split_unescaped(pair, '=', false);
+    if parts.len() < 2 || parts[0].is_empty() {
+        return None;
+    }
+    // Only the first `=` separates the key
+    let key = parts[0];
+    Some((key, &pair[key.len() + 1 ..]))
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("\\,", ",").replace("\\=", "=").replace("\\ ", " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn named(name: &str, timestamp: u32, value: f64) -> NamedPoint {
+        NamedPoint::new(name.to_string(), timestamp, value)
+    }
+
+    #[test]
+    fn test_template() {
+        let template = InfluxTemplate::new("measurement.host.field").unwrap();
+        let tags = vec![("host".to_string(), "web01.example".to_string()), ("region".to_string(), "eu".to_string())];
+        assert_eq!(template.metric_name("cpu", &tags, "usage idle"), "cpu.web01_example.usage_idle");
+        assert_eq!(template.metric_name("cpu", &[], "usage"), "cpu.usage");
+        assert_eq!(template.split("cpu.web01.usage"), Some(("cpu".to_string(), vec![("host".to_string(), "web01".to_string())], "usage".to_string())));
+        assert_eq!(template.split("cpu.usage"), None);
+        assert!(InfluxTemplate::new("host.field").is_err());
+        assert!(InfluxTemplate::new("measurement.field.field").is_err());
+        assert!(InfluxTemplate::new("measurement..field").is_err());
+    }
+
+    #[test]
+    fn test_parse_line() {
+        let template = InfluxTemplate::new("measurement.host.field").unwrap();
+        let line = "cpu,host=web01,region=eu usage_user=1.5,usage_system=2i,up=true,note=\"a, b=c\" 1434598525123456789";
+        assert_eq!(parse_line(line, &template, 0), Ok(vec![
+            named("cpu.web01.usage_user", 1434598525, 1.5),
+            named("cpu.web01.usage_system", 1434598525, 2.0),
+            named("cpu.web01.up", 1434598525, 1.0)
+        ]));
+
+        let escaped = "disk\\ io,host=web\\,01 read\\=bytes=4u";
+        assert_eq!(parse_line(escaped, &template, 1434598525), Ok(vec![named("disk_io.web,01.read=bytes", 1434598525, 4.0)]));
+        assert_eq!(parse_line("# a comment", &template, 0), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_parse_line_errors() {
+        let template = InfluxTemplate::default();
+        assert!(parse_line("cpu", &template, 0).is_err());
+        assert!(parse_line("cpu,host usage=1", &template, 0).is_err());
+        assert!(parse_line("cpu usage=x", &template, 0).is_err());
+        assert!(parse_line("cpu usage=1 -5", &template, 0).is_err());
+        // Anything that could leave the storage dir
+        assert!(parse_line("/tmp/cpu usage=1", &template, 0).is_err());
+        assert!(parse_line("cpu,host=../../etc usage=1", &InfluxTemplate::new("measurement.host.field").unwrap(), 0).is_err());
+        assert!(parse_line("cpu,host= usage=1", &InfluxTemplate::new("measurement.host.field").unwrap(), 0).is_err());
+    }
+}
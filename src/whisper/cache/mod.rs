@@ -1,14 +1,24 @@
 // use carbon::CarbonMsg;
 // use whisper::{ WhisperFile, MutexWhisperFile };
-use whisper::{ WhisperFile, Schema, AggregationType };
+use whisper::{ WhisperFile, Point, Schema, SchemaRegistry, AggregationType };
+use whisper::finder::MetricIndex;
 use std::path::{ Path, PathBuf };
 use std::fs::DirBuilder;
-use std::io::Result;
+use std::io::{ Result, Error, ErrorKind };
 use std::sync::{ Arc, Mutex };
 use lru_cache::LruCache;
 
 mod named_point;
+mod influx;
+mod opentsdb;
+mod pickle;
+mod write_behind;
+mod wal;
 pub use self::named_point::NamedPoint;
+pub use self::influx::InfluxTemplate;
+pub use self::opentsdb::TagOrder;
+pub use self::write_behind::{ WriteBehindCache, WriteBehindConfig, DrainStrategy };
+pub use self::wal::WriteAheadLog;
 
 type WhisperMutex = Arc<Mutex<WhisperFile>>;
 
@@ -16,22 +26,35 @@ pub struct WhisperCache {
 	pub base_path: PathBuf,
 	// open_files: HashMap< PathBuf, WhisperMutex >,
 	open_files: LruCache< PathBuf, WhisperMutex >,
-	schema: Schema
+	schemas: SchemaRegistry,
+	// Told about every file we create
+	index: Option<MetricIndex>
 }
 
 impl WhisperCache {
 	pub fn new<P>(base_path: P, size: usize, schema: Schema) -> WhisperCache
+        where P: AsRef<Path> {
+		WhisperCache::with_registry(base_path, size, SchemaRegistry::new(schema))
+	}
+
+	pub fn with_registry<P>(base_path: P, size: usize, schemas: SchemaRegistry) -> WhisperCache
         where P: AsRef<Path> {
 		WhisperCache {
 			base_path: base_path.as_ref().to_path_buf(),
 			open_files: LruCache::new(size),
-			schema: schema
+			schemas: schemas,
+			index: None
 		}
 	}
 
+	// Keep `index` up to date as new metrics are created
+	pub fn set_index(&mut self, index: MetricIndex) {
+		self.index = Some(index);
+	}
+
 	pub fn write(&mut self, named_point: NamedPoint) -> Result<()> {
 		let metric_rel_path = named_point.rel_path();
-		self.get(&metric_rel_path).map(|cache_entry| {
+		self.get(named_point.name(), &metric_rel_path).map(|cache_entry| {
                         let mut whisper_file = cache_entry.lock().unwrap();
 
                         // We assume opened files always succeed in writes
@@ -39,7 +62,28 @@ impl WhisperCache {
                 })
 	}
 
-	fn get(&mut self, metric_rel_path: &PathBuf) -> Result<&WhisperMutex> {
+	pub fn write_many(&mut self, metric_name: &str, points: &[Point]) -> Result<()> {
+		let metric_rel_path = NamedPoint::metric_rel_path(metric_name);
+		self.get(metric_name, &metric_rel_path).map(|cache_entry| {
+			cache_entry.lock().unwrap().write_many(points);
+		})
+	}
+
+	// Sync every open file to disk. Used on shutdown so nothing is left
+	// sitting in dirty pages.
+	pub fn flush(&mut self) -> Result<()> {
+		for (_, cache_entry) in self.open_files.iter() {
+			try!(cache_entry.lock().unwrap().flush());
+		}
+		Ok(())
+	}
+
+	fn get(&mut self, metric_name: &str, metric_rel_path: &PathBuf) -> Result<&WhisperMutex> {
+		// Names come straight off the network, and one like `/etc/x` would
+		// otherwise join to a path outside base_path
+		if !NamedPoint::is_valid_name(metric_name) {
+			return Err(Error::new(ErrorKind::InvalidInput, format!("Invalid metric name `{}`", metric_name)));
+		}
 		if self.open_files.contains_key(metric_rel_path) {
 			debug!("file cache hit. resolved {:?}", metric_rel_path);
 			Ok(self.open_files.get_mut(metric_rel_path).unwrap())
@@ -51,6 +95,9 @@ impl WhisperCache {
 				debug!("`{:?}` exists on disk. opening.", path_on_disk);
 				try!(WhisperFile::open(&path_on_disk))
 			} else {
+				let schema = try!(self.schemas.schema_for(metric_name).ok_or_else(|| {
+					Error::new(ErrorKind::InvalidInput, format!("No storage schema matches metric `{}`", metric_name))
+				}));
 
 				// Verify the folder structure is present.
 				// TODO: benchmark (for my own curiosity)
@@ -60,7 +107,15 @@ impl WhisperCache {
 					try!( DirBuilder::new().recursive(true).create( path_on_disk.parent().unwrap() ) );
 				}
 				debug!("`{:?}` must now be created", path_on_disk);
-				try!(WhisperFile::new(&path_on_disk, &self.schema, AggregationType::Average, 0.5))
+				let whisper_file = match WhisperFile::new(&path_on_disk, schema, AggregationType::Average, 0.5) {
+					// Another writer created it since we looked
+					Err(ref e) if e.kind() == ErrorKind::AlreadyExists => try!(WhisperFile::open(&path_on_disk)),
+					whisper_file => try!(whisper_file)
+				};
+				if let Some(ref index) = self.index {
+					index.insert(metric_name);
+				}
+				whisper_file
 			};
 
 			self.open_files.insert(path_in_cache.clone(), Arc::new(Mutex::new(whisper_file)));
@@ -73,7 +128,9 @@ impl WhisperCache {
 mod test {
 	extern crate test;
 	use test::Bencher;
-	use whisper::{ WhisperCache, NamedPoint, Schema };
+	use whisper::{ WhisperCache, NamedPoint, Schema, Point };
+	use std::env;
+	use std::fs;
 
 	#[bench]
 	fn test_opening_new_whisper_file(b: &mut Bencher){
@@ -88,4 +145,20 @@ mod test {
 			cache.write(metric).unwrap();
 		});
 	}
+
+	#[test]
+	fn test_rejects_names_outside_base_path() {
+		let schema = Schema::new_from_retention_specs(vec!["1s:60s".to_string()]).unwrap();
+		let base = env::temp_dir().join("whisper-cache-names");
+		let mut cache = WhisperCache::new(&base, 10, schema);
+		let escaped = env::temp_dir().join("whisper-cache-escaped.wsp");
+		let _ = fs::remove_file(&escaped);
+
+		let name = escaped.with_extension("").to_string_lossy().into_owned();
+		assert!(cache.write(NamedPoint::new(name.clone(), 1434598525, 1.0)).is_err());
+		assert!(cache.write_many(&name, &[Point(1434598525, 1.0)]).is_err());
+		assert!(cache.write(NamedPoint::new("a..b".to_string(), 1434598525, 1.0)).is_err());
+		assert!(cache.write(NamedPoint::new("a.b\\c".to_string(), 1434598525, 1.0)).is_err());
+		assert!(!escaped.exists());
+	}
 }
@@ -2,6 +2,9 @@ use std::path::PathBuf;
 use std::str;
 
 use whisper::Point;
+use super::pickle;
+use super::influx::{ self, InfluxTemplate };
+use super::opentsdb::{ self, TagOrder };
 
 #[derive(PartialEq,Debug)]
 pub struct NamedPoint {
@@ -32,6 +35,54 @@ impl NamedPoint {
 
     }
 
+    // Decodes one pickle protocol payload (without its length prefix):
+    // a list of `(metric, (timestamp, value))` tuples
+    pub fn from_pickle(pickle_buffer: &[u8]) -> Result< Vec<NamedPoint>, String > {
+        let unpickled = try!(pickle::loads(pickle_buffer));
+        let entries = try!(unpickled.as_seq().ok_or_else(|| "pickle is not a list of metrics".to_string()));
+
+        entries.iter().map(|entry| {
+            let (name, datapoint) = match entry.as_seq() {
+                Some(pair) if pair.len() == 2 => (&pair[0], &pair[1]),
+                _ => return Err( format!("pickle entry `{:?}` is not a (metric, datapoint) pair", entry) )
+            };
+            let metric_name = try!(name.as_str().ok_or_else(|| format!("pickle metric name `{:?}` is not a string", name)));
+            let (timestamp, value) = match datapoint.as_seq() {
+                Some(pair) if pair.len() == 2 => (pair[0].as_f64(), pair[1].as_f64()),
+                _ => return Err( format!("pickle datapoint `{:?}` is not a (timestamp, value) pair", datapoint) )
+            };
+
+            match (timestamp, value) {
+                (Some(ts), Some(val)) if ts >= 0.0 && ts <= ::std::u32::MAX as f64 => {
+                    Ok(NamedPoint::new(metric_name.to_string(), ts as u32, val))
+                },
+                _ => Err( format!("pickle datapoint `{:?}` for `{}` is not numeric", datapoint, metric_name) )
+            }
+        }).collect()
+    }
+
+    // One InfluxDB line protocol line, which gives a point per numeric field.
+    // `now` stands in for a missing timestamp.
+    pub fn from_line_protocol(line: &str, template: &InfluxTemplate, now: u32) -> Result< Vec<NamedPoint>, String > {
+        influx::parse_line(line, template, now)
+    }
+
+    // OpenTSDB's `put <metric> <timestamp> <value> <tagk=tagv>...`, with the
+    // tag values appended to the metric name in `order`
+    pub fn from_put(line: &str, order: &TagOrder) -> Result< NamedPoint, String > {
+        opentsdb::parse_put(line, order)
+    }
+
+    // A `put` line, or carbon plaintext when it isn't one, so collectors
+    // speaking either can share a port
+    pub fn parse_put_or_line(line: &str, order: &TagOrder) -> Result< NamedPoint, String > {
+        if opentsdb::is_put(line) {
+            NamedPoint::from_put(line, order)
+        } else {
+            NamedPoint::parse_line(line)
+        }
+    }
+
     pub fn parse_line(line: &str) -> Result< NamedPoint, String > {
         let parts : Vec<&str> = line.split(" ").collect();
         if parts.len() != 3 {
@@ -71,11 +122,28 @@ impl NamedPoint {
 	pub fn rel_path(&self) -> PathBuf {
         // Would love to have the NamedPoint keep the UDP datagram or whatever around.
         // But easier to copy that string to this `metric_name` and carry on!
-        let mut rel_path : String = self.metric_name.replace(".","/");
+        NamedPoint::metric_rel_path(&self.metric_name)
+	}
+
+	// `a.b.c` lives at `a/b/c.wsp` under the cache's base path
+	pub fn metric_rel_path(metric_name: &str) -> PathBuf {
+        let mut rel_path : String = metric_name.replace(".","/");
         rel_path.push_str(".wsp");
         PathBuf::from(rel_path)
 	}
 
+	// Whether `metric_name` maps to a file under the storage dir: no empty
+	// nodes, and nothing that could make the path absolute or climb out of it
+	pub fn is_valid_name(metric_name: &str) -> bool {
+		metric_name.split('.').all(|node| {
+			!node.is_empty() && !node.contains('/') && !node.contains('\\') && !node.contains('\0')
+		})
+	}
+
+	pub fn name(&self) -> &str {
+		&self.metric_name
+	}
+
 	pub fn point(&self) -> &Point {
 		&self.point
 	}
@@ -132,6 +200,26 @@ mod tests {
     	assert_eq!(msgs_opt.unwrap(), expected);
     }
 
+    #[test]
+    fn test_pickle() {
+        // pickle.dumps([('a.b', (1434598525, 100.0)), ('a.c', (1434598525, 4))], protocol=2)
+        let pickled = b"\x80\x02]q\x00(X\x03\x00\x00\x00a.bq\x01J}<\x82UG@Y\x00\x00\x00\x00\x00\x00\x86q\x02\x86q\x03X\x03\x00\x00\x00a.cq\x04J}<\x82UK\x04\x86q\x05\x86q\x06e.";
+        let msgs = NamedPoint::from_pickle(pickled).unwrap();
+
+        let expected = vec![
+            NamedPoint { metric_name: "a.b".to_string(), point: Point(1434598525, 100.0) },
+            NamedPoint { metric_name: "a.c".to_string(), point: Point(1434598525, 4.0) }
+        ];
+        assert_eq!(msgs, expected);
+    }
+
+    #[test]
+    fn test_pickle_wrong_shape() {
+        // pickle.dumps([('a.b', 1434598525)], protocol=2)
+        let pickled = b"\x80\x02]q\x00X\x03\x00\x00\x00a.bq\x01J}<\x82U\x86q\x02a.";
+        assert_eq!(NamedPoint::from_pickle(pickled).unwrap_err(), "pickle datapoint `Int(1434598525)` is not a (timestamp, value) pair");
+    }
+
     #[bench]
     fn bench_bad_datagram(b: &mut Bencher){
         let datagram = "home.pets.monkeys.squeeky.squeeks nan";
@@ -0,0 +1,105 @@
+// OpenTSDB's telnet `put <metric> <timestamp> <value> <tagk=tagv> ...` lines.
+//
+// Tag values are appended to the metric name as extra path nodes, so
+// `put sys.cpu.user 1434598525 42 host=web01 cpu=0` is stored as
+// `sys.cpu.user.web01.0` when the tags are ordered `host,cpu`.
+
+use super::NamedPoint;
+
+// Timestamps past this many seconds are in milliseconds. OpenTSDB tells them
+// apart by their length too: ten digits for seconds, thirteen for milliseconds.
+const MAX_SECONDS : u64 = 9_999_999_999;
+
+// The order tag values are appended in. Tags it doesn't list follow the
+// listed ones, sorted by key.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct TagOrder {
+    keys: Vec<String>
+}
+
+impl TagOrder {
+    // Comma separated tag keys, like `host,cpu`
+    pub fn new(order: &str) -> Result<TagOrder, String> {
+        let keys: Vec<String> = order.split(',').map(|key| key.trim().to_string()).filter(|key| !key.is_empty()).collect();
+        if let Some(key) = keys.iter().find(|key| key.contains('=') || key.contains(' ')) {
+            return Err(format!("`{}` in tag order `{}` is not a tag key", key, order));
+        }
+        Ok(TagOrder { keys: keys })
+    }
+
+    pub fn metric_name(&self, metric: &str, mut tags: Vec<(&str, &str)>) -> String {
+        tags.sort_by_key(|&(key, _)| (self.keys.iter().position(|ordered| ordered == key).unwrap_or(self.keys.len()), key));
+
+        let mut name = metric.to_string();
+        for (_, value) in tags {
+            name.push('.');
+            // A dot would add a level to the metric's path
+            name.push_str(&value.replace('.', "_"));
+        }
+        name
+    }
+}
+
+pub fn is_put(line: &str) -> bool {
+    line.starts_with("put ")
+}
+
+pub fn parse_put(line: &str, order: &TagOrder) -> Result<NamedPoint, String> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.len() < 4 || parts[0] != "put" {
+        return Err(format!("`{}` is not a `put <metric> <timestamp> <value> <tagk=tagv>...` line", line));
+    }
+
+    let timestamp = match parts[2].parse::<u64>() {
+        Ok(millis) if millis > MAX_SECONDS => millis / 1000,
+        Ok(seconds) => seconds,
+        Err(_) => return Err(format!("timestamp `{}` is not an epoch time", parts[2]))
+    };
+    if timestamp > ::std::u32::MAX as u64 {
+        return Err(format!("timestamp `{}` is out of range", parts[2]));
+    }
+    let value = try!(parts[3].parse::<f64>().map_err(|_| format!("value `{}` is not a number", parts[3])));
+
+    let tags = try!(parts[4..].iter().map(|tag| {
+        match tag.find('=') {
+            Some(split) if split > 0 && split < tag.len() - 1 => Ok((&tag[..split], &tag[split + 1 ..])),
+            _ => Err(format!("tag `{}` is not a tagk=tagv pair", tag))
+        }
+    }).collect::<Result<Vec<(&str, &str)>, String>>());
+
+    let name = order.metric_name(parts[1], tags);
+    if !NamedPoint::is_valid_name(&name) {
+        return Err(format!("`{}` gives the invalid metric name `{}`", line, name));
+    }
+    Ok(NamedPoint::new(name, timestamp as u32, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tag_order() {
+        let order = TagOrder::new("host, cpu").unwrap();
+        assert_eq!(order.metric_name("sys.cpu.user", vec![("dc", "eu"), ("cpu", "0"), ("host", "web01.example")]), "sys.cpu.user.web01_example.0.eu");
+        assert_eq!(TagOrder::default().metric_name("sys.load", vec![("host", "web01"), ("dc", "eu")]), "sys.load.eu.web01");
+        assert!(TagOrder::new("host=web01").is_err());
+    }
+
+    #[test]
+    fn test_parse_put() {
+        let order = TagOrder::new("host").unwrap();
+        assert_eq!(parse_put("put sys.cpu.user 1434598525 42.5 host=web01 cpu=0", &order),
+            Ok(NamedPoint::new("sys.cpu.user.web01.0".to_string(), 1434598525, 42.5)));
+        assert_eq!(parse_put("put sys.load 1434598525123 1", &order),
+            Ok(NamedPoint::new("sys.load".to_string(), 1434598525, 1.0)));
+        assert!(parse_put("put sys.load 1434598525", &order).is_err());
+        assert!(parse_put("put sys.load -1 1", &order).is_err());
+        assert!(parse_put("put sys.load 1434598525 1 host", &order).is_err());
+        // Anything that could leave the storage dir
+        assert!(parse_put("put /etc/sys.load 1434598525 1", &order).is_err());
+        assert!(parse_put("put sys.load 1434598525 1 host=../../tmp", &order).is_err());
+        assert!(is_put("put sys.load 1434598525 1"));
+        assert!(!is_put("sys.load 1 1434598525"));
+    }
+}
@@ -0,0 +1,485 @@
+// A restricted unpickler for carbon's pickle protocol.
+//
+// Relays only ever send lists of `(metric, (timestamp, value))` tuples,
+// so this understands the opcodes needed to build lists, tuples, strings
+// and numbers in protocols 0 through 4. Anything that would import or
+// call into Python (GLOBAL, REDUCE, BUILD, ...) is rejected outright.
+
+use std::collections::HashMap;
+use std::str;
+
+use byteorder::{ ByteOrder, BigEndian, LittleEndian };
+
+// Most values one load may build. Memo lookups copy whole values, so a
+// tiny pickle could otherwise double its size every few bytes. A full
+// 1MB carbon message holds far fewer than this.
+const MAX_NODES : usize = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    None,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    Tuple(Vec<Value>)
+}
+
+impl Value {
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Int(i) => Some(i as f64),
+            Value::Float(f) => Some(f),
+            Value::Bool(b) => Some(if b { 1.0 } else { 0.0 }),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::String(ref s) => Some(s),
+            Value::Bytes(ref b) => str::from_utf8(b).ok(),
+            _ => None
+        }
+    }
+
+    // Lists and tuples are interchangeable as far as carbon cares
+    pub fn as_seq(&self) -> Option<&[Value]> {
+        match *self {
+            Value::List(ref items) | Value::Tuple(ref items) => Some(items),
+            _ => None
+        }
+    }
+
+    // This value and everything inside it
+    fn node_count(&self) -> usize {
+        match *self {
+            Value::List(ref items) | Value::Tuple(ref items) => 1 + items.iter().map(|item| item.node_count()).sum::<usize>(),
+            _ => 1
+        }
+    }
+}
+
+enum Item {
+    Mark,
+    Value(Value)
+}
+
+pub fn loads(data: &[u8]) -> Result<Value, String> {
+    Unpickler { data: data, pos: 0, stack: vec![], memo: HashMap::new(), nodes: 0 }.load()
+}
+
+struct Unpickler<'a> {
+    data: &'a [u8],
+    pos: usize,
+    stack: Vec<Item>,
+    // Values are copied in to the memo, which is fine for the acyclic
+    // data carbon sends but means a memoized list won't see later appends
+    memo: HashMap<u32, Value>,
+    // Values built so far, held to MAX_NODES
+    nodes: usize
+}
+
+impl<'a> Unpickler<'a> {
+    fn load(mut self) -> Result<Value, String> {
+        loop {
+            // push can't fail, so anything it took over the limit is caught here
+            try!(self.charge(0));
+            let opcode = try!(self.read(1))[0];
+            match opcode {
+                // PROTO
+                0x80 => { try!(self.read(1)); },
+                // FRAME, only a hint for buffering
+                0x95 => { try!(self.read(8)); },
+                // STOP
+                b'.' => return self.pop_value(),
+
+                b'(' => self.stack.push(Item::Mark),
+                b'0' => { try!(self.pop_value()); },
+                b'1' => { try!(self.pop_mark()); },
+                b'2' => {
+                    let count = try!(self.peek_value()).node_count();
+                    try!(self.charge(count));
+                    let top = try!(self.peek_value()).clone();
+                    self.push(top);
+                },
+
+                b'N' => self.push(Value::None),
+                0x88 => self.push(Value::Bool(true)),
+                0x89 => self.push(Value::Bool(false)),
+
+                // INT, which protocol 0 also uses for booleans
+                b'I' => {
+                    let line = try!(self.read_line());
+                    let value = match line {
+                        "01" => Value::Bool(true),
+                        "00" => Value::Bool(false),
+                        _ => Value::Int(try!(parse_int(line)))
+                    };
+                    self.push(value);
+                },
+                // LONG
+                b'L' => {
+                    let line = try!(self.read_line());
+                    let value = try!(parse_int(line.trim_end_matches('L')));
+                    self.push(Value::Int(value));
+                },
+                // BININT
+                b'J' => {
+                    let value = LittleEndian::read_i32(try!(self.read(4)));
+                    self.push(Value::Int(value as i64));
+                },
+                // BININT1
+                b'K' => {
+                    let value = try!(self.read(1))[0];
+                    self.push(Value::Int(value as i64));
+                },
+                // BININT2
+                b'M' => {
+                    let value = LittleEndian::read_u16(try!(self.read(2)));
+                    self.push(Value::Int(value as i64));
+                },
+                // LONG1, LONG4
+                0x8a | 0x8b => {
+                    let len = if opcode == 0x8a {
+                        try!(self.read(1))[0] as usize
+                    } else {
+                        LittleEndian::read_u32(try!(self.read(4))) as usize
+                    };
+                    let value = try!(decode_long(try!(self.read(len))));
+                    self.push(Value::Int(value));
+                },
+                // FLOAT
+                b'F' => {
+                    let line = try!(self.read_line());
+                    let value = try!(line.parse::<f64>().map_err(|_| format!("invalid float `{}`", line)));
+                    self.push(Value::Float(value));
+                },
+                // BINFLOAT
+                b'G' => {
+                    let value = BigEndian::read_f64(try!(self.read(8)));
+                    self.push(Value::Float(value));
+                },
+
+                // STRING
+                b'S' => {
+                    let line = try!(self.read_line());
+                    let value = try!(unquote(line));
+                    self.push(Value::Bytes(value));
+                },
+                // BINSTRING, SHORT_BINSTRING, BINBYTES, SHORT_BINBYTES, BINBYTES8
+                b'T' | b'U' | b'B' | b'C' | 0x8e => {
+                    let len = try!(self.read_len(opcode));
+                    let value = try!(self.read(len)).to_vec();
+                    self.push(Value::Bytes(value));
+                },
+                // UNICODE
+                b'V' => {
+                    let line = try!(self.read_line());
+                    let value = try!(unescape_unicode(line));
+                    self.push(Value::String(value));
+                },
+                // BINUNICODE, SHORT_BINUNICODE, BINUNICODE8
+                b'X' | 0x8c | 0x8d => {
+                    let len = try!(self.read_len(opcode));
+                    let value = try!(str::from_utf8(try!(self.read(len))).map_err(|_| "invalid utf8 in string".to_string()));
+                    self.push(Value::String(value.to_string()));
+                },
+
+                // EMPTY_LIST
+                b']' => self.push(Value::List(vec![])),
+                // LIST
+                b'l' => {
+                    let items = try!(self.pop_mark());
+                    self.push(Value::List(items));
+                },
+                // APPEND
+                b'a' => {
+                    let item = try!(self.pop_value());
+                    try!(self.extend_list(vec![item]));
+                },
+                // APPENDS
+                b'e' => {
+                    let items = try!(self.pop_mark());
+                    try!(self.extend_list(items));
+                },
+
+                // EMPTY_TUPLE
+                b')' => self.push(Value::Tuple(vec![])),
+                // TUPLE
+                b't' => {
+                    let items = try!(self.pop_mark());
+                    self.push(Value::Tuple(items));
+                },
+                // TUPLE1, TUPLE2, TUPLE3
+                0x85 | 0x86 | 0x87 => {
+                    let count = (opcode - 0x84) as usize;
+                    let mut items = Vec::with_capacity(count);
+                    for _ in 0..count {
+                        items.push(try!(self.pop_value()));
+                    }
+                    items.reverse();
+                    self.push(Value::Tuple(items));
+                },
+
+                // PUT, BINPUT, LONG_BINPUT, MEMOIZE
+                b'p' | b'q' | b'r' | 0x94 => {
+                    let index = match opcode {
+                        b'p' => try!(self.read_line().and_then(parse_int)) as u32,
+                        b'q' => try!(self.read(1))[0] as u32,
+                        b'r' => LittleEndian::read_u32(try!(self.read(4))),
+                        _ => self.memo.len() as u32
+                    };
+                    let count = try!(self.peek_value()).node_count();
+                    try!(self.charge(count));
+                    let top = try!(self.peek_value()).clone();
+                    self.memo.insert(index, top);
+                },
+                // GET, BINGET, LONG_BINGET
+                b'g' | b'h' | b'j' => {
+                    let index = match opcode {
+                        b'g' => try!(self.read_line().and_then(parse_int)) as u32,
+                        b'h' => try!(self.read(1))[0] as u32,
+                        _ => LittleEndian::read_u32(try!(self.read(4)))
+                    };
+                    let count = try!(self.memo.get(&index).map(|value| value.node_count()).ok_or_else(|| format!("memo key {} is not defined", index)));
+                    try!(self.charge(count));
+                    let value = self.memo[&index].clone();
+                    self.push(value);
+                },
+
+                // GLOBAL, STACK_GLOBAL, REDUCE, BUILD, INST, OBJ, NEWOBJ,
+                // NEWOBJ_EX, EXT1, EXT2, EXT4, PERSID, BINPERSID
+                b'c' | 0x93 | b'R' | b'b' | b'i' | b'o' | 0x81 | 0x92 | 0x82 | 0x83 | 0x84 | b'P' | b'Q' => {
+                    return Err(format!("refusing to unpickle arbitrary objects (opcode 0x{:02x})", opcode));
+                },
+                _ => return Err(format!("unsupported pickle opcode 0x{:02x}", opcode))
+            }
+        }
+    }
+
+    fn read(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() - self.pos < len {
+            return Err("pickle data was truncated".to_string());
+        }
+        let data = self.data;
+        let slice = &data[self.pos .. self.pos + len];
+        self.pos = self.pos + len;
+        Ok(slice)
+    }
+
+    fn read_line(&mut self) -> Result<&'a str, String> {
+        let data = self.data;
+        let rest = &data[self.pos..];
+        match rest.iter().position(|&b| b == b'\n') {
+            Some(end) => {
+                self.pos = self.pos + end + 1;
+                str::from_utf8(&rest[..end]).map_err(|_| "invalid utf8 in pickle line".to_string())
+            },
+            None => Err("pickle data was truncated".to_string())
+        }
+    }
+
+    fn read_len(&mut self, opcode: u8) -> Result<usize, String> {
+        let len = match opcode {
+            b'U' | b'C' | 0x8c => try!(self.read(1))[0] as u64,
+            b'T' => {
+                let len = LittleEndian::read_i32(try!(self.read(4)));
+                if len < 0 { return Err("negative string length".to_string()); }
+                len as u64
+            },
+            b'B' | b'X' => LittleEndian::read_u32(try!(self.read(4))) as u64,
+            _ => LittleEndian::read_u64(try!(self.read(8)))
+        };
+        if len > (self.data.len() - self.pos) as u64 {
+            return Err("pickle data was truncated".to_string());
+        }
+        Ok(len as usize)
+    }
+
+    // Counts one node. Copies of existing values are charged before they're made.
+    fn push(&mut self, value: Value) {
+        self.nodes = self.nodes + 1;
+        self.stack.push(Item::Value(value));
+    }
+
+    fn charge(&mut self, nodes: usize) -> Result<(), String> {
+        self.nodes = self.nodes + nodes;
+        if self.nodes > MAX_NODES {
+            return Err(format!("pickle expands to more than {} values", MAX_NODES));
+        }
+        Ok(())
+    }
+
+    fn pop_value(&mut self) -> Result<Value, String> {
+        match self.stack.pop() {
+            Some(Item::Value(value)) => Ok(value),
+            Some(Item::Mark) => Err("unexpected mark on the stack".to_string()),
+            None => Err("pickle stack underflow".to_string())
+        }
+    }
+
+    fn peek_value(&self) -> Result<&Value, String> {
+        match self.stack.last() {
+            Some(&Item::Value(ref value)) => Ok(value),
+            Some(&Item::Mark) => Err("unexpected mark on the stack".to_string()),
+            None => Err("pickle stack underflow".to_string())
+        }
+    }
+
+    // Everything pushed since the most recent mark, in push order
+    fn pop_mark(&mut self) -> Result<Vec<Value>, String> {
+        let mut items = vec![];
+        loop {
+            match self.stack.pop() {
+                Some(Item::Value(value)) => items.push(value),
+                Some(Item::Mark) => break,
+                None => return Err("pickle is missing a mark".to_string())
+            }
+        }
+        items.reverse();
+        Ok(items)
+    }
+
+    fn extend_list(&mut self, items: Vec<Value>) -> Result<(), String> {
+        match self.stack.last_mut() {
+            Some(&mut Item::Value(Value::List(ref mut list))) => {
+                list.extend(items);
+                Ok(())
+            },
+            _ => Err("can only append to a list".to_string())
+        }
+    }
+}
+
+fn parse_int(text: &str) -> Result<i64, String> {
+    text.parse::<i64>().map_err(|_| format!("invalid integer `{}`", text))
+}
+
+// Little endian two's complement, as written by LONG1 and LONG4
+fn decode_long(bytes: &[u8]) -> Result<i64, String> {
+    if bytes.len() > 8 {
+        return Err("integer is too large".to_string());
+    }
+    if bytes.is_empty() {
+        return Ok(0);
+    }
+    let negative = bytes[bytes.len() - 1] & 0x80 != 0;
+    let mut buf = if negative { [0xffu8; 8] } else { [0u8; 8] };
+    buf[..bytes.len()].copy_from_slice(bytes);
+    Ok(LittleEndian::read_i64(&buf))
+}
+
+// Undo the `repr()` quoting used by protocol 0 strings
+fn unquote(line: &str) -> Result<Vec<u8>, String> {
+    let bytes = line.as_bytes();
+    if bytes.len() < 2 || (bytes[0] != b'\'' && bytes[0] != b'"') || bytes[bytes.len() - 1] != bytes[0] {
+        return Err(format!("string `{}` is not quoted", line));
+    }
+
+    let mut out = vec![];
+    let mut chars = bytes[1 .. bytes.len() - 1].iter().cloned();
+    while let Some(c) = chars.next() {
+        if c != b'\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(b'n') => out.push(b'\n'),
+            Some(b'r') => out.push(b'\r'),
+            Some(b't') => out.push(b'\t'),
+            Some(b'x') => {
+                let hex: Vec<u8> = chars.by_ref().take(2).collect();
+                let code = try!(str::from_utf8(&hex).ok()
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+                    .ok_or_else(|| format!("invalid escape in `{}`", line)));
+                out.push(code);
+            },
+            Some(other) => out.push(other),
+            None => return Err(format!("dangling escape in `{}`", line))
+        }
+    }
+    Ok(out)
+}
+
+// Protocol 0 unicode uses `raw-unicode-escape`: only \uXXXX and
+// \UXXXXXXXX are escapes, everything else is literal
+fn unescape_unicode(line: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        let width = match (c, chars.peek()) {
+            ('\\', Some(&'u')) => 4,
+            ('\\', Some(&'U')) => 8,
+            _ => {
+                out.push(c);
+                continue;
+            }
+        };
+        chars.next();
+        let hex: String = chars.by_ref().take(width).collect();
+        let decoded = try!(u32::from_str_radix(&hex, 16).ok()
+            .and_then(::std::char::from_u32)
+            .ok_or_else(|| format!("invalid escape in `{}`", line)));
+        out.push(decoded);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol_0() {
+        let data = b"(lp0\n(Va.b\np1\n(I1434598525\nF100.0\ntp2\ntp3\na(S'a.c'\np4\n(I1434598525\nI4\ntp5\ntp6\na.";
+        let expected = Value::List(vec![
+            Value::Tuple(vec![Value::String("a.b".to_string()), Value::Tuple(vec![Value::Int(1434598525), Value::Float(100.0)])]),
+            Value::Tuple(vec![Value::Bytes(b"a.c".to_vec()), Value::Tuple(vec![Value::Int(1434598525), Value::Int(4)])])
+        ]);
+        assert_eq!(loads(data).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_protocol_4_memo_and_long() {
+        let data = b"\x80\x04\x95:\x00\x00\x00\x00\x00\x00\x00]\x94(C\x03a.b\x94]\x94(GA\xd5`\x8f\x1f@\x00\x00G?\xf8\x00\x00\x00\x00\x00\x00e\x86\x94\x8c\x03a.b\x94J~<\x82U\x8a\x06\x00\x00\x00\x00\x00\x01\x86\x94\x86\x94e.";
+        let expected = Value::List(vec![
+            Value::Tuple(vec![Value::Bytes(b"a.b".to_vec()), Value::List(vec![Value::Float(1434598525.0), Value::Float(1.5)])]),
+            Value::Tuple(vec![Value::String("a.b".to_string()), Value::Tuple(vec![Value::Int(1434598526), Value::Int(1 << 40)])])
+        ]);
+        assert_eq!(loads(data).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_rejects_globals() {
+        let data = b"\x80\x02]q\x00X\x01\x00\x00\x00aq\x01cposix\nsystem\nq\x02K\x01\x86q\x03\x86q\x04a.";
+        assert_eq!(loads(data).unwrap_err(), "refusing to unpickle arbitrary objects (opcode 0x63)");
+    }
+
+    #[test]
+    fn test_truncated() {
+        let data = b"\x80\x02]q\x00X\x1f\x00\x00\x00home";
+        assert_eq!(loads(data).unwrap_err(), "pickle data was truncated");
+    }
+
+    #[test]
+    fn test_memo_expansion() {
+        // Each `h\x00 h\x00 \x86 q\x00` pairs the memoized value with itself,
+        // doubling its size in 5 bytes
+        let mut data = b"\x80\x02K\x01q\x00".to_vec();
+        for _ in 0..24 {
+            data.extend_from_slice(b"h\x00h\x00\x86q\x00");
+        }
+        data.push(b'.');
+        assert_eq!(loads(&data).unwrap_err(), format!("pickle expands to more than {} values", MAX_NODES));
+    }
+
+    #[test]
+    fn test_negative_long() {
+        assert_eq!(decode_long(&[0xff]).unwrap(), -1);
+        assert_eq!(decode_long(&[0x00, 0x80]).unwrap(), -32768);
+        assert_eq!(decode_long(&[]).unwrap(), 0);
+    }
+}
@@ -0,0 +1,410 @@
+// Append-only write-ahead log of `NamedPoint`s.
+//
+// Points are appended here before they are queued in memory, so a crash
+// loses nothing that was acknowledged. The log is a directory of numbered
+// segment files. Each record is
+//
+//   length (u32) | crc32 of payload (u32) | timestamp (u32) | value (f64) | metric name
+//
+// with every integer big endian, like the whisper format. The writer seals
+// the active segment and starts a new one once it grows past the size limit.
+// Sealed segments are deleted once every point in them is on disk.
+//
+// Appends are fsynced once the sync interval has passed since the last
+// sync, which by default is every append. A longer interval trades the last
+// moments of points before a power loss for fewer syncs.
+
+use whisper::Point;
+use super::{ WhisperCache, NamedPoint };
+
+use byteorder::{ ByteOrder, BigEndian };
+
+use std::collections::BTreeMap;
+use std::fs::{ self, File, OpenOptions, DirBuilder };
+use std::io::{ Result, Error, ErrorKind, Read, Write };
+use std::path::{ Path, PathBuf };
+use std::str;
+use std::time::{ Duration, Instant };
+
+// length + crc
+const RECORD_HEADER_SIZE : usize = 8;
+// timestamp + value
+const RECORD_POINT_SIZE : usize = 12;
+
+const SEGMENT_EXTENSION : &'static str = "wal";
+
+pub struct WriteAheadLog {
+    dir: PathBuf,
+    max_segment_size: u64,
+    // Segments left behind by a previous run
+    unreplayed: Vec<u64>,
+    // Segments this run has finished appending to
+    sealed: Vec<u64>,
+    current_id: u64,
+    current: File,
+    current_size: u64,
+    sync_interval: Duration,
+    last_sync: Instant,
+    // Appended since the last sync
+    unsynced: bool
+}
+
+impl WriteAheadLog {
+    // Opens the log in `dir`, creating it if needed. Appends always go to a
+    // fresh segment; anything already there waits for `replay_into`.
+    pub fn open<P>(dir: P, max_segment_size: u64) -> Result<WriteAheadLog>
+        where P: AsRef<Path> {
+        let dir = dir.as_ref().to_path_buf();
+        try!(DirBuilder::new().recursive(true).create(&dir));
+
+        let mut existing = vec![];
+        for entry in try!(fs::read_dir(&dir)) {
+            let path = try!(entry).path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+            match path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse::<u64>().ok()) {
+                Some(id) => existing.push(id),
+                None => warn!("ignoring unexpected file {:?} in write-ahead log", path)
+            }
+        }
+        existing.sort();
+
+        let current_id = existing.last().map(|id| id + 1).unwrap_or(0);
+        let current = try!(WriteAheadLog::create_segment(&dir, current_id));
+
+        Ok(WriteAheadLog {
+            dir: dir,
+            max_segment_size: max_segment_size,
+            unreplayed: existing,
+            sealed: vec![],
+            current_id: current_id,
+            current: current,
+            current_size: 0,
+            sync_interval: Duration::from_secs(0),
+            last_sync: Instant::now(),
+            unsynced: false
+        })
+    }
+
+    fn segment_path(dir: &Path, id: u64) -> PathBuf {
+        dir.join(format!("{:020}.{}", id, SEGMENT_EXTENSION))
+    }
+
+    fn create_segment(dir: &Path, id: u64) -> Result<File> {
+        OpenOptions::new().append(true).create(true).open(WriteAheadLog::segment_path(dir, id))
+    }
+
+    pub fn append(&mut self, named_points: &[NamedPoint]) -> Result<()> {
+        let mut buf = vec![];
+        for named_point in named_points {
+            encode_record(named_point, &mut buf);
+        }
+
+        // One write per batch keeps records from different batches apart
+        try!(self.current.write_all(&buf));
+        self.current_size = self.current_size + buf.len() as u64;
+        self.unsynced = true;
+
+        if self.current_size >= self.max_segment_size {
+            try!(self.rotate());
+        }
+        self.sync_if_due()
+    }
+
+    // How long appended points may wait to be fsynced
+    pub fn set_sync_interval(&mut self, interval: Duration) {
+        self.sync_interval = interval;
+    }
+
+    // Fsync the active segment if anything is waiting and the sync interval
+    // has passed. Call it now and then so a quiet log still gets synced.
+    pub fn sync_if_due(&mut self) -> Result<()> {
+        if self.unsynced && self.last_sync.elapsed() >= self.sync_interval {
+            try!(self.current.sync_data());
+            self.last_sync = Instant::now();
+            self.unsynced = false;
+        }
+        Ok(())
+    }
+
+    // Seal the active segment and start appending to a new one.
+    // Returns the id of the segment that was sealed.
+    pub fn rotate(&mut self) -> Result<u64> {
+        let sealed_id = self.current_id;
+        let next = try!(WriteAheadLog::create_segment(&self.dir, sealed_id + 1));
+        try!(self.current.sync_data());
+
+        self.current = next;
+        self.current_id = sealed_id + 1;
+        self.current_size = 0;
+        self.last_sync = Instant::now();
+        self.unsynced = false;
+        self.sealed.push(sealed_id);
+        debug!("sealed write-ahead log segment {}", sealed_id);
+        Ok(sealed_id)
+    }
+
+    // The most recently sealed segment still on disk
+    pub fn last_sealed(&self) -> Option<u64> {
+        self.sealed.last().cloned()
+    }
+
+    // Delete every sealed segment up to and including `id`. Only call this
+    // once the points in them have been flushed to their whisper files.
+    pub fn truncate_through(&mut self, id: u64) -> Result<()> {
+        while !self.sealed.is_empty() && self.sealed[0] <= id {
+            let segment = self.sealed.remove(0);
+            try!(fs::remove_file(WriteAheadLog::segment_path(&self.dir, segment)));
+            debug!("removed write-ahead log segment {}", segment);
+        }
+        Ok(())
+    }
+
+    // Write the points left behind by a previous run straight in to the
+    // cache, flush them and delete their segments. Returns the number of
+    // points replayed. Points are logged before they are validated, so a
+    // metric the cache won't write is skipped like the writer skips it.
+    pub fn replay_into(&mut self, cache: &mut WhisperCache) -> Result<usize> {
+        let mut metrics : BTreeMap<String, Vec<Point>> = BTreeMap::new();
+        let mut replayed = 0;
+        for &id in &self.unreplayed {
+            let path = WriteAheadLog::segment_path(&self.dir, id);
+            let mut contents = vec![];
+            try!(File::open(&path).and_then(|mut file| file.read_to_end(&mut contents)));
+
+            for named_point in decode_segment(&contents, &path) {
+                metrics.entry(named_point.name().to_string())
+                    .or_insert_with(Vec::new)
+                    .push(named_point.point().clone());
+            }
+        }
+
+        for (metric, points) in &metrics {
+            match cache.write_many(metric, points) {
+                Ok(_) => replayed = replayed + points.len(),
+                Err(e) => warn!("dropping {} logged points for `{}`: {}", points.len(), metric, e)
+            }
+        }
+        try!(cache.flush());
+
+        for id in self.unreplayed.drain(..) {
+            try!(fs::remove_file(WriteAheadLog::segment_path(&self.dir, id)));
+        }
+        Ok(replayed)
+    }
+}
+
+fn encode_record(named_point: &NamedPoint, buf: &mut Vec<u8>) {
+    let name = named_point.name().as_bytes();
+    let mut payload = vec![0u8; RECORD_POINT_SIZE];
+    BigEndian::write_u32(&mut payload[0..4], named_point.point().0);
+    BigEndian::write_f64(&mut payload[4..12], named_point.point().1);
+    payload.extend_from_slice(name);
+
+    let mut header = [0u8; RECORD_HEADER_SIZE];
+    BigEndian::write_u32(&mut header[0..4], payload.len() as u32);
+    BigEndian::write_u32(&mut header[4..8], crc32(&payload));
+    buf.extend_from_slice(&header);
+    buf.extend_from_slice(&payload);
+}
+
+// Decodes every intact record. A crash while appending leaves a short or
+// garbled record at the end of a segment; the rest of that segment is skipped.
+fn decode_segment(contents: &[u8], path: &Path) -> Vec<NamedPoint> {
+    let mut named_points = vec![];
+    let mut pos = 0;
+    while pos < contents.len() {
+        match decode_record(&contents[pos..]) {
+            Ok((named_point, len)) => {
+                named_points.push(named_point);
+                pos = pos + len;
+            },
+            Err(why) => {
+                warn!("{:?}: {} at byte {}, skipping the rest of the segment", path, why, pos);
+                break;
+            }
+        }
+    }
+    named_points
+}
+
+fn decode_record(data: &[u8]) -> Result<(NamedPoint, usize)> {
+    if data.len() < RECORD_HEADER_SIZE {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "torn record header"));
+    }
+    let len = BigEndian::read_u32(&data[0..4]) as usize;
+    let checksum = BigEndian::read_u32(&data[4..8]);
+    if len < RECORD_POINT_SIZE {
+        return Err(Error::new(ErrorKind::InvalidData, format!("record length {} is too short", len)));
+    }
+    if data.len() < RECORD_HEADER_SIZE + len {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "torn record"));
+    }
+
+    let payload = &data[RECORD_HEADER_SIZE .. RECORD_HEADER_SIZE + len];
+    if crc32(payload) != checksum {
+        return Err(Error::new(ErrorKind::InvalidData, "record checksum mismatch"));
+    }
+
+    let timestamp = BigEndian::read_u32(&payload[0..4]);
+    let value = BigEndian::read_f64(&payload[4..12]);
+    let name = try!(str::from_utf8(&payload[RECORD_POINT_SIZE..])
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "metric name is not utf8")));
+
+    Ok((NamedPoint::new(name.to_string(), timestamp, value), RECORD_HEADER_SIZE + len))
+}
+
+// CRC-32 (IEEE), the same checksum zlib and gzip use
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc = crc ^ byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use whisper::{ NamedPoint, Schema, WhisperCache, WhisperFile };
+
+    use std::fs::{ self, OpenOptions };
+    use std::io::Write;
+    use std::path::Path;
+    use std::time::Duration;
+
+    fn fresh_dir(name: &str) -> String {
+        let dir = format!("/tmp/whisper_wal_{}", name);
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn segments(dir: &str) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_torn_final_record() {
+        let points = vec![
+            NamedPoint::new("a.b".to_string(), 1434598525, 1.0),
+            NamedPoint::new("a.c".to_string(), 1434598526, 2.0)
+        ];
+        let mut buf = vec![];
+        for point in &points {
+            encode_record(point, &mut buf);
+        }
+        let full_len = buf.len();
+
+        assert_eq!(decode_segment(&buf, Path::new("test")), points);
+        assert_eq!(decode_segment(&buf[..full_len - 1], Path::new("test")), &points[..1]);
+        assert_eq!(decode_segment(&buf[..5], Path::new("test")), vec![]);
+
+        // Flip a bit in the last record's value
+        buf[full_len - 5] ^= 0x01;
+        assert_eq!(decode_segment(&buf, Path::new("test")), &points[..1]);
+    }
+
+    #[test]
+    fn test_rotate_and_truncate() {
+        let dir = fresh_dir("rotate");
+        let mut wal = WriteAheadLog::open(&dir, 60).unwrap();
+        // Each record for `a.b` is 23 bytes, so the third append rotates
+        for ts in 0..3 {
+            wal.append(&[NamedPoint::new("a.b".to_string(), ts, 1.0)]).unwrap();
+        }
+        assert_eq!(wal.last_sealed(), Some(0));
+        assert_eq!(segments(&dir), vec!["00000000000000000000.wal", "00000000000000000001.wal"]);
+
+        wal.truncate_through(0).unwrap();
+        assert_eq!(wal.last_sealed(), None);
+        assert_eq!(segments(&dir), vec!["00000000000000000001.wal"]);
+    }
+
+    #[test]
+    fn test_sync_interval() {
+        let dir = fresh_dir("sync");
+        let mut wal = WriteAheadLog::open(&dir, 1 << 20).unwrap();
+        wal.append(&[NamedPoint::new("a.b".to_string(), 1, 1.0)]).unwrap();
+        assert!(!wal.unsynced);
+
+        wal.set_sync_interval(Duration::from_secs(3600));
+        wal.append(&[NamedPoint::new("a.b".to_string(), 2, 1.0)]).unwrap();
+        assert!(wal.unsynced);
+        wal.sync_if_due().unwrap();
+        assert!(wal.unsynced);
+
+        wal.set_sync_interval(Duration::from_secs(0));
+        wal.sync_if_due().unwrap();
+        assert!(!wal.unsynced);
+    }
+
+    #[test]
+    fn test_replay_after_crash() {
+        let dir = fresh_dir("replay");
+        let storage = fresh_dir("replay_storage");
+        let now = ::time::get_time().sec as u32;
+        {
+            let mut wal = WriteAheadLog::open(&dir, 1 << 20).unwrap();
+            wal.append(&[
+                NamedPoint::new("replay.a".to_string(), now - 2, 1.0),
+                NamedPoint::new("replay.a".to_string(), now - 1, 2.0)
+            ]).unwrap();
+        }
+        // Simulate dying part way through the next append
+        {
+            let mut segment = OpenOptions::new().append(true).open(format!("{}/00000000000000000000.wal", dir)).unwrap();
+            segment.write_all(&[0, 0, 0, 40, 1, 2]).unwrap();
+        }
+
+        let schema = Schema::new_from_retention_specs(vec!["1s:60s".to_string()]).unwrap();
+        let mut cache = WhisperCache::new(&storage, 10, schema);
+        let mut wal = WriteAheadLog::open(&dir, 1 << 20).unwrap();
+        assert_eq!(wal.replay_into(&mut cache).unwrap(), 2);
+        assert_eq!(segments(&dir), vec!["00000000000000000001.wal"]);
+
+        let file = WhisperFile::open(format!("{}/replay/a.wsp", storage)).unwrap();
+        let mut values: Vec<f64> = vec![];
+        let mut points = vec![::whisper::Point::default(); file.archives[0].points()];
+        file.archives[0].read_points(file.archives[0].anchor_bucket_name(), &mut points).unwrap();
+        for point in points {
+            if point.0 != 0 { values.push(point.1); }
+        }
+        assert_eq!(values, vec![1.0, 2.0]);
+    }
+
+    #[test]
+    fn test_replay_skips_unwritable_metrics() {
+        let dir = fresh_dir("replay_invalid");
+        let storage = fresh_dir("replay_invalid_storage");
+        let now = ::time::get_time().sec as u32;
+        {
+            let mut wal = WriteAheadLog::open(&dir, 1 << 20).unwrap();
+            wal.append(&[
+                NamedPoint::new("/tmp/escape".to_string(), now - 1, 1.0),
+                NamedPoint::new("replay.ok".to_string(), now - 1, 2.0)
+            ]).unwrap();
+        }
+
+        let schema = Schema::new_from_retention_specs(vec!["1s:60s".to_string()]).unwrap();
+        let mut cache = WhisperCache::new(&storage, 10, schema);
+        let mut wal = WriteAheadLog::open(&dir, 1 << 20).unwrap();
+        assert_eq!(wal.replay_into(&mut cache).unwrap(), 1);
+        assert_eq!(segments(&dir), vec!["00000000000000000001.wal"]);
+        assert!(Path::new(&format!("{}/replay/ok.wsp", storage)).exists());
+    }
+}
@@ -0,0 +1,442 @@
+// Write-behind point cache, modelled on carbon-cache's MetricCache.
+//
+// Receivers `store` points in memory and return immediately. A single
+// writer thread pops whole metrics off the cache and hands their points
+// to `WhisperCache::write_many`, so bursts are absorbed in RAM instead of
+// stalling ingestion on page faults. With a `WriteAheadLog` attached every
+// stored point is logged first, and log segments are truncated once the
+// writer has put all of their points on disk.
+
+use whisper::Point;
+use super::{ WhisperCache, NamedPoint, WriteAheadLog };
+
+use std::collections::{ BTreeMap, HashMap, HashSet };
+use std::str::FromStr;
+use std::sync::{ Arc, Mutex, Condvar };
+use std::thread::{ self, JoinHandle };
+use std::time::{ Duration, Instant };
+
+// How long the writer sleeps when there is nothing to write
+const IDLE_WAIT_MS : u64 = 250;
+
+// Which metric the writer flushes next, as in carbon.conf's CACHE_WRITE_STRATEGY
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DrainStrategy {
+    // The metric with the most queued points
+    Max,
+    // Every metric once per pass, largest first
+    Sorted,
+    // Whichever metric the map hands out first
+    Naive
+}
+
+impl FromStr for DrainStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DrainStrategy, String> {
+        match s {
+            "max" => Ok(DrainStrategy::Max),
+            "sorted" => Ok(DrainStrategy::Sorted),
+            "naive" => Ok(DrainStrategy::Naive),
+            _ => Err(format!("Unknown cache write strategy `{}`, expected max, sorted or naive", s))
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WriteBehindConfig {
+    pub strategy: DrainStrategy,
+    // Total points held before new ones are dropped (MAX_CACHE_SIZE)
+    pub max_cache_size: Option<usize>,
+    // Per-metric batch writes allowed each second (MAX_UPDATES_PER_SECOND)
+    pub max_updates_per_second: Option<u32>
+}
+
+impl Default for WriteBehindConfig {
+    fn default() -> WriteBehindConfig {
+        WriteBehindConfig {
+            strategy: DrainStrategy::Sorted,
+            max_cache_size: None,
+            max_updates_per_second: None
+        }
+    }
+}
+
+struct State {
+    // Keyed by timestamp so a repeated point replaces the queued one
+    metrics: HashMap<String, BTreeMap<u32, f64>>,
+    size: usize,
+    // Remaining metrics in the current pass of the sorted strategy
+    queue: Vec<String>,
+    wal: Option<WriteAheadLog>,
+    closed: bool
+}
+
+// A sealed log segment and the metrics which must be written before it can go
+type Checkpoint = (u64, HashSet<String>);
+
+#[derive(Clone)]
+pub struct WriteBehindCache {
+    config: WriteBehindConfig,
+    state: Arc<(Mutex<State>, Condvar)>
+}
+
+impl WriteBehindCache {
+    pub fn new(config: WriteBehindConfig) -> WriteBehindCache {
+        WriteBehindCache::with_state(config, None)
+    }
+
+    // Log every stored point to `wal` before queueing it. Replay whatever
+    // the log holds from a previous run before handing it over.
+    pub fn with_wal(config: WriteBehindConfig, wal: WriteAheadLog) -> WriteBehindCache {
+        WriteBehindCache::with_state(config, Some(wal))
+    }
+
+    fn with_state(config: WriteBehindConfig, wal: Option<WriteAheadLog>) -> WriteBehindCache {
+        let state = State {
+            metrics: HashMap::new(),
+            size: 0,
+            queue: vec![],
+            wal: wal,
+            closed: false
+        };
+        WriteBehindCache {
+            config: config,
+            state: Arc::new((Mutex::new(state), Condvar::new()))
+        }
+    }
+
+    // Queue a point for writing. Returns false if the cache is full
+    // and the point was dropped.
+    pub fn store(&self, named_point: NamedPoint) -> bool {
+        self.store_many(vec![named_point]) == 1
+    }
+
+    // Queue a batch of points, returning how many were kept. Points
+    // beyond the cache's capacity are dropped, and so is the whole batch
+    // if it can't be logged, since a crash would lose it.
+    pub fn store_many(&self, mut named_points: Vec<NamedPoint>) -> usize {
+        let &(ref lock, ref wakeup) = &*self.state;
+        let mut state = lock.lock().unwrap();
+
+        if let Some(max_size) = self.config.max_cache_size {
+            let room = max_size.saturating_sub(state.size);
+            named_points.truncate(room);
+        }
+        if named_points.is_empty() {
+            return 0;
+        }
+
+        if let Some(ref mut wal) = state.wal {
+            if let Err(e) = wal.append(&named_points) {
+                warn!("unable to log {} points, dropping them: {}", named_points.len(), e);
+                return 0;
+            }
+        }
+
+        for named_point in &named_points {
+            let Point(timestamp, value) = named_point.point().clone();
+            let is_new = state.metrics
+                .entry(named_point.name().to_string())
+                .or_insert_with(BTreeMap::new)
+                .insert(timestamp, value)
+                .is_none();
+            if is_new {
+                state.size = state.size + 1;
+            }
+        }
+
+        wakeup.notify_one();
+        named_points.len()
+    }
+
+    // Points queued across every metric
+    pub fn size(&self) -> usize {
+        self.state.0.lock().unwrap().size
+    }
+
+    // Remove the next metric chosen by the drain strategy, with its
+    // points in chronological order
+    pub fn pop(&self) -> Option<(String, Vec<Point>)> {
+        let mut state = self.state.0.lock().unwrap();
+        self.pop_locked(&mut state)
+    }
+
+    fn pop_locked(&self, state: &mut State) -> Option<(String, Vec<Point>)> {
+        let metric = match self.config.strategy {
+            DrainStrategy::Max => {
+                state.metrics.iter().max_by_key(|&(_, points)| points.len()).map(|(name, _)| name.clone())
+            },
+            DrainStrategy::Naive => {
+                state.metrics.keys().next().cloned()
+            },
+            DrainStrategy::Sorted => {
+                if state.queue.is_empty() {
+                    let mut by_size: Vec<(&String, usize)> = state.metrics.iter().map(|(name, points)| (name, points.len())).collect();
+                    // Smallest first since the queue is popped from the back
+                    by_size.sort_by_key(|&(_, len)| len);
+                    state.queue = by_size.into_iter().map(|(name, _)| name.clone()).collect();
+                }
+                state.queue.pop()
+            }
+        };
+
+        metric.and_then(|name| state.metrics.remove(&name).map(|points| {
+            state.size = state.size - points.len();
+            let points = points.into_iter().map(|(timestamp, value)| Point(timestamp, value)).collect();
+            (name, points)
+        }))
+    }
+
+    // No more points are coming: the writer writes out everything
+    // still queued, ignoring the rate limit, and then exits
+    pub fn close(&self) {
+        let &(ref lock, ref wakeup) = &*self.state;
+        lock.lock().unwrap().closed = true;
+        wakeup.notify_all();
+    }
+
+    // Start the writer thread. It runs until `close` is called and the
+    // cache is empty, flushes the open files and hands the cache back.
+    pub fn spawn_writer(&self, mut cache: WhisperCache) -> JoinHandle<WhisperCache> {
+        let write_behind = self.clone();
+        thread::spawn(move || {
+            let mut limiter = RateLimiter::new(write_behind.config.max_updates_per_second);
+            let mut checkpoint: Option<Checkpoint> = None;
+            while let Some((metric, points)) = write_behind.next_batch() {
+                limiter.wait_unless(write_behind.is_closed());
+                cache.write_many(&metric, &points)
+                    .unwrap_or_else(|e| warn!("unable to write {} points for `{}`: {}", points.len(), metric, e));
+
+                if let Some((_, ref mut waiting)) = checkpoint {
+                    waiting.remove(&metric);
+                }
+                checkpoint = write_behind.advance_checkpoint(checkpoint, &mut cache);
+            }
+
+            cache.flush().unwrap_or_else(|e| warn!("unable to flush whisper files: {}", e));
+            write_behind.truncate_wal();
+            cache
+        })
+    }
+
+    // Once every metric queued when a segment was sealed has been written,
+    // nothing in that segment is only in memory any more and it can go.
+    fn advance_checkpoint(&self, checkpoint: Option<Checkpoint>, cache: &mut WhisperCache) -> Option<Checkpoint> {
+        let (sealed, waiting) = match checkpoint.or_else(|| self.begin_checkpoint()) {
+            Some(checkpoint) => checkpoint,
+            None => return None
+        };
+        if !waiting.is_empty() {
+            return Some((sealed, waiting));
+        }
+
+        if let Err(e) = cache.flush() {
+            warn!("unable to flush whisper files, keeping log segment {}: {}", sealed, e);
+            return None;
+        }
+        if let Some(ref mut wal) = self.state.0.lock().unwrap().wal {
+            wal.truncate_through(sealed).unwrap_or_else(|e| warn!("unable to truncate log: {}", e));
+        }
+        None
+    }
+
+    fn begin_checkpoint(&self) -> Option<Checkpoint> {
+        let state = self.state.0.lock().unwrap();
+        state.wal.as_ref()
+            .and_then(|wal| wal.last_sealed())
+            .map(|sealed| (sealed, state.metrics.keys().cloned().collect()))
+    }
+
+    // Everything has been written and flushed, so no segment is needed
+    fn truncate_wal(&self) {
+        if let Some(ref mut wal) = self.state.0.lock().unwrap().wal {
+            wal.rotate()
+                .and_then(|sealed| wal.truncate_through(sealed))
+                .unwrap_or_else(|e| warn!("unable to truncate log: {}", e));
+        }
+    }
+
+    // Blocks until a metric is ready, or returns None once closed and empty.
+    // The log is synced while waiting, so a quiet log isn't left unsynced.
+    fn next_batch(&self) -> Option<(String, Vec<Point>)> {
+        let &(ref lock, ref wakeup) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        loop {
+            if let Some(batch) = self.pop_locked(&mut state) {
+                return Some(batch);
+            }
+            if state.closed {
+                return None;
+            }
+            if let Some(ref mut wal) = state.wal {
+                wal.sync_if_due().unwrap_or_else(|e| warn!("unable to sync log: {}", e));
+            }
+            state = wakeup.wait_timeout(state, Duration::from_millis(IDLE_WAIT_MS)).unwrap().0;
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.state.0.lock().unwrap().closed
+    }
+}
+
+// Spaces out batch writes so at most `limit` happen each second
+struct RateLimiter {
+    limit: Option<u32>,
+    window_start: Instant,
+    used: u32
+}
+
+impl RateLimiter {
+    fn new(limit: Option<u32>) -> RateLimiter {
+        RateLimiter {
+            limit: limit,
+            window_start: Instant::now(),
+            used: 0
+        }
+    }
+
+    fn wait_unless(&mut self, unlimited: bool) {
+        let limit = match self.limit {
+            Some(limit) if !unlimited => limit,
+            _ => return
+        };
+
+        let elapsed = self.window_start.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.window_start = Instant::now();
+            self.used = 0;
+        } else if self.used >= limit {
+            thread::sleep(Duration::from_secs(1) - elapsed);
+            self.window_start = Instant::now();
+            self.used = 0;
+        }
+        self.used = self.used + 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use whisper::{ Point, NamedPoint, Schema, WhisperCache };
+    use whisper::cache::WriteAheadLog;
+
+    use std::fs;
+
+    fn point(name: &str, timestamp: u32, value: f64) -> NamedPoint {
+        NamedPoint::new(name.to_string(), timestamp, value)
+    }
+
+    fn filled_cache(strategy: DrainStrategy) -> WriteBehindCache {
+        let cache = WriteBehindCache::new(WriteBehindConfig { strategy: strategy, ..Default::default() });
+        cache.store(point("a", 10, 1.0));
+        cache.store(point("b", 10, 1.0));
+        cache.store(point("b", 11, 2.0));
+        cache.store(point("c", 10, 1.0));
+        cache.store(point("c", 11, 2.0));
+        cache.store(point("c", 12, 3.0));
+        cache
+    }
+
+    #[test]
+    fn test_repeated_timestamp_replaces_point() {
+        let cache = WriteBehindCache::new(Default::default());
+        cache.store(point("a", 12, 1.0));
+        cache.store(point("a", 10, 2.0));
+        cache.store(point("a", 12, 3.0));
+        assert_eq!(cache.size(), 2);
+        assert_eq!(cache.pop(), Some(("a".to_string(), vec![Point(10, 2.0), Point(12, 3.0)])));
+        assert_eq!(cache.size(), 0);
+        assert_eq!(cache.pop(), None);
+    }
+
+    #[test]
+    fn test_max_strategy() {
+        let cache = filled_cache(DrainStrategy::Max);
+        assert_eq!(cache.pop().unwrap().0, "c");
+        cache.store(point("a", 11, 1.0));
+        cache.store(point("a", 12, 1.0));
+        assert_eq!(cache.pop().unwrap().0, "a");
+        assert_eq!(cache.pop().unwrap().0, "b");
+    }
+
+    #[test]
+    fn test_sorted_strategy() {
+        let cache = filled_cache(DrainStrategy::Sorted);
+        assert_eq!(cache.pop().unwrap().0, "c");
+        // Sorted only re-sorts once a pass is complete
+        cache.store(point("a", 11, 1.0));
+        cache.store(point("a", 12, 1.0));
+        assert_eq!(cache.pop().unwrap().0, "b");
+        assert_eq!(cache.pop().unwrap().0, "a");
+        assert_eq!(cache.pop(), None);
+    }
+
+    #[test]
+    fn test_naive_strategy() {
+        let cache = filled_cache(DrainStrategy::Naive);
+        let mut names: Vec<String> = (0..3).map(|_| cache.pop().unwrap().0).collect();
+        names.sort();
+        assert_eq!(names, vec!["a", "b", "c"]);
+        assert_eq!(cache.size(), 0);
+    }
+
+    #[test]
+    fn test_max_cache_size() {
+        let cache = WriteBehindCache::new(WriteBehindConfig { max_cache_size: Some(2), ..Default::default() });
+        assert!(cache.store(point("a", 10, 1.0)));
+        assert!(cache.store(point("a", 11, 1.0)));
+        assert!(!cache.store(point("a", 12, 1.0)));
+        assert_eq!(cache.size(), 2);
+    }
+
+    #[test]
+    fn test_checkpoint_truncates_wal() {
+        let wal_dir = "/tmp/whisper_write_behind_wal";
+        let _ = fs::remove_dir_all(wal_dir);
+        let schema = Schema::new_from_retention_specs(vec!["1s:60s".to_string()]).unwrap();
+        let mut whisper_cache = WhisperCache::new("/tmp/whisper_write_behind", 10, schema);
+
+        // Small enough that every point seals a segment
+        let wal = WriteAheadLog::open(wal_dir, 1).unwrap();
+        let cache = WriteBehindCache::with_wal(Default::default(), wal);
+        cache.store(point("wal.a", 1434598525, 1.0));
+        cache.store(point("wal.b", 1434598525, 1.0));
+        assert_eq!(fs::read_dir(wal_dir).unwrap().count(), 3);
+
+        let checkpoint = cache.advance_checkpoint(None, &mut whisper_cache);
+        let (sealed, waiting) = checkpoint.clone().unwrap();
+        assert_eq!(sealed, 1);
+        assert_eq!(waiting.len(), 2);
+
+        // Writing only one of the two metrics isn't enough
+        let (metric, _) = cache.pop().unwrap();
+        let mut checkpoint = checkpoint;
+        checkpoint.as_mut().unwrap().1.remove(&metric);
+        let checkpoint = cache.advance_checkpoint(checkpoint, &mut whisper_cache);
+        assert!(checkpoint.is_some());
+        assert_eq!(fs::read_dir(wal_dir).unwrap().count(), 3);
+
+        let (metric, _) = cache.pop().unwrap();
+        let mut checkpoint = checkpoint;
+        checkpoint.as_mut().unwrap().1.remove(&metric);
+        assert!(cache.advance_checkpoint(checkpoint, &mut whisper_cache).is_none());
+        assert_eq!(fs::read_dir(wal_dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_writer_drains_on_close() {
+        let schema = Schema::new_from_retention_specs(vec!["1s:60s".to_string()]).unwrap();
+        let whisper_cache = WhisperCache::new("/tmp/whisper_write_behind", 10, schema);
+        let cache = WriteBehindCache::new(WriteBehindConfig { max_updates_per_second: Some(1), ..Default::default() });
+
+        let writer = cache.spawn_writer(whisper_cache);
+        for metric in &["drain.a", "drain.b", "drain.c"] {
+            cache.store(point(metric, 1434598525, 1.0));
+        }
+        cache.close();
+        writer.join().unwrap();
+        assert_eq!(cache.size(), 0);
+    }
+}
@@ -0,0 +1,393 @@
+use whisper::{ WriteBehindCache, NamedPoint, InfluxTemplate, TagOrder };
+
+use byteorder::{ ByteOrder, BigEndian };
+use libc;
+
+use std::io::{ self, Read, ErrorKind };
+use std::net::{ TcpListener, TcpStream, UdpSocket };
+use std::str;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::mpsc::{ Sender, Receiver };
+use std::thread::{ self, JoinHandle };
+use std::time::Duration;
+use time;
+
+// How long a blocked socket waits before checking for shutdown again
+const POLL_INTERVAL_MS : u64 = 250;
+
+// Largest datagram we will accept, the UDP maximum
+const MAX_DATAGRAM_SIZE : usize = 65535;
+
+// Same limit carbon puts on a single pickle message
+const MAX_PICKLE_SIZE : usize = 1 << 20;
+
+// Longest line the line protocols buffer while waiting for its newline
+const MAX_LINE_SIZE : usize = 1 << 20;
+
+// Set from the signal handler, which can't own anything
+static SIGNALLED : AtomicBool = AtomicBool::new(false);
+
+extern "C" fn record_signal(_signal: libc::c_int) {
+    SIGNALLED.store(true, Ordering::SeqCst);
+}
+
+// Shared flag telling every listener to stop accepting data
+#[derive(Clone, Default)]
+pub struct Shutdown(Arc<AtomicBool>);
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown(Arc::new(AtomicBool::new(false)))
+    }
+
+    pub fn request(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    // Request shutdown when the process receives SIGINT or SIGTERM
+    pub fn on_signals(&self) -> JoinHandle<()> {
+        unsafe {
+            libc::signal(libc::SIGINT, record_signal as libc::sighandler_t);
+            libc::signal(libc::SIGTERM, record_signal as libc::sighandler_t);
+        }
+
+        let shutdown = self.clone();
+        thread::spawn(move || {
+            while !shutdown.is_requested() {
+                if SIGNALLED.load(Ordering::SeqCst) {
+                    info!("signal received, shutting down");
+                    shutdown.request();
+                } else {
+                    thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+                }
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Protocol {
+    // Newline separated `metric value timestamp` lines
+    Plaintext,
+    // Big endian u32 length prefix followed by a pickled list of
+    // `(metric, (timestamp, value))` tuples
+    Pickle,
+    // Newline separated InfluxDB line protocol, named by the template
+    Influx(InfluxTemplate),
+    // OpenTSDB's telnet `put` lines, with tags appended in the given order.
+    // Carbon plaintext lines are accepted alongside them.
+    OpenTsdb(TagOrder)
+}
+
+// Accepts connections speaking `protocol` until shutdown is requested.
+// The returned thread only finishes once every connection it spawned
+// has finished, so joining it means no more points will be sent.
+pub fn spawn_tcp_listener(listener: TcpListener, protocol: Protocol, sink: Sender<Vec<NamedPoint>>, shutdown: Shutdown) -> io::Result<JoinHandle<()>> {
+    try!(listener.set_nonblocking(true));
+
+    Ok(thread::spawn(move || {
+        let mut connections: Vec<JoinHandle<()>> = vec![];
+        while !shutdown.is_requested() {
+            match listener.accept() {
+                Ok((stream, peer)) => {
+                    debug!("accepted {:?} connection from {}", protocol, peer);
+                    let protocol = protocol.clone();
+                    let sink = sink.clone();
+                    let shutdown = shutdown.clone();
+                    connections.push(thread::spawn(move || {
+                        handle_connection(stream, protocol, sink, shutdown)
+                            .unwrap_or_else(|e| warn!("connection from {} failed: {}", peer, e));
+                    }));
+                    // Don't let finished connections pile up
+                    connections.retain(|connection| !connection.is_finished());
+                },
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+                },
+                Err(e) => warn!("unable to accept connection: {}", e)
+            }
+        }
+
+        for connection in connections {
+            connection.join().unwrap_or_else(|_| warn!("connection thread panicked"));
+        }
+    }))
+}
+
+fn handle_connection(mut stream: TcpStream, protocol: Protocol, sink: Sender<Vec<NamedPoint>>, shutdown: Shutdown) -> io::Result<()> {
+    try!(stream.set_nonblocking(false));
+    try!(stream.set_read_timeout(Some(Duration::from_millis(POLL_INTERVAL_MS))));
+
+    // Kept across reads so a message split between packets isn't lost
+    let mut pending : Vec<u8> = vec![];
+    let mut buf = [0u8; 8192];
+    while !shutdown.is_requested() {
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => {
+                pending.extend_from_slice(&buf[..len]);
+                match protocol {
+                    Protocol::Pickle => try!(send_pickles(&mut pending, &sink)),
+                    _ => try!(send_lines(&mut pending, &protocol, &sink))
+                }
+            },
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e)
+        }
+    }
+
+    // Whatever trailed the final newline is still a complete line
+    if protocol != Protocol::Pickle && !pending.is_empty() {
+        send_line(&pending, &protocol, &sink);
+    }
+    Ok(())
+}
+
+// Sends every complete line, keeping the unfinished one for the next read
+fn send_lines(pending: &mut Vec<u8>, protocol: &Protocol, sink: &Sender<Vec<NamedPoint>>) -> io::Result<()> {
+    let mut start = 0;
+    while let Some(end) = pending[start..].iter().position(|&b| b == b'\n') {
+        send_line(&pending[start .. start + end + 1], protocol, sink);
+        start = start + end + 1;
+    }
+    pending.drain(..start);
+
+    if pending.len() > MAX_LINE_SIZE {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("line exceeds the {} byte limit", MAX_LINE_SIZE)));
+    }
+    Ok(())
+}
+
+fn send_line(line: &[u8], protocol: &Protocol, sink: &Sender<Vec<NamedPoint>>) {
+    let text = match str::from_utf8(line) {
+        Ok(text) => text.trim_end_matches(|c| c == '\r' || c == '\n'),
+        Err(_) => {
+            warn!("dropping line with invalid utf8");
+            return;
+        }
+    };
+    if text.is_empty() { return; }
+
+    let parsed = match *protocol {
+        Protocol::Influx(ref template) => NamedPoint::from_line_protocol(text, template, time::get_time().sec as u32),
+        Protocol::OpenTsdb(ref order) => NamedPoint::parse_put_or_line(text, order).map(|named_point| vec![named_point]),
+        _ => NamedPoint::parse_line(text).map(|named_point| vec![named_point])
+    };
+    match parsed {
+        Ok(ref named_points) if named_points.is_empty() => (),
+        Ok(named_points) => { let _ = sink.send(named_points); },
+        Err(why) => warn!("dropping line: {}", why)
+    }
+}
+
+fn send_pickles(pending: &mut Vec<u8>, sink: &Sender<Vec<NamedPoint>>) -> io::Result<()> {
+    while pending.len() >= 4 {
+        let len = BigEndian::read_u32(&pending[0..4]) as usize;
+        if len > MAX_PICKLE_SIZE {
+            return Err(io::Error::new(ErrorKind::InvalidData, format!("pickle of {} bytes exceeds the {} byte limit", len, MAX_PICKLE_SIZE)));
+        }
+        if pending.len() < 4 + len { break; }
+
+        match NamedPoint::from_pickle(&pending[4 .. 4 + len]) {
+            Ok(named_points) => { let _ = sink.send(named_points); },
+            Err(why) => warn!("dropping pickle: {}", why)
+        }
+        pending.drain(.. 4 + len);
+    }
+    Ok(())
+}
+
+// Receives carbon plaintext datagrams until shutdown is requested
+pub fn spawn_udp_listener(socket: UdpSocket, sink: Sender<Vec<NamedPoint>>, shutdown: Shutdown) -> io::Result<JoinHandle<()>> {
+    try!(socket.set_read_timeout(Some(Duration::from_millis(POLL_INTERVAL_MS))));
+
+    Ok(thread::spawn(move || {
+        let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+        while !shutdown.is_requested() {
+            match socket.recv_from(&mut buf) {
+                Ok((len, peer)) => match NamedPoint::from_datagram(&buf[..len]) {
+                    Ok(named_points) => { let _ = sink.send(named_points); },
+                    Err(why) => warn!("dropping datagram from {}: {}", peer, why)
+                },
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => continue,
+                Err(e) => warn!("unable to receive datagram: {}", e)
+            }
+        }
+    }))
+}
+
+// Queues incoming points for the writer until every listener has hung up
+pub fn store_points(points: Receiver<Vec<NamedPoint>>, cache: &WriteBehindCache) {
+    for named_points in points.iter() {
+        for named_point in named_points {
+            if !cache.store(named_point) {
+                warn!("unable to queue point, dropping it");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use whisper::NamedPoint;
+
+    use std::io::{ Read, Write };
+    use std::net::{ TcpListener, TcpStream, UdpSocket };
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_tcp_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sink, points) = mpsc::channel();
+        let shutdown = Shutdown::new();
+        let handle = spawn_tcp_listener(listener, Protocol::Plaintext, sink, shutdown.clone()).unwrap();
+
+        {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"home.pets.bears.lua.purr_volume 100.00 1434598525\r\n").unwrap();
+            stream.write_all(b"not a valid line\nhome.pets.cats.purr_volume 4 1434598525").unwrap();
+        }
+
+        let first = points.recv().unwrap();
+        assert_eq!(first, vec![NamedPoint::new("home.pets.bears.lua.purr_volume".to_string(), 1434598525, 100.0)]);
+        let second = points.recv().unwrap();
+        assert_eq!(second, vec![NamedPoint::new("home.pets.cats.purr_volume".to_string(), 1434598525, 4.0)]);
+
+        shutdown.request();
+        handle.join().unwrap();
+        assert!(points.recv().is_err());
+    }
+
+    #[test]
+    fn test_line_too_long() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sink, points) = mpsc::channel();
+        let shutdown = Shutdown::new();
+        let handle = spawn_tcp_listener(listener, Protocol::Plaintext, sink, shutdown.clone()).unwrap();
+
+        {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"a.b 1 1434598525\n").unwrap();
+            // The connection is dropped part way through, so the write may fail
+            let _ = stream.write_all(&vec![b'a'; MAX_LINE_SIZE + 8192]);
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            match stream.read(&mut [0u8; 1]) {
+                Ok(0) => (),
+                Err(ref e) if e.kind() == ErrorKind::ConnectionReset => (),
+                other => panic!("connection was left open: {:?}", other)
+            }
+        }
+
+        assert_eq!(points.recv().unwrap(), vec![NamedPoint::new("a.b".to_string(), 1434598525, 1.0)]);
+        shutdown.request();
+        handle.join().unwrap();
+        assert!(points.recv().is_err());
+    }
+
+    #[test]
+    fn test_pickle_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sink, points) = mpsc::channel();
+        let shutdown = Shutdown::new();
+        let handle = spawn_tcp_listener(listener, Protocol::Pickle, sink, shutdown.clone()).unwrap();
+
+        // pickle.dumps([('a.b', (1434598525, 1.5))], protocol=2)
+        let pickled : &[u8] = b"\x80\x02]q\x00X\x03\x00\x00\x00a.bq\x01J}<\x82UG?\xf8\x00\x00\x00\x00\x00\x00\x86q\x02\x86q\x03a.";
+        let mut message = vec![0, 0, 0, pickled.len() as u8];
+        message.extend_from_slice(pickled);
+        {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            // Split the message to make sure partial frames are buffered
+            stream.write_all(&message[..10]).unwrap();
+            stream.flush().unwrap();
+            stream.write_all(&message[10..]).unwrap();
+        }
+
+        let received = points.recv().unwrap();
+        assert_eq!(received, vec![NamedPoint::new("a.b".to_string(), 1434598525, 1.5)]);
+
+        shutdown.request();
+        handle.join().unwrap();
+        assert!(points.recv().is_err());
+    }
+
+    #[test]
+    fn test_influx_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sink, points) = mpsc::channel();
+        let shutdown = Shutdown::new();
+        let template = InfluxTemplate::new("measurement.host.field").unwrap();
+        let handle = spawn_tcp_listener(listener, Protocol::Influx(template), sink, shutdown.clone()).unwrap();
+
+        {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"cpu,host=web01 idle=90,user=2.5 1434598525000000000\n").unwrap();
+            stream.write_all(b"cpu,host=web01 idle=\n").unwrap();
+        }
+
+        let received = points.recv().unwrap();
+        assert_eq!(received, vec![
+            NamedPoint::new("cpu.web01.idle".to_string(), 1434598525, 90.0),
+            NamedPoint::new("cpu.web01.user".to_string(), 1434598525, 2.5)
+        ]);
+
+        shutdown.request();
+        handle.join().unwrap();
+        assert!(points.recv().is_err());
+    }
+
+    #[test]
+    fn test_opentsdb_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sink, points) = mpsc::channel();
+        let shutdown = Shutdown::new();
+        let order = TagOrder::new("host").unwrap();
+        let handle = spawn_tcp_listener(listener, Protocol::OpenTsdb(order), sink, shutdown.clone()).unwrap();
+
+        {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"put sys.cpu.user 1434598525000 42 cpu=0 host=web01\n").unwrap();
+            stream.write_all(b"sys.load 1.5 1434598525\n").unwrap();
+        }
+
+        assert_eq!(points.recv().unwrap(), vec![NamedPoint::new("sys.cpu.user.web01.0".to_string(), 1434598525, 42.0)]);
+        assert_eq!(points.recv().unwrap(), vec![NamedPoint::new("sys.load".to_string(), 1434598525, 1.5)]);
+
+        shutdown.request();
+        handle.join().unwrap();
+        assert!(points.recv().is_err());
+    }
+
+    #[test]
+    fn test_udp_listener() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let (sink, points) = mpsc::channel();
+        let shutdown = Shutdown::new();
+        let handle = spawn_udp_listener(socket, sink, shutdown.clone()).unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.send_to(b"a.b 1 1434598525\na.c 2 1434598525\n", addr).unwrap();
+
+        let received = points.recv().unwrap();
+        assert_eq!(received, vec![
+            NamedPoint::new("a.b".to_string(), 1434598525, 1.0),
+            NamedPoint::new("a.c".to_string(), 1434598525, 2.0)
+        ]);
+
+        shutdown.request();
+        handle.join().unwrap();
+        assert!(points.recv().is_err());
+    }
+}
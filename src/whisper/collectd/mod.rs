@@ -0,0 +1,42 @@
+// Receives collectd's binary network protocol (its network plugin, by
+// default on UDP 25826) and turns each value in to a `NamedPoint`.
+
+mod packet;
+mod types_db;
+
+pub use self::packet::Decoder;
+pub use self::types_db::TypesDb;
+
+use whisper::NamedPoint;
+use whisper::carbon::Shutdown;
+
+use std::io::{ self, ErrorKind };
+use std::net::UdpSocket;
+use std::sync::mpsc::Sender;
+use std::thread::{ self, JoinHandle };
+use std::time::Duration;
+
+// How long a blocked socket waits before checking for shutdown again
+const POLL_INTERVAL_MS : u64 = 250;
+
+const MAX_DATAGRAM_SIZE : usize = 65535;
+
+// Receives collectd packets until shutdown is requested
+pub fn spawn_udp_listener(socket: UdpSocket, decoder: Decoder, sink: Sender<Vec<NamedPoint>>, shutdown: Shutdown) -> io::Result<JoinHandle<()>> {
+    try!(socket.set_read_timeout(Some(Duration::from_millis(POLL_INTERVAL_MS))));
+
+    Ok(thread::spawn(move || {
+        let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+        while !shutdown.is_requested() {
+            match socket.recv_from(&mut buf) {
+                Ok((len, peer)) => match decoder.decode(&buf[..len]) {
+                    Ok(ref named_points) if named_points.is_empty() => (),
+                    Ok(named_points) => { let _ = sink.send(named_points); },
+                    Err(why) => warn!("dropping collectd packet from {}: {}", peer, why)
+                },
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => continue,
+                Err(e) => warn!("unable to receive datagram: {}", e)
+            }
+        }
+    }))
+}
@@ -0,0 +1,237 @@
+// collectd's binary network protocol, as sent by its network plugin.
+//
+// A packet is a run of parts, each a big endian u16 type and u16 length
+// (which counts the 4 byte header) followed by the body. String and time
+// parts set fields of the current value list, which carry over to later
+// parts, and every values part is one reading of the list as it stands.
+
+use byteorder::{ ByteOrder, BigEndian, LittleEndian };
+
+use std::str;
+
+use whisper::NamedPoint;
+use super::TypesDb;
+
+const PART_HOST : u16 = 0x0000;
+const PART_TIME : u16 = 0x0001;
+const PART_PLUGIN : u16 = 0x0002;
+const PART_PLUGIN_INSTANCE : u16 = 0x0003;
+const PART_TYPE : u16 = 0x0004;
+const PART_TYPE_INSTANCE : u16 = 0x0005;
+const PART_VALUES : u16 = 0x0006;
+const PART_TIME_HR : u16 = 0x0008;
+const PART_SIGNATURE : u16 = 0x0200;
+const PART_ENCRYPTION : u16 = 0x0210;
+
+const VALUE_COUNTER : u8 = 0;
+const VALUE_GAUGE : u8 = 1;
+const VALUE_DERIVE : u8 = 2;
+const VALUE_ABSOLUTE : u8 = 3;
+
+// Fields set by the parts before a values part
+#[derive(Debug, Default)]
+struct ValueList {
+    host: String,
+    time: u32,
+    plugin: String,
+    plugin_instance: String,
+    type_name: String,
+    type_instance: String
+}
+
+// Names metrics like collectd's write_graphite:
+// `<prefix>.<host>.<plugin>[-<plugin_instance>].<type>[-<type_instance>]`, with
+// the data source name appended when a type has more than one value.
+// Counter and derive values are written as they are, not as rates.
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    prefix: String,
+    types: TypesDb
+}
+
+impl Decoder {
+    pub fn new(prefix: &str, types: TypesDb) -> Decoder {
+        Decoder { prefix: prefix.to_string(), types: types }
+    }
+
+    // Signed and encrypted packets are rejected whole, since they can't be verified
+    pub fn decode(&self, packet: &[u8]) -> Result<Vec<NamedPoint>, String> {
+        let mut list = ValueList::default();
+        let mut named_points = vec![];
+        let mut offset = 0;
+        while offset < packet.len() {
+            if packet.len() - offset < 4 {
+                return Err(format!("packet ends with a truncated part header at byte {}", offset));
+            }
+            let part_type = BigEndian::read_u16(&packet[offset..]);
+            let length = BigEndian::read_u16(&packet[offset + 2 ..]) as usize;
+            if length < 4 || offset + length > packet.len() {
+                return Err(format!("part of type {:#06x} at byte {} has an invalid length of {}", part_type, offset, length));
+            }
+            let body = &packet[offset + 4 .. offset + length];
+
+            match part_type {
+                PART_HOST => list.host = try!(string_part(body)),
+                PART_PLUGIN => list.plugin = try!(string_part(body)),
+                PART_PLUGIN_INSTANCE => list.plugin_instance = try!(string_part(body)),
+                PART_TYPE => list.type_name = try!(string_part(body)),
+                PART_TYPE_INSTANCE => list.type_instance = try!(string_part(body)),
+                PART_TIME => list.time = try!(time_part(try!(numeric_part(body)))),
+                // Units of 2^-30 seconds
+                PART_TIME_HR => list.time = try!(time_part(try!(numeric_part(body)) >> 30)),
+                PART_VALUES => named_points.extend(try!(self.values_part(&list, body))),
+                PART_SIGNATURE => return Err("signed packets are not supported".to_string()),
+                PART_ENCRYPTION => return Err("encrypted packets are not supported".to_string()),
+                // Intervals, notifications and anything newer
+                _ => ()
+            }
+            offset += length;
+        }
+        Ok(named_points)
+    }
+
+    fn values_part(&self, list: &ValueList, body: &[u8]) -> Result<Vec<NamedPoint>, String> {
+        if list.host.is_empty() || list.plugin.is_empty() || list.type_name.is_empty() {
+            return Err("values part arrived before its host, plugin and type".to_string());
+        }
+        if body.len() < 2 {
+            return Err("values part is too short for its count".to_string());
+        }
+        let count = BigEndian::read_u16(body) as usize;
+        if body.len() != 2 + count * 9 {
+            return Err(format!("values part of {} bytes can't hold {} values", body.len() + 4, count));
+        }
+        let (kinds, values) = body[2..].split_at(count);
+
+        let name = self.metric_name(list);
+        let data_sources = self.types.data_sources(&list.type_name).filter(|names| names.len() == count);
+        kinds.iter().zip(values.chunks(8)).enumerate().map(|(index, (&kind, value))| {
+            let value = match kind {
+                VALUE_COUNTER | VALUE_ABSOLUTE => BigEndian::read_u64(value) as f64,
+                VALUE_GAUGE => LittleEndian::read_f64(value),
+                VALUE_DERIVE => BigEndian::read_i64(value) as f64,
+                _ => return Err(format!("unknown value type {} for `{}`", kind, name))
+            };
+            let name = if count == 1 {
+                name.clone()
+            } else {
+                // Without the type in types.db the values can only be told apart by position
+                let data_source = data_sources.map(|names| names[index].clone()).unwrap_or_else(|| index.to_string());
+                format!("{}.{}", name, sanitize_node(&data_source))
+            };
+            Ok(NamedPoint::new(name, list.time, value))
+        }).collect()
+    }
+
+    fn metric_name(&self, list: &ValueList) -> String {
+        format!("{}.{}.{}.{}", self.prefix, sanitize_node(&list.host),
+            with_instance(&list.plugin, &list.plugin_instance), with_instance(&list.type_name, &list.type_instance))
+    }
+}
+
+fn with_instance(name: &str, instance: &str) -> String {
+    if instance.is_empty() {
+        sanitize_node(name)
+    } else {
+        format!("{}-{}", sanitize_node(name), sanitize_node(instance))
+    }
+}
+
+// Dots would add levels to the metric's path, as write_graphite's EscapeCharacter avoids
+fn sanitize_node(node: &str) -> String {
+    node.chars().map(|c| if c == '.' || c.is_whitespace() { '_' } else { c }).collect()
+}
+
+// NUL terminated
+fn string_part(body: &[u8]) -> Result<String, String> {
+    match body.split_last() {
+        Some((&0, text)) => str::from_utf8(text).map(|text| text.to_string()).map_err(|_| "string part is not valid utf8".to_string()),
+        _ => Err("string part is not NUL terminated".to_string())
+    }
+}
+
+fn numeric_part(body: &[u8]) -> Result<u64, String> {
+    if body.len() != 8 {
+        return Err(format!("numeric part has {} bytes instead of 8", body.len()));
+    }
+    Ok(BigEndian::read_u64(body))
+}
+
+fn time_part(seconds: u64) -> Result<u32, String> {
+    if seconds > ::std::u32::MAX as u64 {
+        return Err(format!("time {} is out of range", seconds));
+    }
+    Ok(seconds as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(part_type: u16, body: &[u8]) -> Vec<u8> {
+        let mut part = vec![0u8; 4];
+        BigEndian::write_u16(&mut part[0..2], part_type);
+        BigEndian::write_u16(&mut part[2..4], body.len() as u16 + 4);
+        part.extend_from_slice(body);
+        part
+    }
+
+    fn string(part_type: u16, text: &str) -> Vec<u8> {
+        let mut body = text.as_bytes().to_vec();
+        body.push(0);
+        part(part_type, &body)
+    }
+
+    fn numeric(part_type: u16, number: u64) -> Vec<u8> {
+        let mut body = [0u8; 8];
+        BigEndian::write_u64(&mut body, number);
+        part(part_type, &body)
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut packet = vec![];
+        packet.extend(string(PART_HOST, "xle-forwarder-01"));
+        packet.extend(numeric(PART_TIME_HR, 1442949342 << 30));
+        packet.extend(string(PART_PLUGIN, "disk"));
+        packet.extend(string(PART_PLUGIN_INSTANCE, "vda"));
+        packet.extend(string(PART_TYPE, "disk_octets"));
+        // Two derives, read and write
+        packet.extend(part(PART_VALUES, &[0, 2, VALUE_DERIVE, VALUE_DERIVE, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 2]));
+        packet.extend(string(PART_PLUGIN, "memory"));
+        packet.extend(string(PART_PLUGIN_INSTANCE, ""));
+        packet.extend(string(PART_TYPE, "memory"));
+        packet.extend(string(PART_TYPE_INSTANCE, "used"));
+        let mut gauge = vec![0, 1, VALUE_GAUGE, 0, 0, 0, 0, 0, 0, 0, 0];
+        LittleEndian::write_f64(&mut gauge[3..], 1.5);
+        packet.extend(part(PART_VALUES, &gauge));
+
+        let decoder = Decoder::new("collectd.xle", TypesDb::default());
+        assert_eq!(decoder.decode(&packet), Ok(vec![
+            NamedPoint::new("collectd.xle.xle-forwarder-01.disk-vda.disk_octets.read".to_string(), 1442949342, 256.0),
+            NamedPoint::new("collectd.xle.xle-forwarder-01.disk-vda.disk_octets.write".to_string(), 1442949342, 2.0),
+            NamedPoint::new("collectd.xle.xle-forwarder-01.memory.memory-used".to_string(), 1442949342, 1.5)
+        ]));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let decoder = Decoder::new("collectd", TypesDb::default());
+        let mut header = string(PART_HOST, "web01.example");
+        header.extend(string(PART_PLUGIN, "load"));
+        header.extend(string(PART_TYPE, "load"));
+
+        let mut signed = part(PART_SIGNATURE, &[0; 36]);
+        signed.extend(header.iter().cloned());
+        assert_eq!(decoder.decode(&signed), Err("signed packets are not supported".to_string()));
+        assert_eq!(decoder.decode(&part(PART_ENCRYPTION, &[0; 40])), Err("encrypted packets are not supported".to_string()));
+
+        let mut short_values = header.clone();
+        short_values.extend(part(PART_VALUES, &[0, 3, VALUE_GAUGE]));
+        assert!(decoder.decode(&short_values).is_err());
+
+        assert!(decoder.decode(&part(PART_VALUES, &[0, 1, VALUE_GAUGE, 0, 0, 0, 0, 0, 0, 0, 0])).is_err());
+        assert!(decoder.decode(&[0, 0, 0, 9, b'a']).is_err());
+        assert!(decoder.decode(&part(PART_HOST, b"web01")).is_err());
+    }
+}
@@ -0,0 +1,93 @@
+// collectd's types.db: the data source names of each value type. A value
+// list with more than one value gets its data source name appended to the
+// metric name, as `disk_octets.read` and `disk_octets.write`.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// The multi-value types the stock plugins send most, used when no types.db is given
+const BUILTIN_TYPES : &'static str = "
+disk_io_time    io_time:COUNTER:0:U, weighted_io_time:COUNTER:0:U
+disk_merged     read:DERIVE:0:U, write:DERIVE:0:U
+disk_octets     read:DERIVE:0:U, write:DERIVE:0:U
+disk_ops        read:DERIVE:0:U, write:DERIVE:0:U
+disk_time       read:DERIVE:0:U, write:DERIVE:0:U
+if_dropped      rx:DERIVE:0:U, tx:DERIVE:0:U
+if_errors       rx:DERIVE:0:U, tx:DERIVE:0:U
+if_octets       rx:DERIVE:0:U, tx:DERIVE:0:U
+if_packets      rx:DERIVE:0:U, tx:DERIVE:0:U
+io_octets       rx:DERIVE:0:U, tx:DERIVE:0:U
+io_packets      rx:DERIVE:0:U, tx:DERIVE:0:U
+load            shortterm:GAUGE:0:5000, midterm:GAUGE:0:5000, longterm:GAUGE:0:5000
+node_octets     rx:DERIVE:0:U, tx:DERIVE:0:U
+ps_count        processes:GAUGE:0:1000000, threads:GAUGE:0:1000000
+ps_cputime      user:DERIVE:0:U, syst:DERIVE:0:U
+ps_disk_octets  read:DERIVE:0:U, write:DERIVE:0:U
+ps_disk_ops     read:DERIVE:0:U, write:DERIVE:0:U
+ps_pagefaults   minflt:DERIVE:0:U, majflt:DERIVE:0:U
+";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypesDb {
+    types: HashMap<String, Vec<String>>
+}
+
+impl TypesDb {
+    pub fn from_file<P>(path: P) -> Result<TypesDb, String>
+        where P: AsRef<Path> {
+        let mut contents = String::new();
+        try!(File::open(path.as_ref())
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|e| format!("Unable to read '{}'\nCaused by: {}", path.as_ref().display(), e)));
+        TypesDb::parse(&contents)
+    }
+
+    // `type ds_name:DS_TYPE:min:max, ...` lines
+    pub fn parse(types_db: &str) -> Result<TypesDb, String> {
+        let mut types = HashMap::new();
+        for (index, line) in types_db.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let split = try!(line.find(char::is_whitespace)
+                .ok_or_else(|| format!("types.db line {}: `{}` has no data sources", index + 1, line)));
+            let data_sources = line[split..].split(',')
+                .map(|data_source| data_source.trim().split(':').next().unwrap_or("").to_string())
+                .collect::<Vec<String>>();
+            if data_sources.iter().any(|name| name.is_empty()) {
+                return Err(format!("types.db line {}: `{}` has an unnamed data source", index + 1, line));
+            }
+            types.insert(line[..split].to_string(), data_sources);
+        }
+        Ok(TypesDb { types: types })
+    }
+
+    pub fn data_sources(&self, type_name: &str) -> Option<&[String]> {
+        self.types.get(type_name).map(|data_sources| data_sources.as_slice())
+    }
+}
+
+impl Default for TypesDb {
+    fn default() -> TypesDb {
+        TypesDb::parse(BUILTIN_TYPES).expect("built in types.db is valid")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_types_db() {
+        let types = TypesDb::parse("# comment\nload\t\tshortterm:GAUGE:0:5000, midterm:GAUGE:0:5000, longterm:GAUGE:0:5000\ncpu value:DERIVE:0:U\n").unwrap();
+        assert_eq!(types.data_sources("load"), Some(&["shortterm".to_string(), "midterm".to_string(), "longterm".to_string()][..]));
+        assert_eq!(types.data_sources("cpu"), Some(&["value".to_string()][..]));
+        assert_eq!(types.data_sources("memory"), None);
+        assert!(TypesDb::parse("load").is_err());
+        assert_eq!(TypesDb::default().data_sources("disk_octets"), Some(&["read".to_string(), "write".to_string()][..]));
+    }
+}
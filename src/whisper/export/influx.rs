@@ -0,0 +1,55 @@
+// InfluxDB line protocol, the reverse of what the receiver accepts. An
+// `InfluxTemplate` splits metric names back in to a measurement, tags and a
+// field key; names it doesn't fit are written whole with a `value` field.
+
+use whisper::{ Point, InfluxTemplate };
+
+const NANOS_PER_SECOND : i64 = 1_000_000_000;
+
+// Formats `points` as one line each, oldest first as given. Line protocol
+// has no way to represent NaN or infinity, so those points are left out.
+pub fn format_lines(metric_name: &str, points: &[Point], template: Option<&InfluxTemplate>) -> Vec<String> {
+    let (measurement, tags, field) = template
+        .and_then(|template| template.split(metric_name))
+        .unwrap_or_else(|| (metric_name.to_string(), vec![], "value".to_string()));
+
+    let mut series = escape(&measurement, &[',', ' ']);
+    for &(ref key, ref value) in &tags {
+        series.push_str(&format!(",{}={}", escape(key, &[',', '=', ' ']), escape(value, &[',', '=', ' '])));
+    }
+    let field = escape(&field, &[',', '=', ' ']);
+
+    points.iter()
+        .filter(|point| point.1.is_finite())
+        .map(|point| format!("{} {}={} {}", series, field, point.1, point.0 as i64 * NANOS_PER_SECOND))
+        .collect()
+}
+
+fn escape(text: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_lines() {
+        let template = InfluxTemplate::new("measurement.host.field").unwrap();
+        let points = vec![Point(1434598525, 1.5), Point(1434598535, ::std::f64::NAN), Point(1434598545, -2.0)];
+        assert_eq!(format_lines("cpu.web01.usage", &points, Some(&template)), vec![
+            "cpu,host=web01 usage=1.5 1434598525000000000".to_string(),
+            "cpu,host=web01 usage=-2 1434598545000000000".to_string()
+        ]);
+        assert_eq!(format_lines("load.web 01", &points[..1], Some(&template)), vec![
+            "load.web\\ 01 value=1.5 1434598525000000000".to_string()
+        ]);
+    }
+}
@@ -0,0 +1,54 @@
+// Writes whisper data out in other systems' formats, one archive at a time.
+
+mod openmetrics;
+mod influx;
+
+use std::io;
+
+use whisper::{ WhisperFile, Point };
+
+pub use self::openmetrics::{ OpenMetricsEncoder, LabelTemplate, sanitize_metric_name, sanitize_label_name };
+pub use self::influx::format_lines;
+
+// The points an archive still holds at `now`, oldest first. Slots which were
+// never written or have been left behind by the archive's retention are skipped.
+pub fn archive_points(file: &WhisperFile, archive: usize, now: u32) -> io::Result<Vec<Point>> {
+    let archive = match file.archives.get(archive) {
+        Some(archive) => archive,
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+            format!("archive {} does not exist, the file has {} archives", archive, file.archives.len())))
+    };
+
+    let oldest = now.saturating_sub(archive.retention() as u32);
+    let mut points: Vec<Point> = try!(archive.all_points()).into_iter()
+        .filter(|point| point.0 != 0 && point.0 > oldest && point.0 <= now)
+        .collect();
+    points.sort_by_key(|point| point.0);
+    Ok(points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use whisper::{ Schema, AggregationType };
+    use time;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_archive_points() {
+        let path = env::temp_dir().join("whisper-export-points.wsp");
+        let _ = fs::remove_file(&path);
+        let schema = Schema::new_from_retention_specs(vec!["60s:5m".to_string(), "5m:1h".to_string()]).unwrap();
+        let mut file = WhisperFile::new(&path, &schema, AggregationType::Average, 0.0).unwrap();
+        let now = time::get_time().sec as u32;
+        let now = now - now % 60;
+        file.write_many(&[Point(now - 120, 2.0), Point(now - 60, 3.0)]);
+
+        assert_eq!(archive_points(&file, 0, now).unwrap(), vec![Point(now - 120, 2.0), Point(now - 60, 3.0)]);
+        // Everything has aged out of the first archive
+        assert_eq!(archive_points(&file, 0, now + 600).unwrap(), vec![]);
+        assert!(archive_points(&file, 2, now).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
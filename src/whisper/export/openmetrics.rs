@@ -0,0 +1,219 @@
+// OpenMetrics (and Prometheus) text exposition format, with timestamps.
+//
+// Dotted graphite names become metric names by replacing everything
+// OpenMetrics doesn't allow with `_`. A `LabelTemplate` can first pull
+// path segments out as labels, so `servers.web01.load` can be exported as
+// `servers_load{host="web01"}`.
+
+use std::collections::BTreeMap;
+use std::io::{ self, Write };
+
+use whisper::Point;
+
+// Labels named by the template, in template order
+pub type Labels = Vec<(String, String)>;
+
+// A dotted pattern the same length as the metric names it applies to.
+// `{name}` turns a segment in to a label, `*` keeps any segment in the
+// metric name and anything else must match the segment exactly.
+#[derive(Debug, PartialEq, Clone)]
+pub struct LabelTemplate {
+    segments: Vec<TemplateSegment>
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum TemplateSegment {
+    Label(String),
+    Any,
+    Literal(String)
+}
+
+impl LabelTemplate {
+    pub fn new(template: &str) -> Result<LabelTemplate, String> {
+        let segments = try!(template.split('.').map(|segment| {
+            if segment.starts_with('{') && segment.ends_with('}') && segment.len() > 2 {
+                let label = &segment[1 .. segment.len() - 1];
+                if sanitize_label_name(label) != label {
+                    return Err(format!("`{}` in template `{}` is not a valid label name", label, template));
+                }
+                Ok(TemplateSegment::Label(label.to_string()))
+            } else if segment == "*" {
+                Ok(TemplateSegment::Any)
+            } else if segment.is_empty() || segment.contains('{') || segment.contains('}') {
+                Err(format!("template `{}` has an invalid segment `{}`", template, segment))
+            } else {
+                Ok(TemplateSegment::Literal(segment.to_string()))
+            }
+        }).collect::<Result<Vec<TemplateSegment>, String>>());
+
+        // A series can't have two values for one label
+        let mut labels = vec![];
+        for segment in &segments {
+            if let TemplateSegment::Label(ref label) = *segment {
+                if labels.contains(&label) {
+                    return Err(format!("label `{}` appears more than once in template `{}`", label, template));
+                }
+                labels.push(label);
+            }
+        }
+        Ok(LabelTemplate { segments: segments })
+    }
+
+    // The metric name and labels for `metric_name`, or None when it
+    // doesn't fit the template
+    pub fn apply(&self, metric_name: &str) -> Option<(String, Labels)> {
+        let nodes: Vec<&str> = metric_name.split('.').collect();
+        if nodes.len() != self.segments.len() {
+            return None;
+        }
+
+        let mut name = vec![];
+        let mut labels = vec![];
+        for (node, segment) in nodes.into_iter().zip(self.segments.iter()) {
+            match *segment {
+                TemplateSegment::Label(ref label) => labels.push((label.clone(), node.to_string())),
+                TemplateSegment::Any => name.push(node),
+                TemplateSegment::Literal(ref literal) if literal == node => name.push(node),
+                TemplateSegment::Literal(_) => return None
+            }
+        }
+        Some((sanitize_metric_name(&name.join(".")), labels))
+    }
+}
+
+// Metric names match `[a-zA-Z_:][a-zA-Z0-9_:]*`
+pub fn sanitize_metric_name(name: &str) -> String {
+    sanitize(name, |c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+// Label names match `[a-zA-Z_][a-zA-Z0-9_]*`
+pub fn sanitize_label_name(name: &str) -> String {
+    sanitize(name, |c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn sanitize<F>(name: &str, allowed: F) -> String where F: Fn(char) -> bool {
+    let mut sanitized: String = name.chars().map(|c| if allowed(c) { c } else { '_' }).collect();
+    if sanitized.is_empty() || sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// Collects series and writes them grouped in to metric families, since
+// OpenMetrics doesn't allow a family's samples to be interleaved with others
+pub struct OpenMetricsEncoder {
+    template: Option<LabelTemplate>,
+    // The graphite name each series came from, its labels and its points
+    families: BTreeMap<String, Vec<(String, Labels, Vec<Point>)>>
+}
+
+impl OpenMetricsEncoder {
+    pub fn new(template: Option<LabelTemplate>) -> OpenMetricsEncoder {
+        OpenMetricsEncoder { template: template, families: BTreeMap::new() }
+    }
+
+    // `points` must be oldest first. Two graphite names that sanitize to the
+    // same series, like `a-b.c` and `a_b.c`, can't both be exported.
+    pub fn add(&mut self, metric_name: &str, points: Vec<Point>) -> Result<(), String> {
+        let (name, labels) = self.template.as_ref()
+            .and_then(|template| template.apply(metric_name))
+            .unwrap_or_else(|| (sanitize_metric_name(metric_name), vec![]));
+        let family = self.families.entry(name.clone()).or_insert_with(Vec::new);
+        if let Some(&(ref earlier, _, _)) = family.iter().find(|&&(_, ref other, _)| *other == labels) {
+            return Err(format!("`{}` and `{}` would both be exported as `{}{}`", earlier, metric_name, name, format_labels(&labels)));
+        }
+        family.push((metric_name.to_string(), labels, points));
+        Ok(())
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for (name, series) in &self.families {
+            try!(write!(writer, "# TYPE {} gauge\n", name));
+            for &(_, ref labels, ref points) in series {
+                let labels = format_labels(labels);
+                for point in points {
+                    try!(write!(writer, "{}{} {} {}\n", name, labels, format_value(point.1), point.0));
+                }
+            }
+        }
+        write!(writer, "# EOF\n")
+    }
+}
+
+fn format_labels(labels: &Labels) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let pairs: Vec<String> = labels.iter()
+        .map(|&(ref label, ref value)| format!("{}=\"{}\"", label, escape_label_value(value)))
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        (if value > 0.0 { "+Inf" } else { "-Inf" }).to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize_metric_name("servers.web-01.load"), "servers_web_01_load");
+        assert_eq!(sanitize_metric_name("1min.load:avg"), "_1min_load:avg");
+        assert_eq!(sanitize_label_name("cpu:0"), "cpu_0");
+    }
+
+    #[test]
+    fn test_template() {
+        let template = LabelTemplate::new("servers.{host}.*").unwrap();
+        assert_eq!(template.apply("servers.web01.load"), Some(("servers_load".to_string(), vec![("host".to_string(), "web01".to_string())])));
+        assert_eq!(template.apply("servers.web01.cpu.idle"), None);
+        assert_eq!(template.apply("racks.web01.load"), None);
+        assert!(LabelTemplate::new("servers.{host-name}.*").is_err());
+        assert!(LabelTemplate::new("servers..*").is_err());
+        assert!(LabelTemplate::new("{x}.{x}").is_err());
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut encoder = OpenMetricsEncoder::new(Some(LabelTemplate::new("servers.{host}.*").unwrap()));
+        encoder.add("servers.web01.load", vec![Point(60, 1.5), Point(120, 2.0)]).unwrap();
+        encoder.add("other.metric", vec![Point(60, ::std::f64::NAN)]).unwrap();
+        encoder.add("servers.web\"02.load", vec![Point(60, -3.0)]).unwrap();
+
+        let mut out = vec![];
+        encoder.write_to(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "# TYPE other_metric gauge
+other_metric NaN 60
+# TYPE servers_load gauge
+servers_load{host=\"web01\"} 1.5 60
+servers_load{host=\"web01\"} 2 120
+servers_load{host=\"web\\\"02\"} -3 60
+# EOF
+");
+    }
+
+    #[test]
+    fn test_collisions() {
+        let mut encoder = OpenMetricsEncoder::new(Some(LabelTemplate::new("servers.{host}.*").unwrap()));
+        encoder.add("a-b.c", vec![Point(60, 1.0)]).unwrap();
+        assert_eq!(encoder.add("a_b.c", vec![Point(60, 2.0)]), Err("`a-b.c` and `a_b.c` would both be exported as `a_b_c`".to_string()));
+        encoder.add("servers.web01.load", vec![Point(60, 1.0)]).unwrap();
+        encoder.add("servers.web02.load", vec![Point(60, 1.0)]).unwrap();
+        assert!(encoder.add("servers.web01.load", vec![Point(60, 1.0)]).is_err());
+        // Same name, different labels
+        encoder.add("servers_load", vec![Point(60, 1.0)]).unwrap();
+    }
+}
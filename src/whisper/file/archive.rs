@@ -83,6 +83,13 @@ impl Archive {
         }
     }
 
+    // Every slot in the order it is stored, as whisper-dump.py reads them
+    pub fn all_points(&self) -> Result<Vec<Point>> {
+        let mut points = vec![Point::default(); self.points()];
+        try!(Archive::write_data_as_points_to_slice(self.slice(), &mut points));
+        Ok(points)
+    }
+
     fn write_data_as_points_to_slice(data: &[u8], buf: &mut [Point]) -> Result<()> {
         for (i, pt_data) in data.chunks(point::POINT_SIZE).enumerate() {
             if pt_data.len() != point::POINT_SIZE {
@@ -153,6 +160,10 @@ impl Archive {
         BucketName( first_four_bytes )
     }
 
+    pub fn flush(&self) -> Result<()> {
+        self.mmap_view.flush()
+    }
+
     #[inline]
     pub fn slice(&self) -> &[u8] {
         unsafe{ self.mmap_view.as_slice() }
@@ -0,0 +1,133 @@
+use std::io::{ Result, Error, ErrorKind };
+use std::iter::repeat;
+
+use time;
+
+use whisper::Point;
+use super::WhisperFile;
+use super::archive::BucketName;
+
+// A run of evenly spaced values read back out of one archive.
+// `values[i]` belongs to `from + i*step`; gaps are None.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SeriesData {
+    pub from: u32,
+    pub until: u32,
+    pub step: u32,
+    pub values: Vec<Option<f64>>
+}
+
+impl SeriesData {
+    pub fn timestamps(&self) -> Vec<u32> {
+        (0..self.values.len()).map(|i| self.from + i as u32 * self.step).collect()
+    }
+}
+
+impl WhisperFile {
+    // Read the values between `from` and `until` (default now) the same way
+    // whisper.py's fetch does: from the highest precision archive which still
+    // covers `from`. Returns None when the range is entirely outside the file.
+    pub fn fetch(&self, from: u32, until: Option<u32>) -> Result<Option<SeriesData>> {
+        let now = time::get_time().sec as u32;
+        self._fetch(from, until.unwrap_or(now), now)
+    }
+
+    fn _fetch(&self, from: u32, until: u32, now: u32) -> Result<Option<SeriesData>> {
+        if from > until {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Invalid time interval: from time '{}' is after until time '{}'", from, until)));
+        }
+
+        let oldest = now.saturating_sub(self.header.max_retention());
+        if from > now || until < oldest {
+            return Ok(None);
+        }
+        let from = if from < oldest { oldest } else { from };
+        let until = if until > now { now } else { until };
+
+        let age = (now - from) as usize;
+        let archive = match self.archives.iter().find(|archive| archive.retention() >= age) {
+            Some(archive) => archive,
+            None => &self.archives[self.archives.len() - 1]
+        };
+
+        let step = archive.seconds_per_point();
+        let from_interval = from - (from % step) + step;
+        let mut until_interval = until - (until % step) + step;
+        if from_interval == until_interval {
+            until_interval = until_interval + step;
+        }
+
+        let count = ((until_interval - from_interval) / step) as usize;
+        let mut values: Vec<Option<f64>> = repeat(None).take(count).collect();
+
+        // A window wider than the archive would wrap on to itself
+        let readable = if count > archive.points() { archive.points() } else { count };
+        let mut points: Vec<Point> = repeat(Point::default()).take(readable).collect();
+        try!(archive.read_points(BucketName(from_interval), &mut points));
+
+        for (i, point) in points.iter().enumerate() {
+            if point.0 == from_interval + i as u32 * step {
+                values[i] = Some(point.1);
+            }
+        }
+
+        Ok(Some(SeriesData {
+            from: from_interval,
+            until: until_interval,
+            step: step,
+            values: values
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use whisper::{ Schema, WhisperFile, Point };
+    use whisper::file::header::AggregationType;
+
+    fn sample_file() -> WhisperFile {
+        let specs = vec!["1s:10s".to_string(), "10s:1m".to_string(), "1m:3m".to_string()];
+        let schema = Schema::new_from_retention_specs(specs).unwrap();
+        let mut file = WhisperFile::new_transient(&schema, AggregationType::Average, 0.0);
+        for &(t, v) in [(1487974954, 1.0), (1487974956, 3.0), (1487974959, 9.0), (1487974962, 15.0)].iter() {
+            file._write(&Point(t, v), t as i64);
+        }
+        file
+    }
+
+    #[test]
+    fn test_fetch_high_precision() {
+        let file = sample_file();
+        let fetched = file._fetch(1487974955, 1487974962, 1487974962).unwrap().unwrap();
+        assert_eq!(fetched, SeriesData {
+            from: 1487974956,
+            until: 1487974963,
+            step: 1,
+            values: vec![Some(3.0), None, None, Some(9.0), None, None, Some(15.0)]
+        });
+        assert_eq!(fetched.timestamps()[3], 1487974959);
+    }
+
+    #[test]
+    fn test_fetch_picks_covering_archive() {
+        let file = sample_file();
+        let fetched = file._fetch(1487974910, 1487974962, 1487974962).unwrap().unwrap();
+        assert_eq!(fetched.step, 10);
+        assert_eq!(fetched.from, 1487974920);
+        assert_eq!(fetched.until, 1487974970);
+        assert_eq!(fetched.values, vec![None, None, None, Some(13.0 / 3.0), Some(15.0)]);
+
+        let fetched = file._fetch(1487974900, 1487974962, 1487974962).unwrap().unwrap();
+        assert_eq!(fetched.step, 60);
+    }
+
+    #[test]
+    fn test_fetch_outside_retention() {
+        let file = sample_file();
+        assert_eq!(file._fetch(1487974000, 1487974100, 1487974962).unwrap(), None);
+        assert_eq!(file._fetch(1487975000, 1487975100, 1487974962).unwrap(), None);
+        assert_eq!(format!("{}", file._fetch(10, 5, 1487974962).unwrap_err()),
+            "Invalid time interval: from time '10' is after until time '5'");
+    }
+}
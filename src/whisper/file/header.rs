@@ -1,4 +1,5 @@
 use std::fmt;
+use std::str::FromStr;
 
 use memmap::MmapViewSync;
 use byteorder::{ ByteOrder, BigEndian };
@@ -9,7 +10,10 @@ use super::super::point;
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum AggregationType {
     Average = 1,
-    Sum = 2
+    Sum = 2,
+    Last = 3,
+    Max = 4,
+    Min = 5
 }
 
 impl AggregationType {
@@ -21,7 +25,10 @@ impl AggregationType {
                 let sum: f64 = points.iter().map(point::Point::value).sum();
                 sum / count
             },
-            AggregationType::Sum => points.iter().map(point::Point::value).sum()
+            AggregationType::Sum => points.iter().map(point::Point::value).sum(),
+            AggregationType::Last => points.last().map(point::Point::value).unwrap_or(0.0),
+            AggregationType::Max => points.iter().map(point::Point::value).fold(::std::f64::NEG_INFINITY, f64::max),
+            AggregationType::Min => points.iter().map(point::Point::value).fold(::std::f64::INFINITY, f64::min)
         }
     }
 }
@@ -30,7 +37,26 @@ impl fmt::Display for AggregationType {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match *self {
 			AggregationType::Average => write!(f, "average"),
-			AggregationType::Sum => write!(f, "sum")
+			AggregationType::Sum => write!(f, "sum"),
+			AggregationType::Last => write!(f, "last"),
+			AggregationType::Max => write!(f, "max"),
+			AggregationType::Min => write!(f, "min")
+		}
+	}
+}
+
+// The names whisper.py's aggregationMethods uses
+impl FromStr for AggregationType {
+	type Err = String;
+
+	fn from_str(name: &str) -> Result<AggregationType, String> {
+		match name {
+			"average" => Ok(AggregationType::Average),
+			"sum" => Ok(AggregationType::Sum),
+			"last" => Ok(AggregationType::Last),
+			"max" => Ok(AggregationType::Max),
+			"min" => Ok(AggregationType::Min),
+			_ => Err(format!("unsupported aggregation method `{}`", name))
 		}
 	}
 }
@@ -39,6 +65,9 @@ impl AggregationType {
 	pub fn from_u32(val: u32) -> AggregationType {
 		match val {
 			2 => AggregationType::Sum,
+			3 => AggregationType::Last,
+			4 => AggregationType::Max,
+			5 => AggregationType::Min,
 			_  => AggregationType::Average
 		}
 	}
@@ -0,0 +1,143 @@
+use rustc_serialize::json::{ Json, ToJson };
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::WhisperFile;
+use super::header::{ Header, AggregationType };
+
+// What whisper.py's info() reports about a file, plus its size
+#[derive(Debug, PartialEq, Clone)]
+pub struct FileInfo {
+    pub aggregation_method: AggregationType,
+    pub max_retention: u32,
+    pub x_files_factor: f32,
+    pub file_size: u64,
+    pub archives: Vec<ArchiveInfo>
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ArchiveInfo {
+    pub offset: u64,
+    pub seconds_per_point: u32,
+    pub points: u32,
+    pub retention: u32,
+    pub size: u64
+}
+
+impl WhisperFile {
+    pub fn info(&self) -> FileInfo {
+        let mut offset = Header::archives_start(self.archives.len()) as u64;
+        let archives = self.archives.iter().map(|archive| {
+            let info = ArchiveInfo {
+                offset: offset,
+                seconds_per_point: archive.seconds_per_point(),
+                points: archive.points() as u32,
+                retention: archive.seconds_per_point() * archive.points() as u32,
+                size: archive.size() as u64
+            };
+            offset = offset + info.size;
+            info
+        }).collect();
+
+        FileInfo {
+            aggregation_method: self.header.aggregation_type(),
+            max_retention: self.header.max_retention(),
+            x_files_factor: self.header.x_files_factor(),
+            // Archives are laid out back to back, so the last one ends the file
+            file_size: offset,
+            archives: archives
+        }
+    }
+}
+
+// whisper-info.py's layout: the header fields, then a block per archive
+impl fmt::Display for FileInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "aggregationMethod: {}\nmaxRetention: {}\nxFilesFactor: {}\nfileSize: {}\n\n",
+            self.aggregation_method, self.max_retention, self.x_files_factor, self.file_size));
+
+        for (index, archive) in self.archives.iter().enumerate() {
+            try!(write!(f, "Archive {}\noffset: {}\nsecondsPerPoint: {}\npoints: {}\nretention: {}\nsize: {}\n\n",
+                index, archive.offset, archive.seconds_per_point, archive.points, archive.retention, archive.size));
+        }
+        Ok(())
+    }
+}
+
+// The keys whisper-info.py --json uses
+impl ToJson for FileInfo {
+    fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+        object.insert("aggregationMethod".to_string(), Json::String(self.aggregation_method.to_string()));
+        object.insert("maxRetention".to_string(), Json::U64(self.max_retention as u64));
+        object.insert("xFilesFactor".to_string(), Json::F64(self.x_files_factor as f64));
+        object.insert("fileSize".to_string(), Json::U64(self.file_size));
+        object.insert("archives".to_string(), self.archives.to_json());
+        Json::Object(object)
+    }
+}
+
+impl ToJson for ArchiveInfo {
+    fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+        object.insert("offset".to_string(), Json::U64(self.offset));
+        object.insert("secondsPerPoint".to_string(), Json::U64(self.seconds_per_point as u64));
+        object.insert("points".to_string(), Json::U64(self.points as u64));
+        object.insert("retention".to_string(), Json::U64(self.retention as u64));
+        object.insert("size".to_string(), Json::U64(self.size));
+        Json::Object(object)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use whisper::Schema;
+
+    fn sample_info() -> FileInfo {
+        let specs = vec!["1s:60s".to_string(), "1m:1d".to_string()];
+        let schema = Schema::new_from_retention_specs(specs).unwrap();
+        WhisperFile::new_transient(&schema, AggregationType::Sum, 0.5).info()
+    }
+
+    #[test]
+    fn test_info() {
+        let info = sample_info();
+        assert_eq!(info.max_retention, 86400);
+        assert_eq!(info.file_size, 16 + 2 * 12 + 60 * 12 + 1440 * 12);
+        assert_eq!(info.archives[1], ArchiveInfo { offset: 16 + 2 * 12 + 60 * 12, seconds_per_point: 60, points: 1440, retention: 86400, size: 1440 * 12 });
+    }
+
+    #[test]
+    fn test_info_layout() {
+        assert_eq!(sample_info().to_string(), "aggregationMethod: sum
+maxRetention: 86400
+xFilesFactor: 0.5
+fileSize: 18040
+
+Archive 0
+offset: 40
+secondsPerPoint: 1
+points: 60
+retention: 60
+size: 720
+
+Archive 1
+offset: 760
+secondsPerPoint: 60
+points: 1440
+retention: 86400
+size: 17280
+
+");
+    }
+
+    #[test]
+    fn test_info_json() {
+        let json = sample_info().to_json();
+        assert_eq!(json.find("maxRetention"), Some(&Json::U64(86400)));
+        assert_eq!(json.find("aggregationMethod"), Some(&Json::String("sum".to_string())));
+        assert_eq!(json.find("archives").and_then(|archives| archives[0].find("size")), Some(&Json::U64(720)));
+    }
+}
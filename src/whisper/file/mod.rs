@@ -3,6 +3,8 @@ use byteorder::{ BigEndian, WriteBytesExt };
 use time;
 
 mod header;
+mod fetch;
+mod info;
 pub mod archive;
 
 use self::header::Header;
@@ -10,15 +12,20 @@ use self::archive::Archive;
 
 pub use self::header::{STATIC_HEADER_SIZE, AggregationType};
 pub use self::archive::ARCHIVE_INFO_SIZE;
+pub use self::fetch::SeriesData;
+pub use self::info::{ FileInfo, ArchiveInfo };
 
 use whisper::Point;
 use whisper::Schema;
 use std::io::Result;
 
 // Modules needed to create file on disk
-use std::fs::OpenOptions;
+use std::fs::{ File, OpenOptions };
+use std::io::{ Seek, SeekFrom, Write };
 extern crate libc;
 use self::libc::ftruncate;
+#[cfg(target_os = "linux")]
+use self::libc::posix_fallocate;
 use std::os::unix::prelude::AsRawFd;
 use std::io;
 use std::path::{ Path, PathBuf };
@@ -44,9 +51,6 @@ impl fmt::Debug for WhisperFile {
 		let mut index = 0;
 		let mut offset = Header::archives_start(self.archives.len());
 
-		let max_points = self.archives.iter().map(|x| x.points()).max().unwrap();
-		let mut points_buf = Vec::with_capacity(max_points);
-
 		for archive in &self.archives {
 			try!(write!(f, "Archive {} info:
   offset: {}
@@ -58,8 +62,7 @@ impl fmt::Debug for WhisperFile {
 Archive {} data:
 ", index, offset, archive.seconds_per_point(), archive.points(), archive.seconds_per_point() * archive.points() as u32, archive.size(), index ));
 
-			unsafe{ points_buf.set_len(archive.points()) };
-			try!(archive.read_points(archive.anchor_bucket_name(), &mut points_buf).map_err(|_| fmt::Error));
+			let points_buf = try!(archive.all_points().map_err(|_| fmt::Error));
 
 			let mut points_index = 0;
 			for point in &points_buf {
@@ -76,21 +79,45 @@ Archive {} data:
 	}
 }
 
+// How a new file's space is reserved, like whisper-create.py's options
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Allocation {
+	// Write zeroes over the whole file, whisper.py's default
+	Zeroed,
+	// Only set the length and let the filesystem leave holes
+	Sparse,
+	// Ask the filesystem for the blocks up front without writing them
+	Fallocate
+}
+
 impl WhisperFile {
 	pub fn new<P>(path: P, schema: &Schema, agg: AggregationType, xff: f32) -> Result<WhisperFile>
         where P: AsRef<Path> {
-		let mut opened_file = try!(OpenOptions::new().read(true).write(true).create(true).open(path.as_ref()));
+		WhisperFile::new_with_allocation(path, schema, agg, xff, Allocation::Sparse)
+	}
+
+	// Fails with `AlreadyExists` rather than replace a file that's already
+	// there, which may be in use by another writer
+	pub fn new_with_allocation<P>(path: P, schema: &Schema, agg: AggregationType, xff: f32, allocation: Allocation) -> Result<WhisperFile>
+        where P: AsRef<Path> {
+		let mut opened_file = try!(OpenOptions::new().read(true).write(true).create_new(true).open(path.as_ref()));
 
 		// Allocate space on disk (could be costly!)
 		{
 			let size_needed = schema.size_on_disk();
-			let raw_fd = opened_file.as_raw_fd();
-			let retval = unsafe {
-				// TODO skip to fallocate-like behavior. Will need wrapper for OSX.
-				ftruncate(raw_fd, size_needed as i64)
-			};
-			if retval != 0 {
-				return Err(io::Error::last_os_error());
+			match allocation {
+				Allocation::Sparse => try!(truncate(&opened_file, size_needed)),
+				Allocation::Fallocate => try!(fallocate(&opened_file, size_needed)),
+				Allocation::Zeroed => {
+					let zeroes = [0u8; 16384];
+					let mut remaining = size_needed as usize;
+					while remaining > 0 {
+						let chunk = cmp::min(remaining, zeroes.len());
+						try!(opened_file.write_all(&zeroes[..chunk]));
+						remaining = remaining - chunk;
+					}
+					try!(opened_file.seek(SeekFrom::Start(0)));
+				}
 			}
 		}
 
@@ -148,6 +175,18 @@ impl WhisperFile {
             self._write(point, now)
         }
 
+        pub fn aggregation_type(&self) -> AggregationType {
+            self.header.aggregation_type()
+        }
+
+        // Push dirty pages for every archive out to disk
+        pub fn flush(&self) -> Result<()> {
+            for archive in &self.archives {
+                try!(archive.flush());
+            }
+            Ok(())
+        }
+
 	fn _write(&mut self, point: &Point, now: i64) {
             let mut point = point.clone();
             let elapsed = now - point.0 as i64;
@@ -171,31 +210,12 @@ impl WhisperFile {
                   },
 
                   WriteState::Aggregate(last_index) => {
-                      let (points, timestamp, ratio) = {
-                          let seconds_per_point = self.archives[index].seconds_per_point();
-                          let ref last_archive = self.archives[last_index];
-                          let candidate_point_count = cmp::min((seconds_per_point / last_archive.seconds_per_point()) as usize, last_archive.points());
-                          let timestamp = point.0 - (point.0 % seconds_per_point);
-                          let from = archive::BucketName(timestamp);
-                          let mut candidate_points: Vec<Point> = repeat(Point::default()).take(candidate_point_count).collect();
-                          last_archive.read_points(from, &mut candidate_points).unwrap();
-                          let points = candidate_points
-                              .into_iter()
-                              .enumerate()
-                              .filter(|&(i, Point(t, _))| timestamp + (i as u32) * last_archive.seconds_per_point() == t)
-                              .map(|(_, p)| p)
-                              .collect::<Vec<Point>>();
-                          let ratio = points.len() as f32 / candidate_point_count as f32;
-                          (points, timestamp, ratio)
-                      };
-
-                      if ratio >= self.header.x_files_factor() {
-                          point.0 = timestamp;
-                          point.1 = self.header.aggregation_type().aggregate(&points);
-                          self.archives[index].write(&point);
-                          WriteState::Aggregate(index)
-                      } else {
-                          WriteState::Finished
+                      match self.propagate(point.0, last_index, index) {
+                          Some(aggregated) => {
+                              point = aggregated;
+                              WriteState::Aggregate(index)
+                          },
+                          None => WriteState::Finished
                       }
                   },
 
@@ -204,6 +224,99 @@ impl WhisperFile {
             });
 	}
 
+        // Batch version of `write`. Points are bucketed in to the archive
+        // their age falls in and each affected lower resolution interval is
+        // only aggregated once, like whisper.py's update_many.
+        pub fn write_many(&mut self, points: &[Point]) {
+            let now = time::get_time().sec;
+            self._write_many(points, now)
+        }
+
+	fn _write_many(&mut self, points: &[Point], now: i64) {
+            let mut points = points.to_vec();
+            // Newest first, so each archive gets a contiguous run
+            points.sort_by(|a, b| b.0.cmp(&a.0));
+
+            let mut index = 0;
+            let mut archive_points: Vec<Point> = vec![];
+            for point in points {
+                let elapsed = now - point.0 as i64;
+                if elapsed < 0 { continue; }
+
+                while index < self.archives.len() && elapsed as usize >= self.archives[index].retention() {
+                    if !archive_points.is_empty() {
+                        archive_points.reverse();
+                        self.archive_write_many(index, &archive_points);
+                        archive_points.clear();
+                    }
+                    index = index + 1;
+                }
+
+                // Everything left is older than the whole file
+                if index == self.archives.len() { return; }
+                archive_points.push(point);
+            }
+
+            if !archive_points.is_empty() {
+                archive_points.reverse();
+                self.archive_write_many(index, &archive_points);
+            }
+	}
+
+        // `points` must be in chronological order so later duplicates win
+        fn archive_write_many(&mut self, index: usize, points: &[Point]) {
+            for point in points {
+                self.archives[index].write(point);
+            }
+
+            let mut higher = index;
+            for lower in (index + 1)..self.archives.len() {
+                let seconds_per_point = self.archives[lower].seconds_per_point();
+                let mut intervals: Vec<u32> = points.iter().map(|p| p.0 - (p.0 % seconds_per_point)).collect();
+                intervals.dedup();
+
+                let mut propagate_further = false;
+                for interval in intervals {
+                    if self.propagate(interval, higher, lower).is_some() {
+                        propagate_further = true;
+                    }
+                }
+                if !propagate_further { return; }
+                higher = lower;
+            }
+        }
+
+        // Aggregate the points `higher` holds for the `lower` interval containing
+        // `timestamp`. Returns the point written to `lower`, or None if too few
+        // points were known to satisfy the xFilesFactor.
+        fn propagate(&mut self, timestamp: u32, higher: usize, lower: usize) -> Option<Point> {
+            let (points, timestamp, ratio) = {
+                let seconds_per_point = self.archives[lower].seconds_per_point();
+                let ref last_archive = self.archives[higher];
+                let candidate_point_count = cmp::min((seconds_per_point / last_archive.seconds_per_point()) as usize, last_archive.points());
+                let timestamp = timestamp - (timestamp % seconds_per_point);
+                let from = archive::BucketName(timestamp);
+                let mut candidate_points: Vec<Point> = repeat(Point::default()).take(candidate_point_count).collect();
+                last_archive.read_points(from, &mut candidate_points).unwrap();
+                let points = candidate_points
+                    .into_iter()
+                    .enumerate()
+                    .filter(|&(i, Point(t, _))| timestamp + (i as u32) * last_archive.seconds_per_point() == t)
+                    .map(|(_, p)| p)
+                    .collect::<Vec<Point>>();
+                let ratio = points.len() as f32 / candidate_point_count as f32;
+                (points, timestamp, ratio)
+            };
+
+            if ratio >= self.header.x_files_factor() {
+                let point = Point(timestamp, self.header.aggregation_type().aggregate(&points));
+                self.archives[lower].write(&point);
+                Some(point)
+            } else {
+                None
+            }
+        }
+
         #[cfg(test)]
         fn new_transient(schema: &Schema, agg: AggregationType, xff: f32) -> WhisperFile {
             let path = "/dev/null".into();
@@ -245,10 +358,34 @@ impl WhisperFile {
         }
 }
 
+fn truncate(file: &File, size: u32) -> Result<()> {
+	let retval = unsafe { ftruncate(file.as_raw_fd(), size as i64) };
+	if retval != 0 {
+		return Err(io::Error::last_os_error());
+	}
+	Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn fallocate(file: &File, size: u32) -> Result<()> {
+	// posix_fallocate returns the error rather than setting errno
+	let retval = unsafe { posix_fallocate(file.as_raw_fd(), 0, size as i64) };
+	if retval != 0 {
+		return Err(io::Error::from_raw_os_error(retval));
+	}
+	Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn fallocate(_file: &File, _size: u32) -> Result<()> {
+	Err(io::Error::new(io::ErrorKind::Other, "fallocate is not supported on this platform"))
+}
+
 #[cfg(test)]
 mod tests {
 	use whisper::{ Schema, WhisperFile, Point };
 	use super::header;
+	use super::Allocation;
 
 	use std::io::Cursor;
 	use std::io::Write;
@@ -457,6 +594,7 @@ mod tests {
 	#[test]
 	fn test_write() {
 		let path = "/tmp/blah.wsp";
+		let _ = ::std::fs::remove_file(path);
 		let default_specs = vec!["1s:60s".to_string(), "1m:1y".to_string()];
 		let schema = Schema::new_from_retention_specs(default_specs).unwrap();
 
@@ -510,6 +648,85 @@ mod tests {
             assert_eq!(result, sample);
         }
 
+        #[test]
+	fn test_write_many_matches_write() {
+            let specs = vec!["1s:10s".to_string(), "10s:1m".to_string(), "1m:3m".to_string()];
+            let schema = Schema::new_from_retention_specs(specs).unwrap();
+            let now = 1487974970;
+            let points = vec![
+                Point(1487974962, 15.0),
+                Point(1487974954, 1.0),
+                Point(1487974970, 133.0),
+                Point(1487974956, 3.0),
+                Point(1487974959, 9.0),
+                Point(1487974965, 65.0),
+                Point(1487974968, 122.0),
+                Point(1487974700, 7.0), // older than the file
+                Point(1487974800, 8.0), // only fits the last archive
+                Point(1487974980, 7.0)  // from the future
+            ];
+
+            let mut one_by_one = WhisperFile::new_transient(&schema, header::AggregationType::Average, 0.0);
+            let mut sorted = points.clone();
+            sorted.sort_by_key(|p| p.0);
+            for point in &sorted {
+                one_by_one._write(point, now);
+            }
+
+            let mut batched = WhisperFile::new_transient(&schema, header::AggregationType::Average, 0.0);
+            batched._write_many(&points, now);
+
+            // Write order decides where each archive is anchored, so
+            // compare what was stored rather than the raw bytes
+            assert_eq!(stored_points(&batched), stored_points(&one_by_one));
+        }
+
+        fn stored_points(file: &WhisperFile) -> Vec<Vec<Point>> {
+            file.archives.iter().map(|archive| {
+                let mut points = vec![Point::default(); archive.points()];
+                archive.read_points(archive.anchor_bucket_name(), &mut points).unwrap();
+                let mut stored: Vec<Point> = points.into_iter().filter(|p| p.0 != 0).collect();
+                stored.sort_by_key(|p| p.0);
+                stored
+            }).collect()
+        }
+
+        #[test]
+	fn test_last_max_min_aggregation() {
+            let specs = vec!["1s:10s".to_string(), "10s:1m".to_string()];
+            let schema = Schema::new_from_retention_specs(specs).unwrap();
+            for &(aggregation, expected) in [
+                (header::AggregationType::Last, 4.0),
+                (header::AggregationType::Max, 9.0),
+                (header::AggregationType::Min, 1.0)
+            ].iter() {
+                let mut file = WhisperFile::new_transient(&schema, aggregation, 0.0);
+                for &(t, v) in [(1487974960, 3.0), (1487974961, 9.0), (1487974962, 1.0), (1487974963, 4.0)].iter() {
+                    file._write(&Point(t, v), t as i64);
+                }
+                assert_eq!(stored_points(&file)[1], vec![Point(1487974960, expected)], "{}", aggregation);
+            }
+        }
+
+        #[test]
+	fn test_allocations() {
+            let schema = Schema::new_from_retention_specs(vec!["1s:60s".to_string(), "1m:1d".to_string()]).unwrap();
+            for &allocation in [Allocation::Zeroed, Allocation::Sparse, Allocation::Fallocate].iter() {
+                let path = ::std::env::temp_dir().join(format!("whisper-allocation-{:?}.wsp", allocation));
+                let _ = ::std::fs::remove_file(&path);
+
+                let mut file = WhisperFile::new_with_allocation(&path, &schema, header::AggregationType::Max, 0.1, allocation).unwrap();
+                // An existing file is never replaced
+                let existing = WhisperFile::new_with_allocation(&path, &schema, header::AggregationType::Max, 0.1, allocation);
+                assert_eq!(existing.err().map(|e| e.kind()), Some(::std::io::ErrorKind::AlreadyExists));
+                assert_eq!(::std::fs::metadata(&path).unwrap().len(), schema.size_on_disk() as u64);
+                assert_eq!(file.aggregation_type(), header::AggregationType::Max);
+                assert_eq!(stored_points(&file), vec![vec![], vec![]]);
+                file.write(&Point(10, 1.0));
+                ::std::fs::remove_file(&path).unwrap();
+            }
+        }
+
         #[test]
 	fn test_aggregation_matches_py_with_sum() {
             let sample: &[u8] = &SAMPLE_FILE_3;
@@ -0,0 +1,103 @@
+use regex::{ self, Regex };
+
+// One dot separated node of a Graphite query, like `web*`, `{a,b}` or `cpu[0-3]`
+#[derive(Debug)]
+pub enum Glob {
+    Literal(String),
+    Pattern(Regex)
+}
+
+impl Glob {
+    pub fn new(segment: &str) -> Result<Glob, String> {
+        if !has_wildcards(segment) {
+            return Ok(Glob::Literal(segment.to_string()));
+        }
+
+        let mut pattern = String::from("^");
+        let mut chars = segment.chars();
+        let mut braces = 0;
+        while let Some(c) = chars.next() {
+            match c {
+                '*' => pattern.push_str(".*"),
+                '?' => pattern.push('.'),
+                '[' => {
+                    pattern.push('[');
+                    let mut closed = false;
+                    let mut first = true;
+                    while let Some(c) = chars.next() {
+                        match c {
+                            ']' if !first => { closed = true; break; },
+                            '!' | '^' if first => pattern.push('^'),
+                            '\\' | '[' | '&' | '~' => { pattern.push('\\'); pattern.push(c); },
+                            _ => pattern.push(c)
+                        }
+                        first = false;
+                    }
+                    if !closed {
+                        return Err(format!("unclosed `[` in `{}`", segment));
+                    }
+                    pattern.push(']');
+                },
+                '{' => {
+                    braces = braces + 1;
+                    pattern.push_str("(?:");
+                },
+                ',' if braces > 0 => pattern.push('|'),
+                '}' if braces > 0 => {
+                    braces = braces - 1;
+                    pattern.push(')');
+                },
+                _ => pattern.push_str(&regex::escape(&c.to_string()))
+            }
+        }
+        if braces > 0 {
+            return Err(format!("unclosed `{{` in `{}`", segment));
+        }
+        pattern.push('$');
+
+        Regex::new(&pattern).map(Glob::Pattern).map_err(|e| format!("invalid pattern `{}`: {}", segment, e))
+    }
+
+    pub fn matches(&self, node: &str) -> bool {
+        match *self {
+            Glob::Literal(ref literal) => literal == node,
+            // Like graphite, wildcards don't match hidden files
+            Glob::Pattern(ref regex) => !node.starts_with('.') && regex.is_match(node)
+        }
+    }
+}
+
+pub fn has_wildcards(query: &str) -> bool {
+    query.contains(|c| c == '*' || c == '?' || c == '[' || c == '{')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(glob: &str, node: &str) -> bool {
+        Glob::new(glob).unwrap().matches(node)
+    }
+
+    #[test]
+    fn test_wildcards() {
+        assert!(matches("web*", "web01"));
+        assert!(matches("*", "anything"));
+        assert!(!matches("*", ".hidden"));
+        assert!(matches("cpu?", "cpu1"));
+        assert!(!matches("cpu?", "cpu10"));
+        assert!(matches("cpu[0-3]", "cpu2"));
+        assert!(!matches("cpu[0-3]", "cpu4"));
+        assert!(matches("cpu[!0-3]", "cpu4"));
+        assert!(matches("{rx,tx}_bytes", "tx_bytes"));
+        assert!(!matches("{rx,tx}_bytes", "err_bytes"));
+        assert!(matches("a+b(c)", "a+b(c)"));
+        assert!(!matches("a+b(c)*", "aab(c)"));
+    }
+
+    #[test]
+    fn test_bad_globs() {
+        assert!(Glob::new("cpu[0-3").is_err());
+        assert!(Glob::new("{a,b").is_err());
+    }
+}
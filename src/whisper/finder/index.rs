@@ -0,0 +1,245 @@
+use std::collections::BTreeMap;
+use std::fs::{ self, File };
+use std::io::{ self, BufRead, BufReader, BufWriter, Write, ErrorKind };
+use std::mem;
+use std::path::Path;
+use std::sync::{ Arc, RwLock };
+
+use super::{ Glob, MetricNode, join, metric_name };
+
+#[derive(Debug, Default)]
+struct TrieNode {
+    children: BTreeMap<String, TrieNode>,
+    // A metric ends here. It may also have children: `a.b` and `a.b.c`
+    // can both be metrics.
+    is_leaf: bool
+}
+
+// In-memory trie of every metric name under a whisper tree. Cloning hands
+// out another reference to the same index, so the cache creating files and
+// the finder answering queries can share one.
+#[derive(Clone, Default)]
+pub struct MetricIndex {
+    root: Arc<RwLock<TrieNode>>
+}
+
+impl MetricIndex {
+    pub fn new() -> MetricIndex {
+        MetricIndex::default()
+    }
+
+    // Index every `.wsp` file below `base_path`
+    pub fn scan<P>(base_path: P) -> io::Result<MetricIndex> where P: AsRef<Path> {
+        let index = MetricIndex::new();
+        let mut pending = vec![(String::new(), base_path.as_ref().to_path_buf())];
+        while let Some((prefix, dir)) = pending.pop() {
+            for (name, path, is_dir) in try!(super::read_entries(&dir)) {
+                if is_dir {
+                    pending.push((join(&prefix, &name), path));
+                } else if let Some(metric) = metric_name(&name) {
+                    index.insert(&join(&prefix, metric));
+                }
+            }
+        }
+        Ok(index)
+    }
+
+    // Start from `snapshot` when there is one, otherwise scan `base_path`
+    pub fn load_or_scan<P, Q>(snapshot: Option<P>, base_path: Q) -> io::Result<MetricIndex>
+        where P: AsRef<Path>, Q: AsRef<Path> {
+        if let Some(snapshot) = snapshot {
+            match MetricIndex::load(&snapshot) {
+                Ok(index) => {
+                    info!("loaded metric index from {:?}", snapshot.as_ref());
+                    return Ok(index);
+                },
+                Err(ref e) if e.kind() == ErrorKind::NotFound => (),
+                Err(e) => return Err(e)
+            }
+        }
+        info!("scanning {:?} for metrics", base_path.as_ref());
+        MetricIndex::scan(base_path)
+    }
+
+    // Replace the whole index with a fresh scan of `base_path`, picking up
+    // files some other process created
+    pub fn rescan<P>(&self, base_path: P) -> io::Result<()> where P: AsRef<Path> {
+        let fresh = try!(MetricIndex::scan(base_path));
+        let trie = mem::replace(&mut *fresh.root.write().unwrap(), TrieNode::default());
+        *self.root.write().unwrap() = trie;
+        Ok(())
+    }
+
+    // Load a snapshot written by `save`: one metric name per line
+    pub fn load<P>(snapshot: P) -> io::Result<MetricIndex> where P: AsRef<Path> {
+        let index = MetricIndex::new();
+        let reader = BufReader::new(try!(File::open(snapshot)));
+        for line in reader.lines() {
+            let line = try!(line);
+            if !line.is_empty() {
+                index.insert(&line);
+            }
+        }
+        Ok(index)
+    }
+
+    // Write every metric name to `snapshot`. The snapshot is replaced in one
+    // rename so a crash never leaves half of one behind.
+    pub fn save<P>(&self, snapshot: P) -> io::Result<()> where P: AsRef<Path> {
+        let snapshot = snapshot.as_ref();
+        let partial = snapshot.with_extension("partial");
+        {
+            let mut writer = BufWriter::new(try!(File::create(&partial)));
+            for metric in self.metrics() {
+                try!(writeln!(writer, "{}", metric));
+            }
+            try!(writer.flush());
+            try!(writer.get_ref().sync_all());
+        }
+        fs::rename(&partial, snapshot)
+    }
+
+    pub fn insert(&self, metric_name: &str) {
+        let mut root = self.root.write().unwrap();
+        let node = metric_name.split('.').fold(&mut *root, |node, name| {
+            node.children.entry(name.to_string()).or_insert_with(TrieNode::default)
+        });
+        node.is_leaf = true;
+    }
+
+    pub fn contains(&self, metric_name: &str) -> bool {
+        let root = self.root.read().unwrap();
+        let mut node = &*root;
+        for name in metric_name.split('.') {
+            match node.children.get(name) {
+                Some(child) => node = child,
+                None => return false
+            }
+        }
+        node.is_leaf
+    }
+
+    // Every metric name, sorted
+    pub fn metrics(&self) -> Vec<String> {
+        let root = self.root.read().unwrap();
+        let mut metrics = vec![];
+        let mut pending = vec![(String::new(), &*root)];
+        while let Some((path, node)) = pending.pop() {
+            if node.is_leaf {
+                metrics.push(path.clone());
+            }
+            for (name, child) in node.children.iter() {
+                pending.push((join(&path, name), child));
+            }
+        }
+        metrics.sort();
+        metrics
+    }
+
+    pub fn find(&self, globs: &[Glob]) -> Vec<MetricNode> {
+        let root = self.root.read().unwrap();
+        let mut branches: Vec<(String, &TrieNode)> = vec![(String::new(), &*root)];
+        let (last, parents) = match globs.split_last() {
+            Some(split) => split,
+            None => return vec![]
+        };
+
+        for glob in parents {
+            let mut matched = vec![];
+            for (prefix, node) in branches {
+                matched.extend(matching_children(node, glob).filter(|&(_, child)| !child.children.is_empty())
+                    .map(|(name, child)| (join(&prefix, name), child)));
+            }
+            branches = matched;
+        }
+
+        let mut nodes = vec![];
+        for (prefix, node) in branches {
+            for (name, child) in matching_children(node, last) {
+                if !child.children.is_empty() {
+                    nodes.push(MetricNode { path: join(&prefix, name), is_leaf: false });
+                }
+                if child.is_leaf {
+                    nodes.push(MetricNode { path: join(&prefix, name), is_leaf: true });
+                }
+            }
+        }
+        nodes.sort();
+        nodes
+    }
+}
+
+// Literal nodes are looked up directly rather than compared against every child
+fn matching_children<'a>(node: &'a TrieNode, glob: &'a Glob) -> Box<dyn Iterator<Item=(&'a str, &'a TrieNode)> + 'a> {
+    match *glob {
+        Glob::Literal(ref literal) => Box::new(node.children.get_key_value(literal.as_str()).into_iter()
+            .map(|(name, child)| (name.as_str(), child))),
+        Glob::Pattern(_) => Box::new(node.children.iter()
+            .filter(move |&(name, _)| glob.matches(name))
+            .map(|(name, child)| (name.as_str(), child)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use whisper::finder::Glob;
+    use std::env;
+
+    fn globs(query: &str) -> Vec<Glob> {
+        query.split('.').map(|segment| Glob::new(segment).unwrap()).collect()
+    }
+
+    fn paths(nodes: Vec<MetricNode>) -> Vec<(String, bool)> {
+        nodes.into_iter().map(|node| (node.path, node.is_leaf)).collect()
+    }
+
+    fn sample_index() -> MetricIndex {
+        let index = MetricIndex::new();
+        for metric in ["servers.web01.load", "servers.web02.load", "servers.web01.cpu.user", "servers.web01.cpu"].iter() {
+            index.insert(metric);
+        }
+        index
+    }
+
+    #[test]
+    fn test_find() {
+        let index = sample_index();
+        assert_eq!(paths(index.find(&globs("*"))), vec![("servers".to_string(), false)]);
+        assert_eq!(paths(index.find(&globs("servers.web01.*"))), vec![
+            ("servers.web01.cpu".to_string(), false),
+            ("servers.web01.cpu".to_string(), true),
+            ("servers.web01.load".to_string(), true)
+        ]);
+        assert_eq!(paths(index.find(&globs("servers.web0[2].load"))), vec![("servers.web02.load".to_string(), true)]);
+        assert_eq!(index.find(&globs("servers.db*")), vec![]);
+        assert!(index.contains("servers.web01.cpu"));
+        assert!(!index.contains("servers.web01"));
+    }
+
+    #[test]
+    fn test_scan() {
+        let base = env::temp_dir().join("whisper-index-scan-test");
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(base.join("servers/web01/cpu")).unwrap();
+        for file in ["servers/web01/load.wsp", "servers/web01/cpu/user.wsp", "servers/web01/notes.txt"].iter() {
+            File::create(base.join(file)).unwrap();
+        }
+
+        let index = MetricIndex::scan(&base).unwrap();
+        assert_eq!(index.metrics(), vec!["servers.web01.cpu.user", "servers.web01.load"]);
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let snapshot = env::temp_dir().join("whisper-index-test.snapshot");
+        let index = sample_index();
+        index.save(&snapshot).unwrap();
+
+        let loaded = MetricIndex::load(&snapshot).unwrap();
+        assert_eq!(loaded.metrics(), index.metrics());
+        assert_eq!(loaded.metrics()[0], "servers.web01.cpu");
+        fs::remove_file(&snapshot).unwrap();
+    }
+}
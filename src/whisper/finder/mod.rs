@@ -0,0 +1,183 @@
+// Maps Graphite queries back on to the whisper tree, the inverse of
+// `NamedPoint::rel_path`: `servers.*.load` finds `servers/web01/load.wsp`.
+
+mod glob;
+mod index;
+
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+
+pub use self::glob::{ Glob, has_wildcards };
+pub use self::index::MetricIndex;
+
+const WHISPER_EXTENSION : &'static str = "wsp";
+
+// A metric (leaf) or a directory of metrics (branch)
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct MetricNode {
+    pub path: String,
+    pub is_leaf: bool
+}
+
+impl MetricNode {
+    // The last node of the path, what graphite calls the name
+    pub fn name(&self) -> &str {
+        self.path.rsplit('.').next().unwrap_or(&self.path)
+    }
+}
+
+enum Source {
+    // Read the directories on every query
+    Tree(PathBuf),
+    Index(MetricIndex)
+}
+
+pub struct MetricFinder {
+    source: Source
+}
+
+impl MetricFinder {
+    pub fn new<P>(base_path: P) -> MetricFinder where P: Into<PathBuf> {
+        MetricFinder { source: Source::Tree(base_path.into()) }
+    }
+
+    pub fn with_index(index: MetricIndex) -> MetricFinder {
+        MetricFinder { source: Source::Index(index) }
+    }
+
+    // Every branch and leaf matching `query`, sorted by path
+    pub fn find(&self, query: &str) -> io::Result<Vec<MetricNode>> {
+        let globs = try!(query.split('.').map(Glob::new).collect::<Result<Vec<Glob>, String>>()
+            .map_err(|why| io::Error::new(io::ErrorKind::InvalidInput, why)));
+
+        match self.source {
+            Source::Tree(ref base_path) => find_in_tree(base_path, &globs),
+            Source::Index(ref index) => Ok(index.find(&globs))
+        }
+    }
+}
+
+fn find_in_tree(base_path: &Path, globs: &[Glob]) -> io::Result<Vec<MetricNode>> {
+    // Walk the tree a node at a time, keeping only the directories that match
+    let mut branches: Vec<(String, PathBuf)> = vec![(String::new(), base_path.to_path_buf())];
+    let (last, parents) = globs.split_last().unwrap();
+    for glob in parents {
+        let mut matched = vec![];
+        for (prefix, dir) in branches {
+            for (name, path, is_dir) in try!(read_entries(&dir)) {
+                if is_dir && glob.matches(&name) {
+                    matched.push((join(&prefix, &name), path));
+                }
+            }
+        }
+        branches = matched;
+    }
+
+    let mut nodes = vec![];
+    for (prefix, dir) in branches {
+        for (name, _, is_dir) in try!(read_entries(&dir)) {
+            if is_dir {
+                if last.matches(&name) {
+                    nodes.push(MetricNode { path: join(&prefix, &name), is_leaf: false });
+                }
+            } else if let Some(metric) = metric_name(&name) {
+                if last.matches(metric) {
+                    nodes.push(MetricNode { path: join(&prefix, metric), is_leaf: true });
+                }
+            }
+        }
+    }
+    nodes.sort();
+    Ok(nodes)
+}
+
+// (file name, path, is a directory) for everything in `dir`. A directory
+// which has gone away, or was never there, just has nothing in it.
+fn read_entries(dir: &Path) -> io::Result<Vec<(String, PathBuf, bool)>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e)
+    };
+
+    let mut found = vec![];
+    for entry in entries {
+        let entry = try!(entry);
+        let file_type = try!(entry.file_type());
+        // Only utf8 names can be metric names
+        if let Ok(name) = entry.file_name().into_string() {
+            // Follow symlinks the way a path lookup would
+            let is_dir = file_type.is_dir() || (file_type.is_symlink() && entry.path().is_dir());
+            found.push((name, entry.path(), is_dir));
+        }
+    }
+    Ok(found)
+}
+
+fn metric_name(file_name: &str) -> Option<&str> {
+    let path = Path::new(file_name);
+    match path.extension() {
+        Some(extension) if extension == WHISPER_EXTENSION => path.file_stem().and_then(|stem| stem.to_str()),
+        _ => None
+    }
+}
+
+fn join(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", prefix, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::{ self, File };
+
+    fn sample_tree(name: &str) -> PathBuf {
+        let base = env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&base);
+        for dir in ["servers/web01/cpu", "servers/web02", "servers/db01"].iter() {
+            fs::create_dir_all(base.join(dir)).unwrap();
+        }
+        for file in ["servers/web01/load.wsp", "servers/web02/load.wsp", "servers/db01/load.wsp",
+                     "servers/web01/cpu/user.wsp", "servers/web01/notes.txt"].iter() {
+            File::create(base.join(file)).unwrap();
+        }
+        base
+    }
+
+    fn paths(nodes: Vec<MetricNode>) -> Vec<(String, bool)> {
+        nodes.into_iter().map(|node| (node.path, node.is_leaf)).collect()
+    }
+
+    #[test]
+    fn test_find() {
+        let base = sample_tree("whisper-finder-test");
+        let finder = MetricFinder::new(&base);
+
+        assert_eq!(paths(finder.find("*").unwrap()), vec![("servers".to_string(), false)]);
+        assert_eq!(paths(finder.find("servers.web*.load").unwrap()), vec![
+            ("servers.web01.load".to_string(), true),
+            ("servers.web02.load".to_string(), true)
+        ]);
+        assert_eq!(paths(finder.find("servers.web01.*").unwrap()), vec![
+            ("servers.web01.cpu".to_string(), false),
+            ("servers.web01.load".to_string(), true)
+        ]);
+        assert_eq!(paths(finder.find("servers.{db,web}0[1].load").unwrap()), vec![
+            ("servers.db01.load".to_string(), true),
+            ("servers.web01.load".to_string(), true)
+        ]);
+        assert_eq!(paths(finder.find("servers.web0?.cpu.user").unwrap()), vec![
+            ("servers.web01.cpu.user".to_string(), true)
+        ]);
+        assert_eq!(finder.find("nothing.*").unwrap(), vec![]);
+        assert_eq!(finder.find("servers.web01.load").unwrap()[0].name(), "load");
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,228 @@
+// Reads `(timestamp, value)` records, either exported from other systems
+// for loading history in to a whisper file or given to `whisper update`.
+// Gaps (empty or null values) are skipped rather than written.
+
+use rustc_serialize::json::Json;
+
+use std::str::FromStr;
+
+use whisper::Point;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Format {
+    // `timestamp,value` lines, optionally under a header line
+    Csv,
+    // `[[timestamp, value], ...]`, `whisper fetch --json` output or a
+    // graphite `/render?format=json` response
+    Json,
+    // carbon plaintext `metric value timestamp` lines. The name is ignored.
+    Carbon
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Format, String> {
+        match name {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "carbon" => Ok(Format::Carbon),
+            _ => Err(format!("unknown import format `{}`, expected csv, json or carbon", name))
+        }
+    }
+}
+
+pub fn parse_points(format: Format, input: &str) -> Result<Vec<Point>, String> {
+    match format {
+        Format::Csv => parse_csv(input),
+        Format::Json => parse_json(input),
+        Format::Carbon => parse_carbon(input)
+    }
+}
+
+fn parse_csv(input: &str) -> Result<Vec<Point>, String> {
+    let mut points = vec![];
+    for (index, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split(',').map(|field| field.trim()).collect();
+        if fields.len() != 2 {
+            return Err(format!("line {}: expected `timestamp,value`, got `{}`", index + 1, line));
+        }
+        if index == 0 && fields[0].parse::<f64>().is_err() {
+            // A header
+            continue;
+        }
+
+        let timestamp = try!(parse_timestamp(fields[0]).map_err(|why| format!("line {}: {}", index + 1, why)));
+        match fields[1] {
+            "" | "None" | "null" => (),
+            value => {
+                let value = try!(value.parse::<f64>().map_err(|_| format!("line {}: value `{}` is not a number", index + 1, value)));
+                points.push(Point(timestamp, value));
+            }
+        }
+    }
+    Ok(points)
+}
+
+fn parse_json(input: &str) -> Result<Vec<Point>, String> {
+    let json = try!(Json::from_str(input).map_err(|e| format!("invalid json: {}", e)));
+
+    match json {
+        // whisper fetch --json
+        Json::Object(ref object) => {
+            let field = |name: &str| object.get(name).and_then(|value| value.as_u64())
+                .ok_or_else(|| format!("json object is missing a numeric `{}`", name));
+            let start = try!(field("start"));
+            let step = try!(field("step"));
+            let values = try!(object.get("values").and_then(|values| values.as_array())
+                .ok_or_else(|| "json object is missing a `values` array".to_string()));
+
+            let mut points = vec![];
+            for (index, value) in values.iter().enumerate() {
+                if let Some(value) = try!(json_value(value)) {
+                    points.push(Point((start + index as u64 * step) as u32, value));
+                }
+            }
+            Ok(points)
+        },
+        Json::Array(ref entries) => {
+            let mut points = vec![];
+            for entry in entries {
+                match *entry {
+                    // [timestamp, value]
+                    Json::Array(ref pair) if pair.len() == 2 => {
+                        if let Some(value) = try!(json_value(&pair[1])) {
+                            points.push(Point(try!(json_timestamp(&pair[0])), value));
+                        }
+                    },
+                    // graphite's {"target": ..., "datapoints": [[value, timestamp], ...]}
+                    Json::Object(ref series) => {
+                        let datapoints = try!(series.get("datapoints").and_then(|datapoints| datapoints.as_array())
+                            .ok_or_else(|| "json series is missing a `datapoints` array".to_string()));
+                        for datapoint in datapoints {
+                            match datapoint.as_array() {
+                                Some(pair) if pair.len() == 2 => {
+                                    if let Some(value) = try!(json_value(&pair[0])) {
+                                        points.push(Point(try!(json_timestamp(&pair[1])), value));
+                                    }
+                                },
+                                _ => return Err(format!("json datapoint `{}` is not a [value, timestamp] pair", datapoint))
+                            }
+                        }
+                    },
+                    _ => return Err(format!("json entry `{}` is not a [timestamp, value] pair", entry))
+                }
+            }
+            Ok(points)
+        },
+        _ => Err("json must be an array of points or a `whisper fetch --json` object".to_string())
+    }
+}
+
+fn parse_carbon(input: &str) -> Result<Vec<Point>, String> {
+    input.lines().enumerate()
+        .filter(|&(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| parse_carbon_line(line).map_err(|why| format!("line {}: {}", index + 1, why)))
+        .collect()
+}
+
+// Unlike the receiver, which writes a bad value as zero, an import stops at it
+fn parse_carbon_line(line: &str) -> Result<Point, String> {
+    let parts : Vec<&str> = line.split_whitespace().collect();
+    if parts.len() != 3 {
+        return Err(format!("expected `metric value timestamp`, got `{}`", line.trim()));
+    }
+    let value = try!(parts[1].parse::<f64>().map_err(|_| format!("value `{}` is not a number", parts[1])));
+    Ok(Point(try!(parse_timestamp(parts[2])), value))
+}
+
+// whisper-update.py's `timestamp:value`, or `timestamp value` as read from
+// stdin. A timestamp of `N` means now.
+pub fn parse_update(update: &str, now: u32) -> Result<Point, String> {
+    let update = update.trim();
+    let split = try!(update.find(|c: char| c == ':' || c.is_whitespace())
+        .ok_or_else(|| format!("expected `timestamp:value`, got `{}`", update)));
+    let (timestamp, value) = (&update[..split], update[split + 1 ..].trim());
+
+    let timestamp = if timestamp == "N" { now } else { try!(parse_timestamp(timestamp)) };
+    let value = try!(value.parse::<f64>().map_err(|_| format!("value `{}` is not a number", value)));
+    Ok(Point(timestamp, value))
+}
+
+// One update per line. Blank lines are skipped.
+pub fn parse_updates(input: &str, now: u32) -> Result<Vec<Point>, String> {
+    input.lines().enumerate()
+        .filter(|&(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| parse_update(line, now).map_err(|why| format!("line {}: {}", index + 1, why)))
+        .collect()
+}
+
+// Whole seconds, though exports often write them as floats
+fn parse_timestamp(timestamp: &str) -> Result<u32, String> {
+    match timestamp.parse::<f64>() {
+        Ok(seconds) if seconds >= 0.0 && seconds <= ::std::u32::MAX as f64 => Ok(seconds as u32),
+        _ => Err(format!("timestamp `{}` is not an epoch time", timestamp))
+    }
+}
+
+fn json_timestamp(timestamp: &Json) -> Result<u32, String> {
+    match timestamp.as_f64() {
+        Some(seconds) if seconds >= 0.0 && seconds <= ::std::u32::MAX as f64 => Ok(seconds as u32),
+        _ => Err(format!("json timestamp `{}` is not an epoch time", timestamp))
+    }
+}
+
+fn json_value(value: &Json) -> Result<Option<f64>, String> {
+    match *value {
+        Json::Null => Ok(None),
+        ref value => value.as_f64().map(Some).ok_or_else(|| format!("json value `{}` is not a number", value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv() {
+        let csv = "timestamp,value\n1487974954,1.5\n1487974964.0, \n\n1487974974,-2\n";
+        assert_eq!(parse_points(Format::Csv, csv), Ok(vec![Point(1487974954, 1.5), Point(1487974974, -2.0)]));
+        assert_eq!(parse_points(Format::Csv, "1,2\n3,x\n"), Err("line 2: value `x` is not a number".to_string()));
+        assert_eq!(parse_points(Format::Csv, "1,2,3\n"), Err("line 1: expected `timestamp,value`, got `1,2,3`".to_string()));
+    }
+
+    #[test]
+    fn test_json_shapes() {
+        let expected = Ok(vec![Point(60, 1.0), Point(180, 3.0)]);
+        assert_eq!(parse_points(Format::Json, "[[60, 1], [120, null], [180, 3.0]]"), expected);
+        assert_eq!(parse_points(Format::Json, r#"{"start": 60, "end": 240, "step": 60, "values": [1.0, null, 3.0]}"#), expected);
+        assert_eq!(parse_points(Format::Json, r#"[{"target": "a.b", "datapoints": [[1, 60], [null, 120], [3, 180]]}]"#), expected);
+        assert!(parse_points(Format::Json, "[[60, \"one\"]]").is_err());
+        assert!(parse_points(Format::Json, "42").is_err());
+    }
+
+    #[test]
+    fn test_updates() {
+        assert_eq!(parse_update("1487974954:1.5", 0), Ok(Point(1487974954, 1.5)));
+        assert_eq!(parse_update("N:2", 1487974954), Ok(Point(1487974954, 2.0)));
+        assert_eq!(parse_update("1487974954.7 -3", 0), Ok(Point(1487974954, -3.0)));
+        assert!(parse_update("1487974954", 0).is_err());
+
+        let input = "1487974954:1\n\n1487974964\t2\n";
+        assert_eq!(parse_updates(input, 0), Ok(vec![Point(1487974954, 1.0), Point(1487974964, 2.0)]));
+        assert_eq!(parse_updates("1:1\n2:two\n", 0), Err("line 2: value `two` is not a number".to_string()));
+    }
+
+    #[test]
+    fn test_carbon() {
+        let carbon = "a.b 1.5 1487974954\n\na.b 2 1487974964\n";
+        assert_eq!(parse_points(Format::Carbon, carbon), Ok(vec![Point(1487974954, 1.5), Point(1487974964, 2.0)]));
+        assert!(parse_points(Format::Carbon, "a.b 1.5\n").unwrap_err().starts_with("line 1: "));
+        assert_eq!(parse_points(Format::Carbon, "a.b 1 60\na.b x 123\n"), Err("line 2: value `x` is not a number".to_string()));
+    }
+}
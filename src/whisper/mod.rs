@@ -1,10 +1,20 @@
+#[macro_use]
+mod args;
 mod file;
 mod point;
 mod schema;
 mod cache;
+pub mod carbon;
+pub mod statsd;
+pub mod collectd;
+pub mod web;
+pub mod finder;
+pub mod query;
 pub mod errors;
+pub mod import;
+pub mod export;
 
-pub use self::file::{WhisperFile, AggregationType};
+pub use self::file::{WhisperFile, AggregationType, Allocation, SeriesData, FileInfo, ArchiveInfo};
 pub use self::point::{Point, POINT_SIZE};
-pub use self::schema::Schema;
-pub use self::cache::{ WhisperCache, NamedPoint };
+pub use self::schema::{Schema, SchemaRegistry, SchemaRule};
+pub use self::cache::{ WhisperCache, NamedPoint, WriteBehindCache, WriteBehindConfig, DrainStrategy, WriteAheadLog, InfluxTemplate, TagOrder };
@@ -0,0 +1,237 @@
+// Functions which turn several series in to one
+
+use super::{ Args, Arg, format_path_expressions, format_number };
+use whisper::query::series::{ TimeSeries, normalize };
+
+// Apply `combine` to the values at each timestamp, gaps included
+fn combine<F>(function: &str, series: Vec<TimeSeries>, combine: F) -> Vec<TimeSeries>
+    where F: Fn(&[Option<f64>]) -> Option<f64> {
+    let (start, step, values) = match normalize(&series) {
+        Some(normalized) => normalized,
+        None => return vec![]
+    };
+
+    let len = values[0].len();
+    let combined = (0..len).map(|i| {
+        let row: Vec<Option<f64>> = values.iter().map(|values| values[i]).collect();
+        combine(&row)
+    }).collect();
+
+    let name = format!("{}({})", function, format_path_expressions(&series));
+    let mut result = TimeSeries::new(name, start, step, combined);
+    result.consolidation = series[0].consolidation;
+    vec![result]
+}
+
+fn present(row: &[Option<f64>]) -> Vec<f64> {
+    row.iter().filter_map(|value| *value).collect()
+}
+
+fn safe_sum(row: &[Option<f64>]) -> Option<f64> {
+    let values = present(row);
+    if values.is_empty() { None } else { Some(values.iter().sum()) }
+}
+
+pub fn sum_series(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.all_series(0));
+    Ok(combine("sumSeries", series, safe_sum))
+}
+
+pub fn average_series(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.all_series(0));
+    Ok(combine("averageSeries", series, |row| {
+        let values = present(row);
+        if values.is_empty() { None } else { Some(values.iter().sum::<f64>() / values.len() as f64) }
+    }))
+}
+
+pub fn max_series(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.all_series(0));
+    Ok(combine("maxSeries", series, |row| {
+        present(row).into_iter().fold(None, |max, value| Some(max.map_or(value, |max: f64| max.max(value))))
+    }))
+}
+
+pub fn min_series(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.all_series(0));
+    Ok(combine("minSeries", series, |row| {
+        present(row).into_iter().fold(None, |min, value| Some(min.map_or(value, |min: f64| min.min(value))))
+    }))
+}
+
+// Any gap makes the product a gap
+pub fn multiply_series(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.all_series(0));
+    Ok(combine("multiplySeries", series, |row| {
+        row.iter().fold(Some(1.0), |product, value| product.and_then(|product| value.map(|value| product * value)))
+    }))
+}
+
+// The first value present minus all of the others
+pub fn diff_series(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.all_series(0));
+    Ok(combine("diffSeries", series, |row| {
+        let values = present(row);
+        values.split_first().map(|(first, rest)| rest.iter().fold(*first, |diff, value| diff - value))
+    }))
+}
+
+fn safe_div(dividend: Option<f64>, divisor: Option<f64>) -> Option<f64> {
+    match (dividend, divisor) {
+        (Some(dividend), Some(divisor)) if divisor != 0.0 => Some(dividend / divisor),
+        _ => None
+    }
+}
+
+// Each value of `first` divided by the value of `second` at the same time
+fn divide_pair(name: String, first: &TimeSeries, second: &TimeSeries, scale: f64) -> TimeSeries {
+    let pair = [first.clone(), second.clone()];
+    let (start, step, values) = normalize(&pair).unwrap();
+    let divided = values[0].iter().zip(values[1].iter())
+        .map(|(dividend, divisor)| safe_div(*dividend, *divisor).map(|quotient| quotient * scale))
+        .collect();
+
+    let mut result = TimeSeries::new(name, start, step, divided);
+    result.consolidation = first.consolidation;
+    result
+}
+
+pub fn divide_series(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let dividends = try!(args.series(0, "dividendSeriesList"));
+    let mut divisors = try!(args.series(1, "divisorSeries"));
+    if divisors.len() != 1 {
+        return Err(format!("divideSeries: divisorSeries must be exactly one series, got {}", divisors.len()));
+    }
+    let divisor = divisors.remove(0);
+
+    Ok(dividends.iter().map(|dividend| {
+        let name = format!("divideSeries({},{})", dividend.name, divisor.name);
+        divide_pair(name, dividend, &divisor, 1.0)
+    }).collect())
+}
+
+// Each series as a percentage of `total`: a number, one series, a series
+// for each series, or by default the sum of them all
+pub fn as_percent(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.series(0, "seriesList"));
+    match args.optional_arg(1, "total") {
+        None => {
+            let total = match combine("sumSeries", series.clone(), safe_sum).pop() {
+                Some(total) => total,
+                None => return Ok(vec![])
+            };
+            Ok(series.iter().map(|series| {
+                divide_pair(format!("asPercent({})", series.name), series, &total, 100.0)
+            }).collect())
+        },
+        Some(Arg::Number(total)) => Ok(series.iter().map(|series| {
+            let values = series.values.iter().map(|value| safe_div(*value, Some(total)).map(|ratio| ratio * 100.0)).collect();
+            series.derive(format!("asPercent({},{})", series.name, format_number(total)), values)
+        }).collect()),
+        Some(Arg::SeriesList(totals)) => {
+            if totals.len() != 1 && totals.len() != series.len() {
+                return Err(format!("asPercent: total must be one series or as many series as seriesList, got {}", totals.len()));
+            }
+            Ok(series.iter().enumerate().map(|(i, series)| {
+                let total = if totals.len() == 1 { &totals[0] } else { &totals[i] };
+                divide_pair(format!("asPercent({},{})", series.name, total.name), series, total, 100.0)
+            }).collect())
+        },
+        Some(_) => Err("asPercent: argument `total` must be a number or a series list".to_string())
+    }
+}
+
+// How many series there are, at every timestamp
+pub fn count_series(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.all_series(0));
+    let count = series.len() as f64;
+    Ok(combine("countSeries", series, |_| Some(count)))
+}
+
+pub fn group(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    args.all_series(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use whisper::query::functions::{ call, Args, Arg };
+    use whisper::query::series::TimeSeries;
+
+    fn series(name: &str, start: u32, step: u32, values: Vec<Option<f64>>) -> TimeSeries {
+        TimeSeries::new(name.to_string(), start, step, values)
+    }
+
+    fn apply(function: &str, args: Vec<Arg>) -> Result<Vec<TimeSeries>, String> {
+        call(Args::new(function.to_string(), args, vec![]))
+    }
+
+    fn sample() -> Arg {
+        Arg::SeriesList(vec![
+            series("a.x", 60, 60, vec![Some(1.0), None, Some(3.0), None]),
+            series("a.y", 60, 60, vec![Some(4.0), Some(2.0), None, None])
+        ])
+    }
+
+    fn values(function: &str) -> Vec<Option<f64>> {
+        apply(function, vec![sample()]).unwrap().remove(0).values
+    }
+
+    #[test]
+    fn test_simple_combinations() {
+        assert_eq!(values("sumSeries"), vec![Some(5.0), Some(2.0), Some(3.0), None]);
+        assert_eq!(values("averageSeries"), vec![Some(2.5), Some(2.0), Some(3.0), None]);
+        assert_eq!(values("maxSeries"), vec![Some(4.0), Some(2.0), Some(3.0), None]);
+        assert_eq!(values("minSeries"), vec![Some(1.0), Some(2.0), Some(3.0), None]);
+        assert_eq!(values("multiplySeries"), vec![Some(4.0), None, None, None]);
+        assert_eq!(values("diffSeries"), vec![Some(-3.0), Some(2.0), Some(3.0), None]);
+        assert_eq!(values("countSeries"), vec![Some(2.0); 4]);
+        assert_eq!(apply("group", vec![sample(), sample()]).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_combined_name() {
+        let mut listed = match sample() { Arg::SeriesList(series) => series, _ => unreachable!() };
+        for series in listed.iter_mut() {
+            series.path_expression = "a.*".to_string();
+        }
+        let combined = apply("sumSeries", vec![Arg::SeriesList(listed), Arg::SeriesList(vec![series("b", 60, 60, vec![])])]).unwrap();
+        assert_eq!(combined[0].name, "sumSeries(a.*,b)");
+    }
+
+    #[test]
+    fn test_combine_mixed_steps() {
+        let fine = series("fine", 60, 60, vec![Some(1.0), Some(2.0), Some(3.0), Some(4.0)]);
+        let coarse = series("coarse", 120, 120, vec![Some(10.0), Some(20.0)]);
+        let summed = apply("sumSeries", vec![Arg::SeriesList(vec![fine, coarse])]).unwrap().remove(0);
+        assert_eq!((summed.start, summed.step), (0, 120));
+        assert_eq!(summed.values, vec![Some(1.0), Some(12.5), Some(24.0)]);
+    }
+
+    #[test]
+    fn test_divide_series() {
+        let divisor = Arg::SeriesList(vec![series("total", 60, 60, vec![Some(2.0), Some(0.0), Some(4.0), Some(1.0)])]);
+        let divided = apply("divideSeries", vec![sample(), divisor]).unwrap();
+        assert_eq!(divided[0].name, "divideSeries(a.x,total)");
+        assert_eq!(divided[0].values, vec![Some(0.5), None, Some(0.75), None]);
+        assert_eq!(divided[1].values, vec![Some(2.0), None, None, None]);
+
+        assert!(apply("divideSeries", vec![sample(), sample()]).is_err());
+    }
+
+    #[test]
+    fn test_as_percent() {
+        let percent = apply("asPercent", vec![sample()]).unwrap();
+        assert_eq!(percent[0].name, "asPercent(a.x)");
+        assert_eq!(percent[0].values, vec![Some(20.0), None, Some(100.0), None]);
+        assert_eq!(percent[1].values, vec![Some(80.0), Some(100.0), None, None]);
+
+        let percent = apply("asPercent", vec![sample(), Arg::Number(8.0)]).unwrap();
+        assert_eq!(percent[1].name, "asPercent(a.y,8)");
+        assert_eq!(percent[1].values, vec![Some(50.0), Some(25.0), None, None]);
+    }
+
+    #[test]
+    fn test_unknown_function() {
+        assert_eq!(apply("noSuchFunction", vec![]), Err("unknown function `noSuchFunction`".to_string()));
+    }
+}
@@ -0,0 +1,226 @@
+// Functions which pick and order series from a list
+
+use regex::Regex;
+use std::cmp::Ordering;
+use std::f64::NEG_INFINITY;
+
+use super::{ Args, format_number };
+use whisper::query::series::TimeSeries;
+
+fn present(series: &TimeSeries) -> Vec<f64> {
+    series.values.iter().filter_map(|value| *value).collect()
+}
+
+fn current(series: &TimeSeries) -> Option<f64> {
+    series.values.iter().rev().filter_map(|value| *value).next()
+}
+
+fn maximum(series: &TimeSeries) -> Option<f64> {
+    present(series).into_iter().fold(None, |max, value| Some(max.map_or(value, |max: f64| max.max(value))))
+}
+
+fn average(series: &TimeSeries) -> Option<f64> {
+    let values = present(series);
+    if values.is_empty() { None } else { Some(values.iter().sum::<f64>() / values.len() as f64) }
+}
+
+// Sort by `key`, series with nothing to go on sorting lowest
+fn sort_by_key(series: &mut Vec<TimeSeries>, key: fn(&TimeSeries) -> Option<f64>, descending: bool) {
+    series.sort_by(|a, b| {
+        let a = key(a).unwrap_or(NEG_INFINITY);
+        let b = key(b).unwrap_or(NEG_INFINITY);
+        let ordering = a.partial_cmp(&b).unwrap_or(Ordering::Equal);
+        if descending { ordering.reverse() } else { ordering }
+    });
+}
+
+fn top(args: &mut Args, key: fn(&TimeSeries) -> Option<f64>, descending: bool) -> Result<Vec<TimeSeries>, String> {
+    let mut series = try!(args.series(0, "seriesList"));
+    let n = try!(args.optional_number(1, "n")).unwrap_or(1.0);
+    if n < 0.0 {
+        return Err(format!("{}: argument `n` must not be negative", args.function));
+    }
+    sort_by_key(&mut series, key, descending);
+    series.truncate(n as usize);
+    Ok(series)
+}
+
+pub fn highest_current(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    top(args, current, true)
+}
+
+pub fn highest_max(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    top(args, maximum, true)
+}
+
+pub fn highest_average(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    top(args, average, true)
+}
+
+pub fn lowest_current(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    top(args, current, false)
+}
+
+pub fn limit(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let mut series = try!(args.series(0, "seriesList"));
+    let n = try!(args.number(1, "n"));
+    series.truncate(n.max(0.0) as usize);
+    Ok(series)
+}
+
+fn filter_names(args: &mut Args, keep_matches: bool) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.series(0, "seriesList"));
+    let pattern = try!(args.string(1, "pattern"));
+    let regex = try!(Regex::new(&pattern).map_err(|e| format!("{}: invalid pattern `{}`: {}", args.function, pattern, e)));
+    Ok(series.into_iter().filter(|series| regex.is_match(&series.name) == keep_matches).collect())
+}
+
+pub fn exclude(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    filter_names(args, false)
+}
+
+pub fn grep(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    filter_names(args, true)
+}
+
+pub fn sort_by_maxima(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let mut series = try!(args.series(0, "seriesList"));
+    sort_by_key(&mut series, maximum, true);
+    Ok(series)
+}
+
+pub fn sort_by_name(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let mut series = try!(args.series(0, "seriesList"));
+    let natural = try!(args.optional_boolean(1, "natural")).unwrap_or(false);
+    let reverse = try!(args.optional_boolean(2, "reverse")).unwrap_or(false);
+
+    if natural {
+        series.sort_by(|a, b| natural_key(&a.name).cmp(&natural_key(&b.name)));
+    } else {
+        series.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+    if reverse {
+        series.reverse();
+    }
+    Ok(series)
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum NaturalPart {
+    Number(u64),
+    Text(String)
+}
+
+// `web2` sorts before `web10`: runs of digits compare as numbers
+fn natural_key(name: &str) -> Vec<NaturalPart> {
+    let mut parts = vec![];
+    let mut chars = name.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        let is_digit = c.is_digit(10);
+        let mut part = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_digit(10) != is_digit {
+                break;
+            }
+            part.push(c);
+            chars.next();
+        }
+        parts.push(match part.parse::<u64>() {
+            Ok(number) if is_digit => NaturalPart::Number(number),
+            _ => NaturalPart::Text(part)
+        });
+    }
+    parts
+}
+
+fn remove_values<F>(args: &mut Args, remove: F) -> Result<Vec<TimeSeries>, String>
+    where F: Fn(f64, f64) -> bool {
+    let series = try!(args.series(0, "seriesList"));
+    let n = try!(args.number(1, "n"));
+    let function = args.function.clone();
+    Ok(series.iter().map(|series| {
+        let values = series.values.iter().map(|value| value.and_then(|value| {
+            if remove(value, n) { None } else { Some(value) }
+        })).collect();
+        series.derive(format!("{}({}, {})", function, series.name, format_number(n)), values)
+    }).collect())
+}
+
+pub fn remove_below_value(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    remove_values(args, |value, n| value < n)
+}
+
+pub fn remove_above_value(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    remove_values(args, |value, n| value > n)
+}
+
+fn above(args: &mut Args, key: fn(&TimeSeries) -> Option<f64>) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.series(0, "seriesList"));
+    let n = try!(args.number(1, "n"));
+    Ok(series.into_iter().filter(|series| key(series).map_or(false, |value| value > n)).collect())
+}
+
+pub fn current_above(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    above(args, current)
+}
+
+pub fn average_above(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    above(args, average)
+}
+
+#[cfg(test)]
+mod tests {
+    use whisper::query::functions::{ call, Args, Arg };
+    use whisper::query::series::TimeSeries;
+
+    fn hosts() -> Arg {
+        Arg::SeriesList(vec![
+            TimeSeries::new("web10".to_string(), 60, 60, vec![Some(5.0), Some(1.0), None]),
+            TimeSeries::new("web2".to_string(), 60, 60, vec![Some(2.0), Some(3.0), Some(4.5)]),
+            TimeSeries::new("db1".to_string(), 60, 60, vec![Some(9.0), None, Some(0.0)]),
+            TimeSeries::new("empty".to_string(), 60, 60, vec![None, None, None])
+        ])
+    }
+
+    fn names(function: &str, args: Vec<Arg>) -> Vec<String> {
+        let mut all = vec![hosts()];
+        all.extend(args);
+        call(Args::new(function.to_string(), all, vec![])).unwrap().into_iter().map(|series| series.name).collect()
+    }
+
+    #[test]
+    fn test_highest_and_lowest() {
+        assert_eq!(names("highestCurrent", vec![Arg::Number(2.0)]), vec!["web2", "web10"]);
+        assert_eq!(names("highestCurrent", vec![]), vec!["web2"]);
+        assert_eq!(names("highestMax", vec![Arg::Number(2.0)]), vec!["db1", "web10"]);
+        assert_eq!(names("highestAverage", vec![Arg::Number(2.0)]), vec!["db1", "web2"]);
+        assert_eq!(names("lowestCurrent", vec![Arg::Number(2.0)]), vec!["empty", "db1"]);
+    }
+
+    #[test]
+    fn test_filters() {
+        assert_eq!(names("limit", vec![Arg::Number(2.0)]), vec!["web10", "web2"]);
+        assert_eq!(names("exclude", vec![Arg::String("^web".to_string())]), vec!["db1", "empty"]);
+        assert_eq!(names("grep", vec![Arg::String("b\\d$".to_string())]), vec!["web2", "db1"]);
+        assert_eq!(names("currentAbove", vec![Arg::Number(0.5)]), vec!["web10", "web2"]);
+        assert_eq!(names("averageAbove", vec![Arg::Number(3.0)]), vec!["web2", "db1"]);
+    }
+
+    #[test]
+    fn test_sorting() {
+        assert_eq!(names("sortByMaxima", vec![]), vec!["db1", "web10", "web2", "empty"]);
+        assert_eq!(names("sortByName", vec![]), vec!["db1", "empty", "web10", "web2"]);
+        assert_eq!(names("sortByName", vec![Arg::Boolean(true)]), vec!["db1", "empty", "web2", "web10"]);
+        assert_eq!(names("sortByName", vec![Arg::Boolean(true), Arg::Boolean(true)]), vec!["web10", "web2", "empty", "db1"]);
+    }
+
+    #[test]
+    fn test_remove_values() {
+        let series = call(Args::new("removeBelowValue".to_string(), vec![hosts(), Arg::Number(3.0)], vec![])).unwrap();
+        assert_eq!(series[0].name, "removeBelowValue(web10, 3)");
+        assert_eq!(series[0].values, vec![Some(5.0), None, None]);
+
+        let series = call(Args::new("removeAboveValue".to_string(), vec![hosts(), Arg::Number(3.0)], vec![])).unwrap();
+        assert_eq!(series[1].values, vec![Some(2.0), Some(3.0), None]);
+    }
+}
@@ -0,0 +1,265 @@
+// Holt-Winters forecasting, ported from graphite-web so the numbers match.
+// Every function fetches an extra bootstrap window before `from` to train
+// on, so they are evaluated as special forms.
+
+use super::parse_interval;
+use whisper::query::{ Context, Call };
+use whisper::query::series::TimeSeries;
+
+// graphite-web's smoothing constants
+const ALPHA : f64 = 0.1;
+const BETA : f64 = 0.0035;
+const GAMMA : f64 = 0.1;
+
+const DEFAULT_BOOTSTRAP : &'static str = "7d";
+const DEFAULT_SEASONALITY : &'static str = "1d";
+const DEFAULT_DELTA : f64 = 3.0;
+
+struct Analysis {
+    predictions: Vec<Option<f64>>,
+    deviations: Vec<Option<f64>>
+}
+
+fn analyse(values: &[Option<f64>], season_length: usize) -> Analysis {
+    let mut intercepts: Vec<Option<f64>> = vec![];
+    let mut slopes: Vec<f64> = vec![];
+    let mut seasonals: Vec<f64> = vec![];
+    let mut predictions: Vec<Option<f64>> = vec![];
+    let mut deviations: Vec<f64> = vec![];
+
+    let last_in_season = |history: &[f64], i: usize| if i >= season_length { history[i - season_length] } else { 0.0 };
+    let mut next_prediction: Option<f64> = None;
+
+    for (i, actual) in values.iter().enumerate() {
+        let actual = match *actual {
+            Some(actual) => actual,
+            None => {
+                // Missing values break all the math, do the best we can
+                intercepts.push(None);
+                slopes.push(0.0);
+                seasonals.push(0.0);
+                predictions.push(next_prediction);
+                deviations.push(0.0);
+                next_prediction = None;
+                continue;
+            }
+        };
+
+        let (last_intercept, last_slope, prediction) = if i == 0 {
+            // Seed the first prediction with the first value
+            (actual, 0.0, Some(actual))
+        } else {
+            (intercepts[i - 1].unwrap_or(actual), slopes[i - 1], next_prediction)
+        };
+
+        let last_seasonal = last_in_season(&seasonals, i);
+        let next_last_seasonal = if i + 1 >= season_length && i + 1 - season_length < seasonals.len() {
+            seasonals[i + 1 - season_length]
+        } else {
+            0.0
+        };
+        let last_seasonal_deviation = last_in_season(&deviations, i);
+
+        let intercept = ALPHA * (actual - last_seasonal) + (1.0 - ALPHA) * (last_intercept + last_slope);
+        let slope = BETA * (intercept - last_intercept) + (1.0 - BETA) * last_slope;
+        let seasonal = GAMMA * (actual - intercept) + (1.0 - GAMMA) * last_seasonal;
+        next_prediction = Some(intercept + slope + next_last_seasonal);
+        let deviation = GAMMA * (actual - prediction.unwrap_or(0.0)).abs() + (1.0 - GAMMA) * last_seasonal_deviation;
+
+        intercepts.push(Some(intercept));
+        slopes.push(slope);
+        seasonals.push(seasonal);
+        predictions.push(prediction);
+        deviations.push(deviation);
+    }
+
+    Analysis {
+        predictions: predictions,
+        deviations: deviations.into_iter().map(Some).collect()
+    }
+}
+
+fn interval(name: &str, interval: &str) -> Result<u32, String> {
+    match try!(parse_interval(interval)) {
+        seconds if seconds > 0 => Ok(seconds as u32),
+        _ => Err(format!("{}: interval `{}` must be positive", name, interval))
+    }
+}
+
+// The series with its bootstrap window, the bootstrap length in seconds
+// and the season length in seconds
+fn bootstrap(context: &Context, call: &Call, bootstrap_position: usize) -> Result<(Vec<TimeSeries>, u32, u32, f64), String> {
+    let expression = try!(call.args.first().ok_or_else(|| format!("{}: missing argument `seriesList`", call.name)));
+    let mut args = try!(context.evaluate_args(call, 1));
+
+    // The confidence functions take `delta` before the intervals
+    let delta = if bootstrap_position > 0 {
+        try!(args.optional_number(0, "delta")).unwrap_or(DEFAULT_DELTA)
+    } else {
+        DEFAULT_DELTA
+    };
+    let bootstrap = try!(args.optional_string(bootstrap_position, "bootstrapInterval")).unwrap_or_else(|| DEFAULT_BOOTSTRAP.to_string());
+    let seasonality = try!(args.optional_string(bootstrap_position + 1, "seasonality")).unwrap_or_else(|| DEFAULT_SEASONALITY.to_string());
+    let bootstrap = try!(interval(&call.name, &bootstrap));
+    let seasonality = try!(interval(&call.name, &seasonality));
+
+    let bootstrap_context = Context {
+        from: context.from.saturating_sub(bootstrap),
+        until: context.until,
+        now: context.now,
+        fetcher: context.fetcher
+    };
+    let series = try!(bootstrap_context.evaluate(expression));
+
+    // A season must span at least one point, or there is nothing to look back on
+    if let Some(series) = series.iter().find(|series| series.step > seasonality) {
+        return Err(format!("{}: seasonality of {}s is shorter than the {}s step of `{}`", call.name, seasonality, series.step, series.name));
+    }
+    Ok((series, bootstrap, seasonality, delta))
+}
+
+// Drop the bootstrap window from the front of `values`
+fn after_bootstrap(series: &TimeSeries, name: String, values: Vec<Option<f64>>, bootstrap: u32) -> TimeSeries {
+    let skip = ((bootstrap / series.step) as usize).min(values.len());
+    let mut result = series.derive(name, values[skip..].to_vec());
+    result.start = series.start + skip as u32 * series.step;
+    result.end = result.start + result.values.len() as u32 * result.step;
+    result
+}
+
+pub fn forecast(context: &Context, call: &Call) -> Result<Vec<TimeSeries>, String> {
+    let (series, bootstrap, seasonality, _) = try!(bootstrap(context, call, 0));
+    Ok(series.iter().map(|series| {
+        let analysis = analyse(&series.values, (seasonality / series.step) as usize);
+        after_bootstrap(series, format!("holtWintersForecast({})", series.name), analysis.predictions, bootstrap)
+    }).collect())
+}
+
+// (lower, upper) for every series, `delta` deviations either side of the forecast
+fn bands(series: &[TimeSeries], bootstrap: u32, seasonality: u32, delta: f64) -> Vec<(TimeSeries, TimeSeries)> {
+    series.iter().map(|series| {
+        let analysis = analyse(&series.values, (seasonality / series.step) as usize);
+        let (lower, upper): (Vec<Option<f64>>, Vec<Option<f64>>) = analysis.predictions.iter().zip(analysis.deviations.iter())
+            .map(|(prediction, deviation)| match (*prediction, *deviation) {
+                (Some(prediction), Some(deviation)) => (Some(prediction - delta * deviation), Some(prediction + delta * deviation)),
+                _ => (None, None)
+            })
+            .unzip();
+
+        let mut lower = after_bootstrap(series, format!("holtWintersConfidenceLower({})", series.name), lower, bootstrap);
+        let mut upper = after_bootstrap(series, format!("holtWintersConfidenceUpper({})", series.name), upper, bootstrap);
+        lower.path_expression = series.path_expression.clone();
+        upper.path_expression = series.path_expression.clone();
+        (lower, upper)
+    }).collect()
+}
+
+pub fn confidence_bands(context: &Context, call: &Call) -> Result<Vec<TimeSeries>, String> {
+    let (series, bootstrap, seasonality, delta) = try!(bootstrap(context, call, 1));
+    let mut results = vec![];
+    for (lower, upper) in bands(&series, bootstrap, seasonality, delta) {
+        results.push(lower);
+        results.push(upper);
+    }
+    Ok(results)
+}
+
+// How far each value is outside its confidence band, 0 inside it
+pub fn aberration(context: &Context, call: &Call) -> Result<Vec<TimeSeries>, String> {
+    let (bootstrapped, bootstrap, seasonality, delta) = try!(bootstrap(context, call, 1));
+    let series = try!(context.evaluate(&call.args[0]));
+    let bands = bands(&bootstrapped, bootstrap, seasonality, delta);
+
+    Ok(series.iter().zip(bands.iter()).map(|(series, &(ref lower, ref upper))| {
+        let band_at = |band: &TimeSeries, timestamp: u32| -> Option<f64> {
+            if timestamp < band.start || band.step != series.step {
+                return None;
+            }
+            band.values.get(((timestamp - band.start) / band.step) as usize).cloned().unwrap_or(None)
+        };
+
+        let values = series.values.iter().zip(series.timestamps()).map(|(actual, timestamp)| {
+            Some(match *actual {
+                Some(actual) => match (band_at(lower, timestamp), band_at(upper, timestamp)) {
+                    (_, Some(upper)) if actual > upper => actual - upper,
+                    (Some(lower), _) if actual < lower => actual - lower,
+                    _ => 0.0
+                },
+                None => 0.0
+            })
+        }).collect();
+        series.derive(format!("holtWintersAberration({})", series.name), values)
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::analyse;
+    use whisper::query::{ Context, Fetcher, TimeSeries };
+
+    // A daily cycle sampled every 6 hours, with a spike at the end
+    struct FakeFetcher;
+
+    impl Fetcher for FakeFetcher {
+        fn fetch(&self, _path_expression: &str, from: u32, until: u32) -> Result<Vec<TimeSeries>, String> {
+            let step = 6 * 3600;
+            let start = from - from % step + step;
+            let values = (start..until + 1).step_by(step as usize).map(|timestamp| {
+                let cycle = [10.0, 20.0, 30.0, 20.0][((timestamp / step) % 4) as usize];
+                Some(if timestamp + step > until { cycle * 3.0 } else { cycle })
+            }).collect();
+            Ok(vec![TimeSeries::new("a.b".to_string(), start, step, values)])
+        }
+    }
+
+    #[test]
+    fn test_analysis_matches_graphite() {
+        // Computed with graphite-web's holtWintersAnalysis, seasonality of 2 points
+        let analysis = analyse(&[Some(1.0), Some(2.0), None, Some(4.0), Some(3.0)], 2);
+        let expected = [Some(1.0), Some(1.0), Some(1.10035), None, Some(3.9909685)];
+        for (actual, expected) in analysis.predictions.iter().zip(expected.iter()) {
+            assert_eq!(actual.is_some(), expected.is_some());
+            assert!((actual.unwrap_or(0.0) - expected.unwrap_or(0.0)).abs() < 1e-9, "{:?}", analysis.predictions);
+        }
+        let expected = [0.0, 0.1, 0.0, 0.49, 0.09909685];
+        for (actual, expected) in analysis.deviations.iter().zip(expected.iter()) {
+            assert!((actual.unwrap() - expected).abs() < 1e-9, "{:?}", analysis.deviations);
+        }
+    }
+
+    #[test]
+    fn test_forecast_skips_bootstrap() {
+        let day = 86400;
+        let context = Context { from: 30 * day, until: 31 * day, now: 31 * day, fetcher: &FakeFetcher };
+
+        let forecast = context.evaluate_target("holtWintersForecast(a.b)").unwrap();
+        assert_eq!(forecast[0].name, "holtWintersForecast(a.b)");
+        assert_eq!(forecast[0].start, 30 * day + 6 * 3600);
+        assert_eq!(forecast[0].values.len(), 4);
+
+        let bands = context.evaluate_target("holtWintersConfidenceBands(a.b, 2)").unwrap();
+        assert_eq!(bands[0].name, "holtWintersConfidenceLower(a.b)");
+        assert_eq!(bands[1].name, "holtWintersConfidenceUpper(a.b)");
+        assert_eq!(bands[0].start, forecast[0].start);
+        for ((lower, upper), forecast) in bands[0].values.iter().zip(bands[1].values.iter()).zip(forecast[0].values.iter()) {
+            assert!(lower.unwrap() <= forecast.unwrap() && forecast.unwrap() <= upper.unwrap());
+        }
+
+        // Only the spike at the end is outside the bands
+        let aberration = context.evaluate_target("holtWintersAberration(a.b, 2)").unwrap();
+        assert_eq!(aberration[0].name, "holtWintersAberration(a.b)");
+        assert_eq!(&aberration[0].values[..3], &[Some(0.0), Some(0.0), Some(0.0)]);
+        assert!(aberration[0].values[3].unwrap() > 0.0);
+    }
+
+    #[test]
+    fn test_seasonality_shorter_than_step() {
+        let day = 86400;
+        let context = Context { from: 30 * day, until: 31 * day, now: 31 * day, fetcher: &FakeFetcher };
+
+        assert_eq!(context.evaluate_target("holtWintersForecast(a.b, '7d', '1h')").unwrap_err(),
+            "holtWintersForecast: seasonality of 3600s is shorter than the 21600s step of `a.b`");
+        assert!(context.evaluate_target("holtWintersConfidenceBands(a.b, 3, '7d', '1s')").is_err());
+        assert!(context.evaluate_target("holtWintersAberration(a.b, 3, '7d', '1s')").is_err());
+    }
+}
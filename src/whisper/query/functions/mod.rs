@@ -0,0 +1,219 @@
+// Graphite's render functions. Each module holds one family of them and
+// `call` routes a function name to its implementation.
+
+mod combine;
+mod transform;
+mod filter;
+mod summarize;
+mod holt_winters;
+
+use std::mem;
+
+use super::TimeSeries;
+use super::time_expr::parse_interval;
+
+pub use self::transform::time_shift;
+pub use self::summarize::{ smart_summarize, hitcount };
+pub use self::holt_winters::{ forecast as holt_winters_forecast, confidence_bands as holt_winters_confidence_bands,
+    aberration as holt_winters_aberration };
+
+// An evaluated argument to a function
+#[derive(Debug, PartialEq, Clone)]
+pub enum Arg {
+    SeriesList(Vec<TimeSeries>),
+    Number(f64),
+    String(String),
+    Boolean(bool)
+}
+
+// The arguments a function was called with. Accessors look a parameter up
+// by position or by keyword, so `summarize(a, "1h", "sum")` and
+// `summarize(a, "1h", func="sum")` mean the same thing.
+pub struct Args {
+    pub function: String,
+    positional: Vec<Option<Arg>>,
+    keywords: Vec<(String, Option<Arg>)>
+}
+
+impl Args {
+    pub fn new(function: String, positional: Vec<Arg>, keywords: Vec<(String, Arg)>) -> Args {
+        Args {
+            function: function,
+            positional: positional.into_iter().map(Some).collect(),
+            keywords: keywords.into_iter().map(|(key, arg)| (key, Some(arg))).collect()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.positional.len()
+    }
+
+    fn take(&mut self, position: usize, name: &str) -> Option<Arg> {
+        if let Some(&mut (_, ref mut arg)) = self.keywords.iter_mut().find(|&&mut (ref key, _)| key == name) {
+            return arg.take();
+        }
+        self.positional.get_mut(position).and_then(|arg| arg.take())
+    }
+
+    fn missing(&self, name: &str) -> String {
+        format!("{}: missing argument `{}`", self.function, name)
+    }
+
+    fn wrong_type(&self, name: &str, expected: &str) -> String {
+        format!("{}: argument `{}` must be {}", self.function, name, expected)
+    }
+
+    pub fn series(&mut self, position: usize, name: &str) -> Result<Vec<TimeSeries>, String> {
+        match self.take(position, name) {
+            Some(Arg::SeriesList(series)) => Ok(series),
+            Some(_) => Err(self.wrong_type(name, "a series list")),
+            None => Err(self.missing(name))
+        }
+    }
+
+    // Every positional argument from `position` on, for functions taking
+    // any number of series lists like sumSeries
+    pub fn all_series(&mut self, position: usize) -> Result<Vec<TimeSeries>, String> {
+        let mut all = vec![];
+        for i in position..self.positional.len() {
+            match mem::replace(&mut self.positional[i], None) {
+                Some(Arg::SeriesList(series)) => all.extend(series),
+                Some(_) => return Err(self.wrong_type("seriesLists", "series lists")),
+                None => ()
+            }
+        }
+        Ok(all)
+    }
+
+    pub fn number(&mut self, position: usize, name: &str) -> Result<f64, String> {
+        self.optional_number(position, name).and_then(|number| number.ok_or_else(|| self.missing(name)))
+    }
+
+    pub fn optional_number(&mut self, position: usize, name: &str) -> Result<Option<f64>, String> {
+        match self.take(position, name) {
+            Some(Arg::Number(number)) => Ok(Some(number)),
+            Some(_) => Err(self.wrong_type(name, "a number")),
+            None => Ok(None)
+        }
+    }
+
+    pub fn string(&mut self, position: usize, name: &str) -> Result<String, String> {
+        self.optional_string(position, name).and_then(|string| string.ok_or_else(|| self.missing(name)))
+    }
+
+    pub fn optional_string(&mut self, position: usize, name: &str) -> Result<Option<String>, String> {
+        match self.take(position, name) {
+            Some(Arg::String(string)) => Ok(Some(string)),
+            Some(_) => Err(self.wrong_type(name, "a string")),
+            None => Ok(None)
+        }
+    }
+
+    pub fn optional_boolean(&mut self, position: usize, name: &str) -> Result<Option<bool>, String> {
+        match self.take(position, name) {
+            Some(Arg::Boolean(boolean)) => Ok(Some(boolean)),
+            Some(_) => Err(self.wrong_type(name, "true or false")),
+            None => Ok(None)
+        }
+    }
+
+    // Whatever was passed, for parameters taking more than one type
+    pub fn optional_arg(&mut self, position: usize, name: &str) -> Option<Arg> {
+        self.take(position, name)
+    }
+}
+
+pub fn call(mut args: Args) -> Result<Vec<TimeSeries>, String> {
+    match args.function.as_str() {
+        "sumSeries" | "sum" => combine::sum_series(&mut args),
+        "averageSeries" | "avg" => combine::average_series(&mut args),
+        "maxSeries" => combine::max_series(&mut args),
+        "minSeries" => combine::min_series(&mut args),
+        "multiplySeries" => combine::multiply_series(&mut args),
+        "diffSeries" => combine::diff_series(&mut args),
+        "divideSeries" => combine::divide_series(&mut args),
+        "asPercent" => combine::as_percent(&mut args),
+        "countSeries" => combine::count_series(&mut args),
+        "group" => combine::group(&mut args),
+        "derivative" => transform::derivative(&mut args),
+        "nonNegativeDerivative" => transform::non_negative_derivative(&mut args),
+        "perSecond" => transform::per_second(&mut args),
+        "integral" => transform::integral(&mut args),
+        "scale" => transform::scale(&mut args),
+        "scaleToSeconds" => transform::scale_to_seconds(&mut args),
+        "offset" => transform::offset(&mut args),
+        "absolute" => transform::absolute(&mut args),
+        "log" => transform::log(&mut args),
+        "keepLastValue" => transform::keep_last_value(&mut args),
+        "transformNull" => transform::transform_null(&mut args),
+        "highestCurrent" => filter::highest_current(&mut args),
+        "highestMax" => filter::highest_max(&mut args),
+        "highestAverage" => filter::highest_average(&mut args),
+        "lowestCurrent" => filter::lowest_current(&mut args),
+        "limit" => filter::limit(&mut args),
+        "exclude" => filter::exclude(&mut args),
+        "grep" => filter::grep(&mut args),
+        "sortByMaxima" => filter::sort_by_maxima(&mut args),
+        "sortByName" => filter::sort_by_name(&mut args),
+        "removeBelowValue" => filter::remove_below_value(&mut args),
+        "removeAboveValue" => filter::remove_above_value(&mut args),
+        "currentAbove" => filter::current_above(&mut args),
+        "averageAbove" => filter::average_above(&mut args),
+        "summarize" => summarize::summarize(&mut args),
+        "consolidateBy" => summarize::consolidate_by(&mut args),
+        name => Err(format!("unknown function `{}`", name))
+    }
+}
+
+// `a.*,b.c` style list of the targets which made up a combined series
+pub fn format_path_expressions(series: &[TimeSeries]) -> String {
+    let mut expressions: Vec<&str> = vec![];
+    for series in series {
+        if !expressions.contains(&series.path_expression.as_str()) {
+            expressions.push(&series.path_expression);
+        }
+    }
+    expressions.join(",")
+}
+
+// Numbers in series names are printed like python's `%g`: `2.5`, `10`, `1e-07`
+pub fn format_number(number: f64) -> String {
+    if number == 0.0 || !number.is_finite() {
+        return format!("{}", number);
+    }
+
+    let exponent = number.abs().log10().floor() as i32;
+    if exponent < -4 || exponent >= 6 {
+        let formatted = format!("{:.5e}", number);
+        let (mantissa, exponent) = formatted.split_at(formatted.find('e').unwrap());
+        let exponent: i32 = exponent[1..].parse().unwrap();
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", trim_zeros(mantissa), sign, exponent.abs())
+    } else {
+        let decimals = (5 - exponent).max(0) as usize;
+        trim_zeros(&format!("{:.*}", decimals, number)).to_string()
+    }
+}
+
+fn trim_zeros(number: &str) -> &str {
+    if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_number() {
+        assert_eq!(format_number(10.0), "10");
+        assert_eq!(format_number(2.5), "2.5");
+        assert_eq!(format_number(-0.125), "-0.125");
+        assert_eq!(format_number(1.0 / 3.0), "0.333333");
+        assert_eq!(format_number(1e-7), "1e-07");
+        assert_eq!(format_number(1234567.0), "1.23457e+06");
+    }
+}
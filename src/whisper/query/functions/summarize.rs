@@ -0,0 +1,315 @@
+// Functions which roll series up in to fixed size buckets of time
+
+use time::{ self, Timespec };
+
+use std::str::FromStr;
+
+use super::{ Args, parse_interval };
+use whisper::query::{ Context, Call };
+use whisper::query::series::{ TimeSeries, ConsolidationFunc };
+
+const MINUTE : u32 = 60;
+const HOUR : u32 = 60 * MINUTE;
+const DAY : u32 = 24 * HOUR;
+const WEEK : u32 = 7 * DAY;
+const MONTH : u32 = 30 * DAY;
+const YEAR : u32 = 365 * DAY;
+
+fn interval_seconds(args: &Args, interval: &str) -> Result<u32, String> {
+    match try!(parse_interval(interval)) {
+        seconds if seconds > 0 => Ok(seconds as u32),
+        _ => Err(format!("{}: interval `{}` must be positive", args.function, interval))
+    }
+}
+
+fn aggregation(args: &mut Args, position: usize) -> Result<(String, ConsolidationFunc), String> {
+    let func = try!(args.optional_string(position, "func")).unwrap_or_else(|| "sum".to_string());
+    let consolidation = try!(ConsolidationFunc::from_str(&func).map_err(|why| format!("{}: {}", args.function, why)));
+    Ok((func, consolidation))
+}
+
+// Gather the values present in to `count` buckets of `interval` seconds from `start`
+fn bucket(series: &TimeSeries, start: u32, interval: u32, count: usize, func: ConsolidationFunc) -> Vec<Option<f64>> {
+    let mut buckets: Vec<Vec<f64>> = (0..count).map(|_| vec![]).collect();
+    for (timestamp, value) in series.timestamps().into_iter().zip(series.values.iter()) {
+        if let Some(value) = *value {
+            if timestamp >= start {
+                let index = ((timestamp - start) / interval) as usize;
+                if index < count {
+                    buckets[index].push(value);
+                }
+            }
+        }
+    }
+    buckets.iter().map(|bucket| func.apply(bucket)).collect()
+}
+
+// Buckets line up with multiples of the interval, or with the start of
+// each series if `alignToFrom` is true
+pub fn summarize(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.series(0, "seriesList"));
+    let interval_string = try!(args.string(1, "intervalString"));
+    let interval = try!(interval_seconds(args, &interval_string));
+    let (func_name, func) = try!(aggregation(args, 2));
+    let align_to_from = try!(args.optional_boolean(3, "alignToFrom")).unwrap_or(false);
+
+    Ok(series.iter().map(|series| {
+        let (start, end) = if align_to_from {
+            (series.start, series.end)
+        } else {
+            (series.start - series.start % interval, series.end - series.end % interval + interval)
+        };
+        let count = ((end - start + interval - 1) / interval) as usize;
+
+        let name = format!("summarize({}, \"{}\", \"{}\"{})", series.name, interval_string, func_name,
+            if align_to_from { ", true" } else { "" });
+        let mut summarized = TimeSeries::new(name, start, interval, bucket(series, start, interval, count, func));
+        summarized.consolidation = series.consolidation;
+        summarized
+    }).collect())
+}
+
+// Move `from` back to the start of the minute, hour, day, week (Monday),
+// month or year it is in. Times are UTC.
+fn align_time(from: u32, unit: u32) -> u32 {
+    let mut tm = time::at_utc(Timespec::new(from as i64, 0));
+    if unit >= MINUTE {
+        tm.tm_sec = 0;
+    }
+    if unit >= HOUR {
+        tm.tm_min = 0;
+    }
+    if unit >= DAY {
+        tm.tm_hour = 0;
+    }
+    if unit >= YEAR {
+        tm.tm_mon = 0;
+    }
+    if unit >= MONTH {
+        tm.tm_mday = 1;
+    }
+    let aligned = tm.to_timespec().sec as u32;
+    if unit >= WEEK && unit < MONTH {
+        // tm_wday counts from Sunday
+        aligned - ((tm.tm_wday as u32 + 6) % 7) * DAY
+    } else {
+        aligned
+    }
+}
+
+// Evaluate the first argument of `call` again from an earlier `from`
+fn evaluate_aligned(context: &Context, call: &Call, from: u32) -> Result<Vec<TimeSeries>, String> {
+    let expression = try!(call.args.first().ok_or_else(|| format!("{}: missing argument `seriesList`", call.name)));
+    let aligned_context = Context {
+        from: from,
+        until: context.until,
+        now: context.now,
+        fetcher: context.fetcher
+    };
+    aligned_context.evaluate(expression)
+}
+
+// Like summarize with `alignToFrom`, but `alignTo` (a unit like `1d` or
+// `hours`) first moves `from` back to the start of that unit
+pub fn smart_summarize(context: &Context, call: &Call) -> Result<Vec<TimeSeries>, String> {
+    let mut args = try!(context.evaluate_args(call, 1));
+    let interval_string = try!(args.string(0, "intervalString"));
+    let interval = try!(interval_seconds(&args, &interval_string));
+    let (func_name, func) = try!(aggregation(&mut args, 1));
+    let align_to = try!(args.optional_string(2, "alignTo"));
+
+    let from = match align_to {
+        Some(ref align_to) => {
+            let unit = align_to.trim_start_matches(|c: char| c.is_digit(10) || c == '-' || c == '+');
+            let seconds = try!(parse_interval(&format!("1{}", unit)));
+            align_time(context.from, seconds as u32)
+        },
+        None => context.from
+    };
+    let series = try!(evaluate_aligned(context, call, from));
+
+    Ok(series.iter().map(|series| {
+        let count = ((series.end - series.start + interval - 1) / interval) as usize;
+        let name = format!("smartSummarize({}, \"{}\", \"{}\")", series.name, interval_string, func_name);
+        let mut summarized = TimeSeries::new(name, series.start, interval, bucket(series, series.start, interval, count, func));
+        summarized.consolidation = series.consolidation;
+        summarized
+    }).collect())
+}
+
+// Each value is a rate per second over its step; count the hits that fall
+// in to each bucket, splitting a step's hits between the buckets it spans
+fn hits(series: &TimeSeries, start: u32, interval: u32, count: usize) -> Vec<Option<f64>> {
+    let mut buckets: Vec<Option<f64>> = vec![None; count];
+    let mut add = |index: i64, hits: f64| {
+        if index >= 0 && (index as usize) < count {
+            let bucket = &mut buckets[index as usize];
+            *bucket = Some(bucket.unwrap_or(0.0) + hits);
+        }
+    };
+
+    let interval = interval as i64;
+    let step = series.step as i64;
+    for (i, value) in series.values.iter().enumerate() {
+        let value = match *value {
+            Some(value) => value,
+            None => continue
+        };
+
+        let step_start = series.start as i64 + i as i64 * step - start as i64;
+        let (start_bucket, start_mod) = (div_floor(step_start, interval), mod_floor(step_start, interval));
+        let step_end = step_start + step;
+        let (mut end_bucket, mut end_mod) = (div_floor(step_end, interval), mod_floor(step_end, interval));
+        if end_bucket >= count as i64 {
+            end_bucket = count as i64 - 1;
+            end_mod = interval;
+        }
+
+        if start_bucket == end_bucket {
+            add(start_bucket, value * (end_mod - start_mod) as f64);
+        } else {
+            add(start_bucket, value * (interval - start_mod) as f64);
+            for bucket in (start_bucket + 1)..end_bucket {
+                add(bucket, value * interval as f64);
+            }
+            if end_mod > 0 {
+                add(end_bucket, value * end_mod as f64);
+            }
+        }
+    }
+    buckets
+}
+
+fn div_floor(a: i64, b: i64) -> i64 {
+    (a - mod_floor(a, b)) / b
+}
+
+fn mod_floor(a: i64, b: i64) -> i64 {
+    ((a % b) + b) % b
+}
+
+// Estimate hits per bucket from rates. Buckets end where the series ends,
+// unless `alignToInterval` moves `from` back to the start of the interval's
+// unit and fetches again so the buckets start there.
+pub fn hitcount(context: &Context, call: &Call) -> Result<Vec<TimeSeries>, String> {
+    let mut args = try!(context.evaluate_args(call, 1));
+    let interval_string = try!(args.string(0, "intervalString"));
+    let interval = try!(interval_seconds(&args, &interval_string));
+    let align_to_interval = try!(args.optional_boolean(1, "alignToInterval")).unwrap_or(false);
+
+    let from = if align_to_interval { align_time(context.from, interval.min(DAY)) } else { context.from };
+    let series = try!(evaluate_aligned(context, call, from));
+
+    Ok(series.iter().map(|series| {
+        let count = ((series.end - series.start + interval - 1) / interval) as usize;
+        let start = if align_to_interval { series.start } else { series.end - count as u32 * interval };
+        let name = format!("hitcount({}, \"{}\"{})", series.name, interval_string,
+            if align_to_interval { ", true" } else { "" });
+        let mut counted = TimeSeries::new(name, start, interval, hits(series, start, interval, count));
+        counted.consolidation = series.consolidation;
+        counted
+    }).collect())
+}
+
+// How values are combined when a series is drawn with fewer points than it has
+pub fn consolidate_by(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.series(0, "seriesList"));
+    let func_name = try!(args.string(1, "consolidationFunc"));
+    let func = try!(ConsolidationFunc::from_str(&func_name).map_err(|why| format!("consolidateBy: {}", why)));
+
+    Ok(series.into_iter().map(|mut series| {
+        series.name = format!("consolidateBy({},\"{}\")", series.name, func_name);
+        series.path_expression = series.name.clone();
+        series.consolidation = func;
+        series
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use whisper::query::functions::{ call, Args, Arg };
+    use whisper::query::{ Context, Fetcher, TimeSeries, ConsolidationFunc };
+
+    fn apply(function: &str, series: TimeSeries, args: Vec<Arg>) -> TimeSeries {
+        let mut all = vec![Arg::SeriesList(vec![series])];
+        all.extend(args);
+        call(Args::new(function.to_string(), all, vec![])).unwrap().remove(0)
+    }
+
+    fn minutely() -> TimeSeries {
+        // 00:01 to 00:06 on 1970-01-02
+        TimeSeries::new("a.b".to_string(), DAY + 60, 60, vec![Some(1.0), Some(2.0), None, Some(4.0), Some(5.0), Some(6.0)])
+    }
+
+    #[test]
+    fn test_summarize() {
+        let summarized = apply("summarize", minutely(), vec![Arg::String("2m".to_string())]);
+        assert_eq!(summarized.name, "summarize(a.b, \"2m\", \"sum\")");
+        assert_eq!((summarized.start, summarized.step), (DAY, 120));
+        assert_eq!(summarized.values, vec![Some(1.0), Some(2.0), Some(9.0), Some(6.0)]);
+
+        let summarized = apply("summarize", minutely(), vec![Arg::String("2m".to_string()), Arg::String("max".to_string()), Arg::Boolean(true)]);
+        assert_eq!(summarized.name, "summarize(a.b, \"2m\", \"max\", true)");
+        assert_eq!(summarized.start, DAY + 60);
+        assert_eq!(summarized.values, vec![Some(2.0), Some(4.0), Some(6.0)]);
+    }
+
+    #[test]
+    fn test_consolidate_by() {
+        let consolidated = apply("consolidateBy", minutely(), vec![Arg::String("max".to_string())]);
+        assert_eq!(consolidated.name, "consolidateBy(a.b,\"max\")");
+        assert_eq!(consolidated.consolidation, ConsolidationFunc::Max);
+    }
+
+    #[test]
+    fn test_align_time() {
+        // Friday 1970-01-09 13:45:30
+        let time = 8 * DAY + 13 * HOUR + 45 * MINUTE + 30;
+        assert_eq!(align_time(time, 1), time);
+        assert_eq!(align_time(time, HOUR), 8 * DAY + 13 * HOUR);
+        assert_eq!(align_time(time, DAY), 8 * DAY);
+        assert_eq!(align_time(time, WEEK), 4 * DAY);
+        assert_eq!(align_time(time + 40 * DAY, MONTH), 31 * DAY);
+        assert_eq!(align_time(time + 400 * DAY, YEAR), 365 * DAY);
+    }
+
+    struct FakeFetcher;
+
+    impl Fetcher for FakeFetcher {
+        fn fetch(&self, _path_expression: &str, from: u32, until: u32) -> Result<Vec<TimeSeries>, String> {
+            // One hit per second, every minute
+            let start = from - from % 60 + 60;
+            let count = ((until - start) / 60 + 1) as usize;
+            Ok(vec![TimeSeries::new("a.b".to_string(), start, 60, vec![Some(1.0); count])])
+        }
+    }
+
+    #[test]
+    fn test_hitcount() {
+        let context = Context { from: DAY + 30, until: DAY + 600, now: DAY + 600, fetcher: &FakeFetcher };
+
+        let counted = context.evaluate_target("hitcount(a.b, '5m')").unwrap().remove(0);
+        assert_eq!(counted.name, "hitcount(a.b, \"5m\")");
+        assert_eq!((counted.start, counted.end), (DAY + 60, DAY + 660));
+        assert_eq!(counted.values, vec![Some(300.0), Some(300.0)]);
+
+        let counted = context.evaluate_target("hitcount(a.b, '5m', true)").unwrap().remove(0);
+        assert_eq!(counted.start, DAY + 60);
+        assert_eq!(counted.values, vec![Some(300.0), Some(300.0)]);
+    }
+
+    #[test]
+    fn test_smart_summarize() {
+        let context = Context { from: DAY + 7200 + 30, until: DAY + 7200 + 600, now: DAY + 7800, fetcher: &FakeFetcher };
+
+        let summarized = context.evaluate_target("smartSummarize(a.b, '5m')").unwrap().remove(0);
+        assert_eq!(summarized.name, "smartSummarize(a.b, \"5m\", \"sum\")");
+        assert_eq!(summarized.start, DAY + 7260);
+        assert_eq!(summarized.values, vec![Some(5.0), Some(5.0)]);
+
+        let summarized = context.evaluate_target("smartSummarize(a.b, '1h', 'sum', '1d')").unwrap().remove(0);
+        assert_eq!(summarized.start, DAY + 60);
+        assert_eq!(summarized.values, vec![Some(60.0), Some(60.0), Some(10.0)]);
+    }
+}
@@ -0,0 +1,318 @@
+// Functions which rework each series on its own
+
+use super::{ Args, format_number, parse_interval };
+use whisper::query::{ Context, Call };
+use whisper::query::series::TimeSeries;
+
+fn map_values<F>(series: Vec<TimeSeries>, name: F, transform: &dyn Fn(&TimeSeries) -> Vec<Option<f64>>) -> Vec<TimeSeries>
+    where F: Fn(&TimeSeries) -> String {
+    series.iter().map(|series| series.derive(name(series), transform(series))).collect()
+}
+
+// Apply `transform` to every value present, leaving gaps alone
+fn map_present<F>(series: Vec<TimeSeries>, name: &dyn Fn(&TimeSeries) -> String, transform: F) -> Vec<TimeSeries>
+    where F: Fn(f64) -> Option<f64> {
+    map_values(series, name, &|series| series.values.iter().map(|value| value.and_then(&transform)).collect())
+}
+
+pub fn derivative(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.series(0, "seriesList"));
+    Ok(map_values(series, |series| format!("derivative({})", series.name), &|series| {
+        let mut previous = None;
+        series.values.iter().map(|&value| {
+            let delta = match (previous, value) {
+                (Some(previous), Some(value)) => Some(value - previous),
+                _ => None
+            };
+            previous = value;
+            delta
+        }).collect()
+    }))
+}
+
+// The change from `previous` to `value` for a counter, and the value to
+// compare the next one against. Values outside [min_value, max_value]
+// are ignored, and a counter which went down either wrapped from
+// `max_value` back to `min_value` (or zero) or was reset.
+fn non_negative_delta(value: Option<f64>, previous: Option<f64>, max_value: Option<f64>, min_value: Option<f64>) -> (Option<f64>, Option<f64>) {
+    let value = match value {
+        Some(value) => value,
+        None => return (None, None)
+    };
+    if max_value.map_or(false, |max_value| value > max_value) || min_value.map_or(false, |min_value| value < min_value) {
+        return (None, None);
+    }
+    let previous = match previous {
+        Some(previous) => previous,
+        None => return (None, Some(value))
+    };
+
+    if value >= previous {
+        (Some(value - previous), Some(value))
+    } else if let Some(max_value) = max_value {
+        (Some((max_value - previous) + (value - min_value.unwrap_or(0.0)) + 1.0), Some(value))
+    } else if let Some(min_value) = min_value {
+        (Some(value - min_value), Some(value))
+    } else {
+        (None, Some(value))
+    }
+}
+
+fn counter_deltas(series: &TimeSeries, max_value: Option<f64>, min_value: Option<f64>) -> Vec<Option<f64>> {
+    let mut previous = None;
+    series.values.iter().map(|&value| {
+        let (delta, next) = non_negative_delta(value, previous, max_value, min_value);
+        previous = next;
+        delta
+    }).collect()
+}
+
+pub fn non_negative_derivative(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.series(0, "seriesList"));
+    let max_value = try!(args.optional_number(1, "maxValue"));
+    let min_value = try!(args.optional_number(2, "minValue"));
+    Ok(map_values(series, |series| format!("nonNegativeDerivative({})", series.name), &|series| {
+        counter_deltas(series, max_value, min_value)
+    }))
+}
+
+pub fn per_second(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.series(0, "seriesList"));
+    let max_value = try!(args.optional_number(1, "maxValue"));
+    let min_value = try!(args.optional_number(2, "minValue"));
+    Ok(map_values(series, |series| format!("perSecond({})", series.name), &|series| {
+        let step = series.step as f64;
+        counter_deltas(series, max_value, min_value).into_iter().map(|delta| delta.map(|delta| delta / step)).collect()
+    }))
+}
+
+pub fn integral(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.series(0, "seriesList"));
+    Ok(map_values(series, |series| format!("integral({})", series.name), &|series| {
+        let mut total = 0.0;
+        series.values.iter().map(|value| value.map(|value| {
+            total = total + value;
+            total
+        })).collect()
+    }))
+}
+
+pub fn scale(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.series(0, "seriesList"));
+    let factor = try!(args.number(1, "factor"));
+    Ok(map_present(series, &|series| format!("scale({},{})", series.name, format_number(factor)), |value| Some(value * factor)))
+}
+
+// Turn a per-step value in to a per-`seconds` value
+pub fn scale_to_seconds(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.series(0, "seriesList"));
+    let seconds = try!(args.number(1, "seconds"));
+    Ok(map_values(series, |series| format!("scaleToSeconds({},{})", series.name, format_number(seconds)), &|series| {
+        let factor = seconds / series.step as f64;
+        series.values.iter().map(|value| value.map(|value| value * factor)).collect()
+    }))
+}
+
+pub fn offset(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.series(0, "seriesList"));
+    let factor = try!(args.number(1, "factor"));
+    Ok(map_present(series, &|series| format!("offset({},{})", series.name, format_number(factor)), |value| Some(value + factor)))
+}
+
+pub fn absolute(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.series(0, "seriesList"));
+    Ok(map_present(series, &|series| format!("absolute({})", series.name), |value| Some(value.abs())))
+}
+
+// Values which have no logarithm become gaps
+pub fn log(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.series(0, "seriesList"));
+    let base = try!(args.optional_number(1, "base")).unwrap_or(10.0);
+    Ok(map_present(series, &|series| format!("log({}, {})", series.name, format_number(base)), |value| {
+        if value <= 0.0 { None } else { Some(value.log(base)) }
+    }))
+}
+
+// Fill runs of at most `limit` gaps with the value before them
+pub fn keep_last_value(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.series(0, "seriesList"));
+    let limit = try!(args.optional_number(1, "limit")).unwrap_or(::std::f64::INFINITY);
+    Ok(map_values(series, |series| format!("keepLastValue({})", series.name), &|series| {
+        let mut values = series.values.clone();
+        let mut gaps = 0;
+        for i in 0..values.len() {
+            // Nothing is known about what came before the first value
+            if i == 0 {
+                continue;
+            }
+            if values[i].is_none() {
+                gaps = gaps + 1;
+                // A run reaching the end is filled too
+                if i + 1 < values.len() {
+                    continue;
+                }
+                if gaps as f64 <= limit {
+                    let last = values[i - gaps];
+                    for value in &mut values[i + 1 - gaps ..] {
+                        *value = last;
+                    }
+                }
+            } else {
+                if gaps > 0 && gaps as f64 <= limit {
+                    let last = values[i - gaps - 1];
+                    for value in &mut values[i - gaps .. i] {
+                        *value = last;
+                    }
+                }
+                gaps = 0;
+            }
+        }
+        values
+    }))
+}
+
+// Replace gaps with `default`. With `referenceSeries` only the gaps where
+// some reference series has a value are replaced.
+pub fn transform_null(args: &mut Args) -> Result<Vec<TimeSeries>, String> {
+    let series = try!(args.series(0, "seriesList"));
+    let default = try!(args.optional_number(1, "default")).unwrap_or(0.0);
+    let reference = match args.optional_arg(2, "referenceSeries") {
+        Some(super::Arg::SeriesList(reference)) => Some(reference),
+        Some(_) => return Err("transformNull: argument `referenceSeries` must be a series list".to_string()),
+        None => None
+    };
+
+    Ok(map_values(series, |series| format!("transformNull({},{})", series.name, format_number(default)), &|series| {
+        series.values.iter().zip(series.timestamps()).map(|(value, timestamp)| {
+            let is_referenced = match reference {
+                Some(ref reference) => reference.iter().any(|reference| {
+                    timestamp >= reference.start && timestamp < reference.end &&
+                        reference.values.get(((timestamp - reference.start) / reference.step) as usize).map_or(false, |value| value.is_some())
+                }),
+                None => true
+            };
+            match *value {
+                None if is_referenced => Some(default),
+                value => value
+            }
+        }).collect()
+    }))
+}
+
+// Fetches `seriesList` from another window and draws it over this one,
+// so it is evaluated here rather than having its arguments fetched first
+pub fn time_shift(context: &Context, call: &Call) -> Result<Vec<TimeSeries>, String> {
+    let expression = try!(call.args.first().ok_or_else(|| "timeShift: missing argument `seriesList`".to_string()));
+    let mut args = try!(context.evaluate_args(call, 1));
+    let mut shift = try!(args.string(0, "timeShift"));
+    let reset_end = try!(args.optional_boolean(1, "resetEnd")).unwrap_or(true);
+
+    // Unlike other offsets a shift is backwards unless told otherwise
+    if shift.starts_with(|c: char| c.is_digit(10)) {
+        shift = format!("-{}", shift);
+    }
+    let delta = try!(parse_interval(&shift));
+    let shifted = |time: u32| (time as i64 + delta).max(0) as u32;
+
+    let shifted_context = Context {
+        from: shifted(context.from),
+        until: shifted(context.until),
+        now: context.now,
+        fetcher: context.fetcher
+    };
+    let series = try!(shifted_context.evaluate(expression));
+
+    Ok(series.into_iter().map(|series| {
+        let start = (series.start as i64 - delta).max(0) as u32;
+        let mut values = series.values.clone();
+        if reset_end {
+            let len = values.iter().enumerate()
+                .take_while(|&(i, _)| start + i as u32 * series.step <= context.until)
+                .count();
+            values.truncate(len);
+        }
+
+        let mut moved = series.derive(format!("timeShift({}, \"{}\")", series.name, shift), values);
+        moved.start = start;
+        moved.end = start + moved.values.len() as u32 * moved.step;
+        moved
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use whisper::query::functions::{ call, Args, Arg };
+    use whisper::query::series::TimeSeries;
+
+    fn apply(function: &str, values: Vec<Option<f64>>, args: Vec<Arg>) -> TimeSeries {
+        let series = TimeSeries::new("a.b".to_string(), 60, 60, values);
+        let mut all = vec![Arg::SeriesList(vec![series])];
+        all.extend(args);
+        call(Args::new(function.to_string(), all, vec![])).unwrap().remove(0)
+    }
+
+    #[test]
+    fn test_derivatives() {
+        let counter = vec![Some(1.0), Some(4.0), None, Some(10.0), Some(2.0), Some(5.0)];
+        let derived = apply("derivative", counter.clone(), vec![]);
+        assert_eq!(derived.name, "derivative(a.b)");
+        assert_eq!(derived.values, vec![None, Some(3.0), None, None, Some(-8.0), Some(3.0)]);
+
+        let derived = apply("nonNegativeDerivative", counter.clone(), vec![]);
+        assert_eq!(derived.values, vec![None, Some(3.0), None, None, None, Some(3.0)]);
+
+        // Wrapped at 10, so 10 -> 2 is 10 -> 11 (0) -> 2
+        let derived = apply("nonNegativeDerivative", counter.clone(), vec![Arg::Number(10.0)]);
+        assert_eq!(derived.values, vec![None, Some(3.0), None, None, Some(3.0), Some(3.0)]);
+
+        // Counting from 1 to 10, so 10 -> 2 is 10 -> 1 -> 2
+        let derived = apply("nonNegativeDerivative", counter.clone(), vec![Arg::Number(10.0), Arg::Number(1.0)]);
+        assert_eq!(derived.values, vec![None, Some(3.0), None, None, Some(2.0), Some(3.0)]);
+
+        let derived = apply("perSecond", counter, vec![]);
+        assert_eq!(derived.name, "perSecond(a.b)");
+        assert_eq!(derived.values, vec![None, Some(0.05), None, None, None, Some(0.05)]);
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let values = vec![Some(-1.0), None, Some(2.0)];
+        let scaled = apply("scale", values.clone(), vec![Arg::Number(2.5)]);
+        assert_eq!(scaled.name, "scale(a.b,2.5)");
+        assert_eq!(scaled.values, vec![Some(-2.5), None, Some(5.0)]);
+
+        let shifted = apply("offset", values.clone(), vec![Arg::Number(10.0)]);
+        assert_eq!(shifted.name, "offset(a.b,10)");
+        assert_eq!(shifted.values, vec![Some(9.0), None, Some(12.0)]);
+
+        assert_eq!(apply("absolute", values.clone(), vec![]).values, vec![Some(1.0), None, Some(2.0)]);
+        assert_eq!(apply("integral", values.clone(), vec![]).values, vec![Some(-1.0), None, Some(1.0)]);
+        assert_eq!(apply("scaleToSeconds", values.clone(), vec![Arg::Number(1.0)]).values,
+            vec![Some(-1.0 / 60.0), None, Some(2.0 / 60.0)]);
+
+        let logged = apply("log", vec![Some(100.0), Some(0.0), None], vec![]);
+        assert_eq!(logged.name, "log(a.b, 10)");
+        assert_eq!(logged.values, vec![Some(2.0), None, None]);
+    }
+
+    #[test]
+    fn test_keep_last_value() {
+        let values = vec![None, Some(1.0), None, None, Some(4.0), None, None, None, Some(8.0), None];
+        assert_eq!(apply("keepLastValue", values.clone(), vec![]).values,
+            vec![None, Some(1.0), Some(1.0), Some(1.0), Some(4.0), Some(4.0), Some(4.0), Some(4.0), Some(8.0), Some(8.0)]);
+        assert_eq!(apply("keepLastValue", values, vec![Arg::Number(2.0)]).values,
+            vec![None, Some(1.0), Some(1.0), Some(1.0), Some(4.0), None, None, None, Some(8.0), Some(8.0)]);
+    }
+
+    #[test]
+    fn test_transform_null() {
+        let values = vec![Some(1.0), None, None];
+        let transformed = apply("transformNull", values.clone(), vec![]);
+        assert_eq!(transformed.name, "transformNull(a.b,0)");
+        assert_eq!(transformed.values, vec![Some(1.0), Some(0.0), Some(0.0)]);
+
+        let reference = TimeSeries::new("ref".to_string(), 60, 60, vec![None, Some(1.0), None]);
+        let transformed = apply("transformNull", values, vec![Arg::Number(-1.0), Arg::SeriesList(vec![reference])]);
+        assert_eq!(transformed.values, vec![Some(1.0), Some(-1.0), None]);
+    }
+}
@@ -0,0 +1,156 @@
+// Evaluates graphite render targets: parses the expression, fetches every
+// path it names and runs the functions wrapped around them.
+
+mod parser;
+mod series;
+mod functions;
+mod time_expr;
+
+use std::path::PathBuf;
+
+use whisper::WhisperFile;
+use whisper::finder::{ MetricFinder, has_wildcards };
+use whisper::web::metric_path;
+
+pub use self::parser::{ parse, Expression, Call };
+pub use self::series::{ TimeSeries, ConsolidationFunc, normalize };
+pub use self::functions::{ Arg, Args };
+pub use self::time_expr::{ parse_time, parse_interval };
+
+// Where the series named by a path expression come from
+pub trait Fetcher {
+    fn fetch(&self, path_expression: &str, from: u32, until: u32) -> Result<Vec<TimeSeries>, String>;
+}
+
+// Reads series out of the whisper files under `base_path`
+pub struct WhisperFetcher<'a> {
+    pub base_path: PathBuf,
+    pub finder: &'a MetricFinder
+}
+
+impl<'a> Fetcher for WhisperFetcher<'a> {
+    fn fetch(&self, path_expression: &str, from: u32, until: u32) -> Result<Vec<TimeSeries>, String> {
+        let metrics = if has_wildcards(path_expression) {
+            let nodes = try!(self.finder.find(path_expression).map_err(|e| format!("invalid target `{}`: {}", path_expression, e)));
+            nodes.into_iter().filter(|node| node.is_leaf).map(|node| node.path).collect()
+        } else {
+            vec![path_expression.to_string()]
+        };
+
+        let mut series = vec![];
+        for metric in metrics {
+            let path = try!(metric_path(&self.base_path, &metric).ok_or_else(|| format!("invalid target `{}`", metric)));
+
+            // graphite-web leaves metrics it can't find out of the response
+            let file = match WhisperFile::open(&path) {
+                Ok(file) => file,
+                Err(e) => {
+                    debug!("skipping metric {}: {}", metric, e);
+                    continue;
+                }
+            };
+
+            match file.fetch(from, Some(until)) {
+                Ok(Some(data)) => {
+                    let consolidation = ConsolidationFunc::from(file.aggregation_type());
+                    series.push(TimeSeries::from_data(metric, path_expression.to_string(), data, consolidation));
+                },
+                Ok(None) => (),
+                Err(e) => return Err(format!("unable to read `{}`: {}", metric, e))
+            }
+        }
+        Ok(series)
+    }
+}
+
+// What a target is evaluated against
+pub struct Context<'a> {
+    pub from: u32,
+    pub until: u32,
+    pub now: u32,
+    pub fetcher: &'a dyn Fetcher
+}
+
+impl<'a> Context<'a> {
+    pub fn evaluate_target(&self, target: &str) -> Result<Vec<TimeSeries>, String> {
+        let expression = try!(parse(target));
+        self.evaluate(&expression)
+    }
+
+    pub fn evaluate(&self, expression: &Expression) -> Result<Vec<TimeSeries>, String> {
+        match *expression {
+            Expression::Path(ref path) => self.fetcher.fetch(path, self.from, self.until),
+            // These fetch their series from a different window
+            Expression::Call(ref call) if call.name == "timeShift" => functions::time_shift(self, call),
+            Expression::Call(ref call) if call.name == "smartSummarize" => functions::smart_summarize(self, call),
+            Expression::Call(ref call) if call.name == "hitcount" => functions::hitcount(self, call),
+            Expression::Call(ref call) if call.name == "holtWintersForecast" => functions::holt_winters_forecast(self, call),
+            Expression::Call(ref call) if call.name == "holtWintersConfidenceBands" => functions::holt_winters_confidence_bands(self, call),
+            Expression::Call(ref call) if call.name == "holtWintersAberration" => functions::holt_winters_aberration(self, call),
+            Expression::Call(ref call) => functions::call(try!(self.evaluate_args(call, 0))),
+            _ => Err(format!("a target must be a series, not `{:?}`", expression))
+        }
+    }
+
+    // The arguments to `call` after the first `skip` positional ones
+    pub fn evaluate_args(&self, call: &Call, skip: usize) -> Result<Args, String> {
+        let args = try!(call.args.iter().skip(skip).map(|arg| self.evaluate_arg(arg)).collect());
+        let kwargs = try!(call.kwargs.iter()
+            .map(|&(ref key, ref arg)| self.evaluate_arg(arg).map(|arg| (key.clone(), arg)))
+            .collect());
+        Ok(Args::new(call.name.clone(), args, kwargs))
+    }
+
+    fn evaluate_arg(&self, expression: &Expression) -> Result<Arg, String> {
+        match *expression {
+            Expression::Number(number) => Ok(Arg::Number(number)),
+            Expression::String(ref string) => Ok(Arg::String(string.clone())),
+            Expression::Boolean(boolean) => Ok(Arg::Boolean(boolean)),
+            _ => self.evaluate(expression).map(Arg::SeriesList)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeFetcher;
+
+    impl Fetcher for FakeFetcher {
+        fn fetch(&self, path_expression: &str, from: u32, _until: u32) -> Result<Vec<TimeSeries>, String> {
+            let names = match path_expression {
+                "servers.*.load" => vec!["servers.web01.load", "servers.web02.load"],
+                "servers.web01.load" => vec!["servers.web01.load"],
+                _ => vec![]
+            };
+            Ok(names.into_iter().enumerate().map(|(i, name)| {
+                let mut series = TimeSeries::new(name.to_string(), from, 60, vec![Some(i as f64 + 1.0), None]);
+                series.path_expression = path_expression.to_string();
+                series
+            }).collect())
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let context = Context { from: 600, until: 720, now: 720, fetcher: &FakeFetcher };
+
+        let series = context.evaluate_target("servers.*.load").unwrap();
+        assert_eq!(series.len(), 2);
+
+        let summed = context.evaluate_target("sumSeries(servers.*.load, servers.nothing)").unwrap();
+        assert_eq!(summed[0].name, "sumSeries(servers.*.load)");
+        assert_eq!(summed[0].values, vec![Some(3.0), None]);
+
+        let percent = context.evaluate_target("asPercent(servers.*.load, sumSeries(servers.*.load))").unwrap();
+        assert_eq!(percent[1].values, vec![Some(2.0 / 3.0 * 100.0), None]);
+
+        let shifted = context.evaluate_target("timeShift(servers.web01.load, '1m')").unwrap();
+        assert_eq!(shifted[0].name, "timeShift(servers.web01.load, \"-1m\")");
+        assert_eq!((shifted[0].start, shifted[0].values.clone()), (600, vec![Some(1.0), None]));
+
+        assert!(context.evaluate_target("42").is_err());
+        assert!(context.evaluate_target("sumSeries(servers.*.load").is_err());
+    }
+}
@@ -0,0 +1,230 @@
+// Parses graphite target expressions like
+// `asPercent(sumSeries(servers.web*.errors), 1000)` in to an `Expression`.
+
+use std::str::Chars;
+use std::iter::Peekable;
+
+// Calls nested deeper than this are refused, since parsing and evaluating
+// both recurse once per level and a long enough target overflows the stack
+const MAX_DEPTH : usize = 64;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expression {
+    // A metric name or glob, `servers.web*.load`
+    Path(String),
+    Call(Call),
+    Number(f64),
+    String(String),
+    Boolean(bool)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Call {
+    pub name: String,
+    pub args: Vec<Expression>,
+    // `name=value` arguments, in the order given
+    pub kwargs: Vec<(String, Expression)>
+}
+
+pub fn parse(target: &str) -> Result<Expression, String> {
+    let mut parser = Parser { chars: target.chars().peekable() };
+    let expression = try!(parser.expression(0));
+    parser.skip_whitespace();
+    match parser.chars.next() {
+        None => Ok(expression),
+        Some(c) => Err(format!("unexpected `{}` in `{}`", c, target))
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().map_or(false, |c| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    // `depth` is the number of calls the expression is inside
+    fn expression(&mut self, depth: usize) -> Result<Expression, String> {
+        self.skip_whitespace();
+        match self.chars.peek().cloned() {
+            Some(quote) if quote == '"' || quote == '\'' => self.string(quote).map(Expression::String),
+            Some(_) => {
+                let token = try!(self.token());
+                self.skip_whitespace();
+                if self.chars.peek() == Some(&'(') {
+                    self.chars.next();
+                    return self.call(token, depth + 1).map(Expression::Call);
+                }
+                Ok(classify(token))
+            },
+            None => Err("expected an expression".to_string())
+        }
+    }
+
+    fn string(&mut self, quote: char) -> Result<String, String> {
+        self.chars.next();
+        let mut string = String::new();
+        loop {
+            match self.chars.next() {
+                Some(c) if c == quote => return Ok(string),
+                Some('\\') => match self.chars.next() {
+                    Some(c) => string.push(c),
+                    None => break
+                },
+                Some(c) => string.push(c),
+                None => break
+            }
+        }
+        Err(format!("unterminated string `{}{}`", quote, string))
+    }
+
+    // A function name, path, number or boolean. Commas inside `{}` belong
+    // to the path, like `servers.{web,db}*.load`.
+    fn token(&mut self) -> Result<String, String> {
+        let mut token = String::new();
+        let mut braces = 0;
+        while let Some(&c) = self.chars.peek() {
+            match c {
+                '{' => braces = braces + 1,
+                '}' if braces > 0 => braces = braces - 1,
+                ',' if braces > 0 => (),
+                '(' | ')' | ',' | '=' | '"' | '\'' => break,
+                c if c.is_whitespace() => break,
+                _ => ()
+            }
+            token.push(c);
+            self.chars.next();
+        }
+        if token.is_empty() {
+            Err(match self.chars.peek() {
+                Some(c) => format!("unexpected `{}`", c),
+                None => "expected an expression".to_string()
+            })
+        } else {
+            Ok(token)
+        }
+    }
+
+    // Everything after `name(`
+    fn call(&mut self, name: String, depth: usize) -> Result<Call, String> {
+        if depth > MAX_DEPTH {
+            return Err("target nests too deeply".to_string());
+        }
+        let mut call = Call { name: name, args: vec![], kwargs: vec![] };
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&')') {
+            self.chars.next();
+            return Ok(call);
+        }
+
+        loop {
+            let arg = try!(self.expression(depth));
+            self.skip_whitespace();
+            if self.chars.peek() == Some(&'=') {
+                self.chars.next();
+                let key = match arg {
+                    Expression::Path(key) => key,
+                    _ => return Err(format!("bad keyword argument in `{}`", call.name))
+                };
+                let value = try!(self.expression(depth));
+                call.kwargs.push((key, value));
+                self.skip_whitespace();
+            } else if !call.kwargs.is_empty() {
+                return Err(format!("positional argument follows keyword argument in `{}`", call.name));
+            } else {
+                call.args.push(arg);
+            }
+
+            match self.chars.next() {
+                Some(',') => (),
+                Some(')') => return Ok(call),
+                Some(c) => return Err(format!("unexpected `{}` in `{}`", c, call.name)),
+                None => return Err(format!("missing `)` after `{}`", call.name))
+            }
+        }
+    }
+}
+
+fn classify(token: String) -> Expression {
+    if token.eq_ignore_ascii_case("true") {
+        return Expression::Boolean(true);
+    }
+    if token.eq_ignore_ascii_case("false") {
+        return Expression::Boolean(false);
+    }
+    match token.parse::<f64>() {
+        // `inf` and `nan` are metric names, not numbers
+        Ok(number) if number.is_finite() => Expression::Number(number),
+        _ => Expression::Path(token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(path: &str) -> Expression {
+        Expression::Path(path.to_string())
+    }
+
+    fn call(name: &str, args: Vec<Expression>) -> Expression {
+        Expression::Call(Call { name: name.to_string(), args: args, kwargs: vec![] })
+    }
+
+    #[test]
+    fn test_parse_path() {
+        assert_eq!(parse("servers.web01.load"), Ok(path("servers.web01.load")));
+        assert_eq!(parse(" servers.{web,db}0[1-3].* "), Ok(path("servers.{web,db}0[1-3].*")));
+    }
+
+    #[test]
+    fn test_parse_calls() {
+        assert_eq!(parse("sumSeries(a.*.c, b.{x,y})"), Ok(call("sumSeries", vec![path("a.*.c"), path("b.{x,y}")])));
+        assert_eq!(parse("asPercent(sumSeries(a.*), 1000)"), Ok(call("asPercent", vec![
+            call("sumSeries", vec![path("a.*")]),
+            Expression::Number(1000.0)
+        ])));
+        assert_eq!(parse("alias(a.b, 'the \\'b\\' series')"), Ok(call("alias", vec![
+            path("a.b"),
+            Expression::String("the 'b' series".to_string())
+        ])));
+        assert_eq!(parse("countSeries()"), Ok(call("countSeries", vec![])));
+    }
+
+    #[test]
+    fn test_parse_kwargs() {
+        assert_eq!(parse("summarize(a.b, \"1h\", alignToFrom=true)"), Ok(Expression::Call(Call {
+            name: "summarize".to_string(),
+            args: vec![path("a.b"), Expression::String("1h".to_string())],
+            kwargs: vec![("alignToFrom".to_string(), Expression::Boolean(true))]
+        })));
+        assert!(parse("summarize(a.b, func='sum', \"1h\")").is_err());
+    }
+
+    #[test]
+    fn test_parse_numbers() {
+        assert_eq!(parse("scale(a.b, -0.5)"), Ok(call("scale", vec![path("a.b"), Expression::Number(-0.5)])));
+        assert_eq!(parse("scale(a.b, 1e3)"), Ok(call("scale", vec![path("a.b"), Expression::Number(1000.0)])));
+        assert_eq!(parse("1.b"), Ok(path("1.b")));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("sumSeries(a.b").is_err());
+        assert!(parse("sumSeries(a.b))").is_err());
+        assert!(parse("alias(a.b, 'x)").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn test_parse_depth() {
+        let nested = |depth: usize| format!("{}a.b{}", "sumSeries(".repeat(depth), ")".repeat(depth));
+        assert!(parse(&nested(MAX_DEPTH)).is_ok());
+        assert_eq!(parse(&nested(MAX_DEPTH + 1)), Err("target nests too deeply".to_string()));
+        assert_eq!(parse(&"sumSeries(".repeat(100000)), Err("target nests too deeply".to_string()));
+    }
+}
@@ -0,0 +1,234 @@
+use std::str::FromStr;
+
+use whisper::{ AggregationType, SeriesData };
+
+// How several values become one when a series is drawn at a coarser step
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ConsolidationFunc {
+    Average,
+    Sum,
+    Min,
+    Max,
+    First,
+    Last
+}
+
+impl ConsolidationFunc {
+    // None when there is nothing but gaps
+    pub fn apply(&self, values: &[f64]) -> Option<f64> {
+        if values.is_empty() {
+            return None;
+        }
+        Some(match *self {
+            ConsolidationFunc::Average => values.iter().sum::<f64>() / values.len() as f64,
+            ConsolidationFunc::Sum => values.iter().sum(),
+            ConsolidationFunc::Min => values.iter().cloned().fold(::std::f64::INFINITY, f64::min),
+            ConsolidationFunc::Max => values.iter().cloned().fold(::std::f64::NEG_INFINITY, f64::max),
+            ConsolidationFunc::First => values[0],
+            ConsolidationFunc::Last => values[values.len() - 1]
+        })
+    }
+}
+
+impl From<AggregationType> for ConsolidationFunc {
+    fn from(aggregation: AggregationType) -> ConsolidationFunc {
+        match aggregation {
+            AggregationType::Average => ConsolidationFunc::Average,
+            AggregationType::Sum => ConsolidationFunc::Sum,
+            AggregationType::Last => ConsolidationFunc::Last,
+            AggregationType::Max => ConsolidationFunc::Max,
+            AggregationType::Min => ConsolidationFunc::Min
+        }
+    }
+}
+
+impl FromStr for ConsolidationFunc {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<ConsolidationFunc, String> {
+        match name {
+            "average" | "avg" => Ok(ConsolidationFunc::Average),
+            "sum" => Ok(ConsolidationFunc::Sum),
+            "min" => Ok(ConsolidationFunc::Min),
+            "max" => Ok(ConsolidationFunc::Max),
+            "first" => Ok(ConsolidationFunc::First),
+            "last" => Ok(ConsolidationFunc::Last),
+            _ => Err(format!("unknown consolidation function `{}`", name))
+        }
+    }
+}
+
+// A named run of evenly spaced values, graphite's TimeSeries.
+// `values[i]` belongs to `start + i*step` and `end` is exclusive.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TimeSeries {
+    pub name: String,
+    // The target this series came from, used to name combined series
+    pub path_expression: String,
+    pub start: u32,
+    pub end: u32,
+    pub step: u32,
+    pub values: Vec<Option<f64>>,
+    pub consolidation: ConsolidationFunc
+}
+
+impl TimeSeries {
+    pub fn new(name: String, start: u32, step: u32, values: Vec<Option<f64>>) -> TimeSeries {
+        TimeSeries {
+            path_expression: name.clone(),
+            name: name,
+            start: start,
+            end: start + values.len() as u32 * step,
+            step: step,
+            values: values,
+            consolidation: ConsolidationFunc::Average
+        }
+    }
+
+    pub fn from_data(name: String, path_expression: String, data: SeriesData, consolidation: ConsolidationFunc) -> TimeSeries {
+        TimeSeries {
+            name: name,
+            path_expression: path_expression,
+            start: data.from,
+            end: data.from + data.values.len() as u32 * data.step,
+            step: data.step,
+            values: data.values,
+            consolidation: consolidation
+        }
+    }
+
+    // A copy of this series under another name with new values, which
+    // is what most functions return
+    pub fn derive(&self, name: String, values: Vec<Option<f64>>) -> TimeSeries {
+        TimeSeries {
+            path_expression: name.clone(),
+            name: name,
+            start: self.start,
+            end: self.start + values.len() as u32 * self.step,
+            step: self.step,
+            values: values,
+            consolidation: self.consolidation
+        }
+    }
+
+    pub fn timestamps(&self) -> Vec<u32> {
+        (0..self.values.len()).map(|i| self.start + i as u32 * self.step).collect()
+    }
+
+    // At most `max_points` values, combining neighbours with our
+    // consolidation function. Buckets line up with multiples of the new
+    // step so they don't shift about as time moves on.
+    pub fn consolidate_to(&self, max_points: usize) -> TimeSeries {
+        if max_points == 0 || self.values.len() <= max_points {
+            return self.clone();
+        }
+
+        let values_per_point = (self.values.len() + max_points - 1) / max_points;
+        let step = self.step * values_per_point as u32;
+        let start = self.start - self.start % step;
+        let len = ((self.end - start + step - 1) / step) as usize;
+
+        let mut consolidated = self.clone();
+        consolidated.values = self.consolidate(start, step, len);
+        consolidated.start = start;
+        consolidated.step = step;
+        consolidated.end = start + len as u32 * step;
+        consolidated
+    }
+
+    // Values for `len` buckets of `step` seconds from `start`, where `step`
+    // is a multiple of our own step
+    pub fn consolidate(&self, start: u32, step: u32, len: usize) -> Vec<Option<f64>> {
+        let mut buckets: Vec<Vec<f64>> = (0..len).map(|_| vec![]).collect();
+        for (timestamp, value) in self.timestamps().into_iter().zip(self.values.iter()) {
+            if let Some(value) = *value {
+                if timestamp >= start {
+                    let bucket = ((timestamp - start) / step) as usize;
+                    if bucket < len {
+                        buckets[bucket].push(value);
+                    }
+                }
+            }
+        }
+        buckets.iter().map(|values| self.consolidation.apply(values)).collect()
+    }
+}
+
+// Graphite's normalize: bring series with different steps on to the least
+// common multiple of their steps, over the union of their time ranges.
+// Returns (start, step, values for each series).
+pub fn normalize(series: &[TimeSeries]) -> Option<(u32, u32, Vec<Vec<Option<f64>>>)> {
+    if series.is_empty() {
+        return None;
+    }
+
+    let step = series.iter().map(|series| series.step).fold(1, lcm);
+    let start = series.iter().map(|series| series.start - series.start % step).min().unwrap();
+    let end = series.iter().map(|series| series.end).max().unwrap();
+    let len = ((end - start + step - 1) / step) as usize;
+
+    let values = series.iter().map(|series| {
+        if series.step == step && series.start == start {
+            let mut values = series.values.clone();
+            values.resize(len, None);
+            values
+        } else {
+            series.consolidate(start, step, len)
+        }
+    }).collect();
+    Some((start, step, values))
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+fn lcm(a: u32, b: u32) -> u32 {
+    a / gcd(a, b) * b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_consolidation_funcs() {
+        let values = [3.0, 1.0, 2.0];
+        assert_eq!(ConsolidationFunc::Average.apply(&values), Some(2.0));
+        assert_eq!(ConsolidationFunc::Sum.apply(&values), Some(6.0));
+        assert_eq!(ConsolidationFunc::Min.apply(&values), Some(1.0));
+        assert_eq!(ConsolidationFunc::Max.apply(&values), Some(3.0));
+        assert_eq!(ConsolidationFunc::First.apply(&values), Some(3.0));
+        assert_eq!(ConsolidationFunc::Last.apply(&values), Some(2.0));
+        assert_eq!(ConsolidationFunc::Sum.apply(&[]), None);
+    }
+
+    #[test]
+    fn test_normalize_mixed_steps() {
+        let fine = TimeSeries::new("fine".to_string(), 60, 60, vec![Some(1.0), Some(2.0), None, Some(4.0)]);
+        let mut coarse = TimeSeries::new("coarse".to_string(), 120, 120, vec![Some(10.0), Some(20.0)]);
+        coarse.consolidation = ConsolidationFunc::Sum;
+
+        let (start, step, values) = normalize(&[fine, coarse]).unwrap();
+        assert_eq!((start, step), (0, 120));
+        assert_eq!(values[0], vec![Some(1.0), Some(2.0), Some(4.0)]);
+        assert_eq!(values[1], vec![None, Some(10.0), Some(20.0)]);
+    }
+
+    #[test]
+    fn test_consolidate_to() {
+        let mut series = TimeSeries::new("a".to_string(), 60, 60, vec![Some(1.0), Some(2.0), Some(3.0), Some(4.0), Some(5.0)]);
+        assert_eq!(series.consolidate_to(10), series);
+
+        series.consolidation = ConsolidationFunc::Max;
+        let consolidated = series.consolidate_to(2);
+        assert_eq!((consolidated.start, consolidated.step), (0, 180));
+        assert_eq!(consolidated.values, vec![Some(2.0), Some(5.0)]);
+    }
+
+    #[test]
+    fn test_lcm() {
+        assert_eq!(lcm(10, 60), 60);
+        assert_eq!(lcm(40, 60), 120);
+    }
+}
@@ -0,0 +1,240 @@
+// Graphite's `from`/`until` grammar, after graphite-web's attime.py:
+// a time reference like `noon yesterday` or `20170224` followed by an
+// optional offset like `-3h` or `+1d12h`. Everything is UTC and relative
+// to the caller's `now`, so results are reproducible in tests.
+
+use time::{ self, Timespec };
+
+use whisper::errors::SchemaError;
+use whisper::schema::mult_str_to_num;
+
+const DAY : i64 = 24 * 60 * 60;
+
+pub fn parse_time(expression: &str, now: u32) -> Result<u32, String> {
+    let invalid = || format!("invalid time `{}`", expression);
+    let normalized : String = expression.trim().to_lowercase().chars()
+        .filter(|&c| c != '_' && c != ',' && c != ' ')
+        .collect();
+
+    if !normalized.is_empty() && normalized.chars().all(|c| c.is_digit(10)) && !is_yyyymmdd(&normalized) {
+        return normalized.parse::<u32>().map_err(|_| invalid());
+    }
+
+    // `HH:MM_YYYYMMDD`, with the underscore already gone
+    let (reference, offset) = if normalized.len() == 13 && normalized.contains(':') {
+        (&normalized[..], "")
+    } else {
+        match normalized.find(|c: char| c == '+' || c == '-') {
+            Some(split) => (&normalized[..split], &normalized[split..]),
+            None => (&normalized[..], "")
+        }
+    };
+
+    let reference = try!(parse_reference(reference, now as i64).map_err(|_| invalid()));
+    let offset = if offset.is_empty() { 0 } else { try!(parse_interval(offset)) };
+    let timestamp = reference + offset;
+    if timestamp < 0 || timestamp > ::std::u32::MAX as i64 {
+        return Err(format!("time `{}` is out of range", expression));
+    }
+    Ok(timestamp as u32)
+}
+
+// Seconds in a signed run of `<count><unit>` parts like `-6h` or `+1d12h`,
+// using retention spec units and a few more of graphite's. A bare count is seconds.
+pub fn parse_interval(interval: &str) -> Result<i64, String> {
+    let interval = interval.trim();
+    let invalid = || format!("invalid interval `{}`", interval);
+    let (sign, mut rest) = match interval.chars().next() {
+        Some('-') => (-1, &interval[1..]),
+        Some('+') => (1, &interval[1..]),
+        _ => (1, interval)
+    };
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut seconds = 0i64;
+    while !rest.is_empty() {
+        let split = rest.find(|c: char| !c.is_digit(10)).unwrap_or(rest.len());
+        let count = try!(rest[..split].parse::<i64>().map_err(|_| invalid()));
+        rest = &rest[split..];
+
+        let split = rest.find(|c: char| !c.is_alphabetic()).unwrap_or(rest.len());
+        let unit = match &rest[..split] {
+            "" if split == rest.len() && seconds == 0 => 1,
+            "" => return Err(invalid()),
+            unit => try!(unit_seconds(unit))
+        };
+        rest = &rest[split..];
+        seconds = try!(count.checked_mul(unit).and_then(|part| part.checked_add(seconds)).ok_or_else(invalid));
+    }
+    Ok(sign * seconds)
+}
+
+fn unit_seconds(unit: &str) -> Result<i64, String> {
+    match unit {
+        // graphite's spellings that retention specs don't take.
+        // Its months are always 30 days.
+        "secs" => Ok(1),
+        "mins" => Ok(60),
+        "mon" | "month" | "months" => Ok(30 * DAY),
+        unit => mult_str_to_num(unit).map(|seconds| seconds as i64).map_err(|SchemaError(why)| why)
+    }
+}
+
+// attime.py falls back to a date when eight digits look like one
+fn is_yyyymmdd(digits: &str) -> bool {
+    digits.len() == 8 && digits[..4].parse::<u32>().map(|year| year > 1900).unwrap_or(false)
+        && digits[4..6].parse::<u32>().map(|month| month < 13).unwrap_or(false)
+        && digits[6..].parse::<u32>().map(|day| day < 32).unwrap_or(false)
+}
+
+// A time of day (`HH:MM`, `9am`, `noon`, `midnight`, `teatime`) followed by a
+// day (`today`, `yesterday`, `tomorrow`, `YYYYMMDD` or `MM/DD/YY[YY]`)
+fn parse_reference(reference: &str, now: i64) -> Result<i64, ()> {
+    if reference.is_empty() || reference == "now" {
+        return Ok(now);
+    }
+
+    let mut rest = reference;
+    let (mut hour, mut minute) = (0, 0);
+    match rest.find(':') {
+        Some(split) if split > 0 && split < 3 && rest.len() >= split + 3 && rest.is_char_boundary(split + 3) => {
+            hour = try!(number(&rest[..split]));
+            minute = try!(number(&rest[split + 1 .. split + 3]));
+            rest = &rest[split + 3 ..];
+            if rest.starts_with("am") {
+                rest = &rest[2..];
+            } else if rest.starts_with("pm") {
+                hour = (hour + 12) % 24;
+                rest = &rest[2..];
+            }
+        },
+        _ => ()
+    }
+    match rest.find("am") {
+        Some(split) if split > 0 && split < 3 => {
+            hour = try!(number(&rest[..split]));
+            rest = &rest[split + 2 ..];
+        },
+        _ => ()
+    }
+    match rest.find("pm") {
+        Some(split) if split > 0 && split < 3 => {
+            hour = (try!(number(&rest[..split])) + 12) % 24;
+            rest = &rest[split + 2 ..];
+        },
+        _ => ()
+    }
+    for &(name, named_hour) in &[("noon", 12), ("midnight", 0), ("teatime", 16)] {
+        if rest.starts_with(name) {
+            hour = named_hour;
+            minute = 0;
+            rest = &rest[name.len()..];
+            break;
+        }
+    }
+    if hour > 23 || minute > 59 {
+        return Err(());
+    }
+
+    let day = match rest {
+        "" | "today" => now - now % DAY,
+        "yesterday" => now - now % DAY - DAY,
+        "tomorrow" => now - now % DAY + DAY,
+        date if date.len() == 8 && date.chars().all(|c| c.is_digit(10)) => {
+            try!(midnight_of(try!(number(&date[..4])), try!(number(&date[4..6])), try!(number(&date[6..]))))
+        },
+        date if date.matches('/').count() == 2 => {
+            let parts : Vec<i64> = try!(date.split('/').map(number).collect());
+            let mut year = parts[2];
+            if year < 1900 {
+                year += 1900;
+            }
+            if year < 1970 {
+                year += 100;
+            }
+            try!(midnight_of(year, parts[0], parts[1]))
+        },
+        _ => return Err(())
+    };
+    Ok(day + hour * 60 * 60 + minute * 60)
+}
+
+fn number(digits: &str) -> Result<i64, ()> {
+    digits.parse::<i64>().map_err(|_| ())
+}
+
+fn midnight_of(year: i64, month: i64, day: i64) -> Result<i64, ()> {
+    if year < 1970 || year > 2106 || month < 1 || month > 12 || day < 1 || day > 31 {
+        return Err(());
+    }
+    let mut tm = time::at_utc(Timespec::new(0, 0));
+    tm.tm_year = year as i32 - 1900;
+    tm.tm_mon = month as i32 - 1;
+    tm.tm_mday = day as i32;
+    Ok(tm.to_timespec().sec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Friday 2017-02-24 22:22:34 UTC
+    const NOW : u32 = 1487974954;
+    const MIDNIGHT : u32 = 1487894400;
+
+    #[test]
+    fn test_parse_relative() {
+        assert_eq!(parse_time("now", NOW), Ok(NOW));
+        assert_eq!(parse_time("", NOW), Ok(NOW));
+        assert_eq!(parse_time("-1d", NOW), Ok(NOW - 86400));
+        assert_eq!(parse_time("-3h", NOW), Ok(NOW - 3 * 3600));
+        assert_eq!(parse_time("-2weeks", NOW), Ok(NOW - 14 * 86400));
+        assert_eq!(parse_time("-5min", NOW), Ok(NOW - 300));
+        assert_eq!(parse_time("-1mon", NOW), Ok(NOW - 30 * 86400));
+        assert_eq!(parse_time("now-1d12h", NOW), Ok(NOW - 36 * 3600));
+        assert_eq!(parse_time("now+30s", NOW), Ok(NOW + 30));
+        assert!(parse_time("-6x", NOW).is_err());
+        assert!(parse_time("-", NOW).is_err());
+    }
+
+    #[test]
+    fn test_parse_absolute() {
+        assert_eq!(parse_time("1487974954", NOW), Ok(1487974954));
+        assert_eq!(parse_time("20170224", NOW), Ok(MIDNIGHT));
+        assert_eq!(parse_time("13:45_20170224", NOW), Ok(MIDNIGHT + 13 * 3600 + 45 * 60));
+        assert_eq!(parse_time("02/24/17", NOW), Ok(MIDNIGHT));
+        // Not a date, so an epoch timestamp
+        assert_eq!(parse_time("20171324", NOW), Ok(20171324));
+        assert!(parse_time("25:00_20170224", NOW).is_err());
+        // The minute ends inside a multibyte character
+        assert!(parse_time("1:aé", NOW).is_err());
+        assert!(parse_time("12:é", NOW).is_err());
+    }
+
+    #[test]
+    fn test_parse_named() {
+        assert_eq!(parse_time("midnight", NOW), Ok(MIDNIGHT));
+        assert_eq!(parse_time("noon", NOW), Ok(MIDNIGHT + 12 * 3600));
+        assert_eq!(parse_time("yesterday", NOW), Ok(MIDNIGHT - 86400));
+        assert_eq!(parse_time("noon yesterday", NOW), Ok(MIDNIGHT - 86400 + 12 * 3600));
+        assert_eq!(parse_time("midnight tomorrow", NOW), Ok(MIDNIGHT + 86400));
+        assert_eq!(parse_time("9pm", NOW), Ok(MIDNIGHT + 21 * 3600));
+        assert_eq!(parse_time("midnight-1h", NOW), Ok(MIDNIGHT - 3600));
+        assert!(parse_time("someday", NOW).is_err());
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("1d"), Ok(86400));
+        assert_eq!(parse_interval("-6h"), Ok(-21600));
+        assert_eq!(parse_interval("+30"), Ok(30));
+        assert_eq!(parse_interval("1hour30minutes"), Ok(5400));
+        assert_eq!(parse_interval("2months"), Ok(60 * 86400));
+        assert_eq!(parse_interval("10secs"), Ok(10));
+        assert!(parse_interval("d").is_err());
+        assert!(parse_interval("3x").is_err());
+        assert!(parse_interval("1h30").is_err());
+    }
+}
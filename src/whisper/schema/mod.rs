@@ -1,9 +1,13 @@
 mod retention_policy;
+mod registry;
 
 use whisper::file::STATIC_HEADER_SIZE;
 use whisper::file::ARCHIVE_INFO_SIZE;
 use whisper::errors::Result;
-pub use self::retention_policy::RetentionPolicy;
+
+use std::fmt;
+pub use self::retention_policy::{RetentionPolicy, mult_str_to_num};
+pub use self::registry::{SchemaRegistry, SchemaRule};
 
 #[derive(Debug)]
 pub struct Schema {
@@ -11,9 +15,10 @@ pub struct Schema {
 }
 
 impl Schema {
+    // Each spec may also be a comma-separated list, as in storage-schemas.conf
     pub fn new_from_retention_specs(specs: Vec<String>) -> Result<Schema> {
         let retention_policies: Result<Vec<RetentionPolicy>> =
-            specs.iter().fold(Ok(vec![]), |policies_result, next| {
+            specs.iter().flat_map(|specs| specs.split(',')).fold(Ok(vec![]), |policies_result, next| {
                 policies_result
                     .and_then(|mut policies| RetentionPolicy::spec_to_retention_policy(next)
                         .map(|policy| { policies.push(policy); policies })
@@ -23,6 +28,11 @@ impl Schema {
         retention_policies.map(|policies| Schema { retention_policies: policies })
     }
 
+    // The canonical form of every policy, like `10s:1d,1m:30d`
+    pub fn to_spec(&self) -> String {
+        self.to_string()
+    }
+
     pub fn header_size_on_disk(&self) -> u32 {
         STATIC_HEADER_SIZE as u32 +
         (ARCHIVE_INFO_SIZE*self.retention_policies.len()) as u32
@@ -46,6 +56,13 @@ impl Schema {
     }
 }
 
+impl fmt::Display for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let specs : Vec<String> = self.retention_policies.iter().map(|policy| policy.to_spec()).collect();
+        write!(f, "{}", specs.join(","))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -79,4 +96,14 @@ mod tests {
         assert_eq!(little_schema.size_on_disk(), expected);
     }
 
+    #[test]
+    fn test_spec_lists() {
+        let specs = vec!["10sec:6hours, 1min:7days".to_string(), "1hour:5years".to_string()];
+        let schema = Schema::new_from_retention_specs(specs).unwrap();
+        assert_eq!(schema.retention_policies.len(), 3);
+        assert_eq!(schema.to_spec(), "10s:6h,1m:1w,1h:5y");
+
+        let reparsed = Schema::new_from_retention_specs(vec![schema.to_spec()]).unwrap();
+        assert_eq!(reparsed.to_string(), schema.to_string());
+    }
 }
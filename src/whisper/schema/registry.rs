@@ -0,0 +1,167 @@
+use whisper::errors::{SchemaError, Result};
+use super::Schema;
+
+use regex::Regex;
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// One `[section]` of a storage-schemas.conf file
+#[derive(Debug)]
+pub struct SchemaRule {
+    pub name: String,
+    pub pattern: Regex,
+    pub schema: Schema
+}
+
+// Resolves the schema a new whisper file should be created with,
+// the way carbon-cache consults storage-schemas.conf. Rules are
+// checked in file order and the first matching pattern wins.
+#[derive(Debug)]
+pub struct SchemaRegistry {
+    pub rules: Vec<SchemaRule>
+}
+
+impl SchemaRegistry {
+    // A registry which hands out the same schema for every metric
+    pub fn new(default: Schema) -> SchemaRegistry {
+        SchemaRegistry {
+            rules: vec![SchemaRule {
+                name: "default".to_string(),
+                pattern: Regex::new(".*").unwrap(),
+                schema: default
+            }]
+        }
+    }
+
+    pub fn from_config_file<P>(path: P) -> Result<SchemaRegistry>
+        where P: AsRef<Path> {
+        let mut contents = String::new();
+        try!(File::open(path.as_ref())
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|e| SchemaError(format!("Unable to read '{}'\nCaused by: {}", path.as_ref().display(), e))));
+        SchemaRegistry::from_config_str(&contents)
+    }
+
+    pub fn from_config_str(config: &str) -> Result<SchemaRegistry> {
+        let mut rules = vec![];
+        let mut section: Option<(String, Option<String>, Option<String>)> = None;
+
+        for (index, raw_line) in config.lines().enumerate() {
+            let line = raw_line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                if let Some(finished) = section.take() {
+                    rules.push(try!(SchemaRegistry::build_rule(finished)));
+                }
+                section = Some((line[1..line.len()-1].trim().to_string(), None, None));
+                continue;
+            }
+
+            let (key, value) = match line.find('=') {
+                Some(split) => (line[..split].trim(), line[split+1..].trim().to_string()),
+                None => return Err(SchemaError(format!("Line {} is not a `key = value` pair: '{}'", index + 1, line)))
+            };
+
+            match section {
+                Some((_, ref mut pattern, ref mut retentions)) => match key {
+                    "pattern" => *pattern = Some(value),
+                    "retentions" => *retentions = Some(value),
+                    // priority, xFilesFactor, etc. are not schema settings
+                    _ => ()
+                },
+                None => return Err(SchemaError(format!("Line {} appears before any [section]: '{}'", index + 1, line)))
+            }
+        }
+
+        if let Some(finished) = section.take() {
+            rules.push(try!(SchemaRegistry::build_rule(finished)));
+        }
+
+        Ok(SchemaRegistry { rules: rules })
+    }
+
+    fn build_rule(section: (String, Option<String>, Option<String>)) -> Result<SchemaRule> {
+        match section {
+            (name, Some(pattern), Some(retentions)) => {
+                let regex = try!(Regex::new(&pattern)
+                    .map_err(|e| SchemaError(format!("Section [{}] has an invalid pattern '{}'\nCaused by: {}", name, pattern, e))));
+                Schema::new_from_retention_specs(vec![retentions]).map(|schema| SchemaRule {
+                    name: name,
+                    pattern: regex,
+                    schema: schema
+                })
+            },
+            (name, None, _) => Err(SchemaError(format!("Section [{}] is missing a pattern", name))),
+            (name, _, None) => Err(SchemaError(format!("Section [{}] is missing retentions", name)))
+        }
+    }
+
+    pub fn schema_for(&self, metric_name: &str) -> Option<&Schema> {
+        self.rules.iter()
+            .find(|rule| rule.pattern.is_match(metric_name))
+            .map(|rule| &rule.schema)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_CONFIG : &'static str = "
+# Schema definitions for Whisper files. Entries are scanned in order,
+# and first match wins.
+[carbon]
+pattern = ^carbon\\.
+retentions = 60:90d
+
+[collectd]
+pattern = ^collectd\\.
+retentions = 10s:1d, 1m:30d
+
+[default_1min_for_1day]
+pattern = .*
+retentions = 60s:1d
+";
+
+    #[test]
+    fn test_first_match_wins() {
+        let registry = SchemaRegistry::from_config_str(SAMPLE_CONFIG).unwrap();
+        assert_eq!(registry.rules.len(), 3);
+
+        let carbon = registry.schema_for("carbon.agents.a.cpuUsage").unwrap();
+        assert_eq!(carbon.retention_policies.len(), 1);
+        assert_eq!(carbon.retention_policies[0].precision, 60);
+        assert_eq!(carbon.max_retention(), 90*24*60*60);
+
+        let collectd = registry.schema_for("collectd.xle.load.load.shortterm").unwrap();
+        assert_eq!(collectd.retention_policies.len(), 2);
+        assert_eq!(collectd.retention_policies[1].precision, 60);
+
+        let other = registry.schema_for("home.pets.bears.lua.purr_volume").unwrap();
+        assert_eq!(other.max_retention(), 24*60*60);
+    }
+
+    #[test]
+    fn test_no_match() {
+        let registry = SchemaRegistry::from_config_str("[carbon]\npattern = ^carbon\\.\nretentions = 60:90d\n").unwrap();
+        assert!(registry.schema_for("collectd.xle.load").is_none());
+    }
+
+    #[test]
+    fn test_missing_retentions() {
+        let registry = SchemaRegistry::from_config_str("[carbon]\npattern = ^carbon\\.\n");
+        assert_eq!(format!("{}", registry.unwrap_err()), "Error: Invalid schema: Section [carbon] is missing retentions\n");
+    }
+
+    #[test]
+    fn test_default_registry() {
+        let schema = Schema::new_from_retention_specs(vec!["1s:60s".to_string()]).unwrap();
+        let registry = SchemaRegistry::new(schema);
+        assert_eq!(registry.schema_for("anything.at.all").unwrap().max_retention(), 60);
+    }
+}
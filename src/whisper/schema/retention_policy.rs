@@ -5,9 +5,21 @@ use whisper::errors::{SchemaError, Result};
 use byteorder::{ BigEndian, WriteBytesExt };
 use regex;
 
+use std::fmt;
 use std::io::{ BufWriter, Write };
 use std::fs::File;
 
+// whisper.py's UnitMultipliers. A unit may be written as any prefix of its
+// name, so `s`, `sec` and `seconds` are all seconds and `m` is minutes.
+const UNITS : [(&'static str, u32); 6] = [
+    ("seconds", 1),
+    ("minutes", 60),
+    ("hours", 60*60),
+    ("days", 60*60*24),
+    ("weeks", 60*60*24*7),
+    ("years", 60*60*24*365)
+];
+
 // A RetentionPolicy is the abstract form of an ArchiveInfo
 // It does not know it's position in the file. Should it just
 // be collapsed in to ArchiveInfo? Possibly.
@@ -21,13 +33,19 @@ impl RetentionPolicy {
     pub fn spec_to_retention_policy(spec: &str) -> Result<RetentionPolicy> {
         // TODO: regex should be built as const using macro regex!
         // but that's only available in nightlies.
-        let retention_matcher = regex::Regex::new({r"^(\d+)([smhdwy])?:(\d+)([smhdwy])?$"}).unwrap();
+        let spec = spec.trim();
+        let retention_matcher = regex::Regex::new({r"^(\d+)([a-z]+)?:(\d+)([a-z]+)?$"}).unwrap();
         match retention_matcher.captures(spec) {
-            Some(regex_match) => retention_capture_to_pair(spec, regex_match),
-            None => Err(SchemaError(format!("Policy '{}' is in an invalid format", spec)))
+            Some(ref regex_match) if has_valid_units(regex_match) => retention_capture_to_pair(spec, regex_match),
+            _ => Err(SchemaError(format!("Policy '{}' is in an invalid format", spec)))
         }
     }
 
+    // The canonical form, like `10s:1d`: both sides in their largest whole unit
+    pub fn to_spec(&self) -> String {
+        self.to_string()
+    }
+
     // TODO how do we guarantee even divisibility?
     pub fn points(&self) -> u32 {
         self.retention / self.precision
@@ -56,7 +74,24 @@ impl RetentionPolicy {
     }
 }
 
-fn retention_capture_to_pair(original_spec: &str, regex_match: regex::Captures) -> Result<RetentionPolicy> {
+impl fmt::Display for RetentionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", duration_spec(self.precision), duration_spec(self.retention))
+    }
+}
+
+fn duration_spec(seconds: u32) -> String {
+    let &(name, unit) = UNITS.iter().rev()
+        .find(|&&(_, unit)| seconds % unit == 0)
+        .unwrap_or(&UNITS[0]);
+    format!("{}{}", seconds / unit, &name[..1])
+}
+
+fn has_valid_units(regex_match: &regex::Captures) -> bool {
+    [2, 4].iter().all(|&group| regex_match.get(group).map(|m| mult_str_to_num(m.as_str()).is_ok()).unwrap_or(true))
+}
+
+fn retention_capture_to_pair(original_spec: &str, regex_match: &regex::Captures) -> Result<RetentionPolicy> {
     let precision_opt = regex_match.get(1).map(|m| m.as_str());
     let precision_mult = regex_match.get(2).map(|m| m.as_str()).unwrap_or("s");
     let retention_opt = regex_match.get(3).map(|m| m.as_str());
@@ -97,17 +132,11 @@ fn retention_capture_to_pair(original_spec: &str, regex_match: regex::Captures)
     }
 }
 
-fn mult_str_to_num(mult_str: &str) -> Result<u32> {
-    // TODO: is this exactly how whisper does it?
-    match mult_str {
-        "s" => Ok(1),
-        "m" => Ok(60),
-        "h" => Ok(60*60),
-        "d" => Ok(60*60*24),
-        "w" => Ok(60*60*24*7),
-        "y" => Ok(60*60*24*365),
-        //Regex should ensure this is impossible
-        _ => Err(SchemaError(format!("Unrecognized time multiplier specified: '{}'", mult_str)))
+pub fn mult_str_to_num(mult_str: &str) -> Result<u32> {
+    let whisper_unit = UNITS.iter().find(|&&(name, _)| !mult_str.is_empty() && name.starts_with(mult_str));
+    match whisper_unit {
+        Some(&(_, unit)) => Ok(unit),
+        None => Err(SchemaError(format!("Unrecognized time multiplier specified: '{}'", mult_str)))
     }
 }
 
@@ -157,6 +186,41 @@ mod tests {
         assert_eq!(retention_policy.retention, expected.retention);
     }
 
+    #[test]
+    fn test_spec_with_long_units() {
+        let cases = [
+            ("10sec:1min", 10, 60),
+            ("1min:5minutes", 60, 5*60),
+            ("1hour:7days", 60*60, 7*60*60*24),
+            ("1h:2weeks", 60*60, 2*60*60*24*7),
+            ("1d:1year", 60*60*24, 60*60*24*365),
+            (" 1m:60 ", 60, 60*60)
+        ];
+        for &(spec, precision, retention) in cases.iter() {
+            let retention_policy = RetentionPolicy::spec_to_retention_policy(spec).unwrap();
+            assert_eq!((retention_policy.precision, retention_policy.retention), (precision, retention), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn test_to_spec() {
+        let specs = [("10sec:1d", "10s:1d"), ("60:1440", "1m:1d"), ("90s:7days", "90s:1w"), ("1h:365d", "1h:1y")];
+        for &(spec, canonical) in specs.iter() {
+            assert_eq!(RetentionPolicy::spec_to_retention_policy(spec).unwrap().to_spec(), canonical);
+        }
+    }
+
+    #[test]
+    fn test_mult_str_prefixes() {
+        assert_eq!(mult_str_to_num("m").unwrap(), 60);
+        assert_eq!(mult_str_to_num("mi").unwrap(), 60);
+        assert_eq!(mult_str_to_num("weeks").unwrap(), 60*60*24*7);
+        assert!(mult_str_to_num("").is_err());
+        assert!(mult_str_to_num("secondss").is_err());
+        // graphite's months are only for time expressions, as python whisper has none
+        assert!(RetentionPolicy::spec_to_retention_policy("1m:6months").is_err());
+    }
+
     #[test]
     fn test_invalid_empty_spec() {
         let spec = "";
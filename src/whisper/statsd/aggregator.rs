@@ -0,0 +1,233 @@
+// Collects samples between flushes and turns them in to the points etsy
+// statsd's graphite backend sends, with its legacy namespace:
+//
+//   stats.<counter>                per second rate
+//   stats_counts.<counter>         count
+//   stats.gauges.<gauge>           current value
+//   stats.timers.<timer>.<stat>    upper, lower, mean, median, std, sum, ...
+//   stats.sets.<set>.count         unique values
+//
+// As in statsd, counters, timers and sets seen once keep being written (as
+// zero) after they go quiet, and gauges keep their last value.
+
+use std::collections::{ BTreeMap, BTreeSet };
+
+use whisper::NamedPoint;
+use super::sample::{ self, Sample, Metric };
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatsdConfig {
+    // Seconds between flushes, which counter rates are per
+    pub flush_interval: u32,
+    // Percentiles of each timer's values to summarise, like statsd's percentThreshold
+    pub percent_thresholds: Vec<f64>
+}
+
+impl Default for StatsdConfig {
+    fn default() -> StatsdConfig {
+        StatsdConfig { flush_interval: 10, percent_thresholds: vec![90.0] }
+    }
+}
+
+// Comma separated percentiles, like `90,99.9`
+pub fn parse_percent_thresholds(thresholds: &str) -> Result<Vec<f64>, String> {
+    thresholds.split(',').map(|threshold| {
+        match threshold.trim().parse::<f64>() {
+            Ok(threshold) if threshold > 0.0 && threshold <= 100.0 => Ok(threshold),
+            _ => Err(format!("percent threshold `{}` is not between 0 and 100", threshold))
+        }
+    }).collect()
+}
+
+pub struct Aggregator {
+    config: StatsdConfig,
+    counters: BTreeMap<String, f64>,
+    gauges: BTreeMap<String, f64>,
+    // Values, and how many samples they stand for once sample rates are applied
+    timers: BTreeMap<String, (Vec<f64>, f64)>,
+    sets: BTreeMap<String, BTreeSet<String>>
+}
+
+impl Aggregator {
+    pub fn new(config: StatsdConfig) -> Aggregator {
+        Aggregator {
+            config: config,
+            counters: BTreeMap::new(),
+            gauges: BTreeMap::new(),
+            timers: BTreeMap::new(),
+            sets: BTreeMap::new()
+        }
+    }
+
+    // A datagram of newline separated lines. Bad lines are skipped and
+    // counted in `statsd.bad_lines_seen`.
+    pub fn add_packet(&mut self, packet: &str) {
+        self.add(Sample { name: "statsd.packets_received".to_string(), metric: Metric::Counter(1.0) });
+        for line in packet.lines().map(|line| line.trim()).filter(|line| !line.is_empty()) {
+            match sample::parse_line(line) {
+                Ok(samples) => for sample in samples { self.add(sample) },
+                Err(why) => {
+                    warn!("dropping statsd line: {}", why);
+                    self.add(Sample { name: "statsd.bad_lines_seen".to_string(), metric: Metric::Counter(1.0) });
+                }
+            }
+        }
+    }
+
+    pub fn add(&mut self, sample: Sample) {
+        match sample.metric {
+            Metric::Counter(count) => *self.counters.entry(sample.name).or_insert(0.0) += count,
+            Metric::Gauge(value) => { self.gauges.insert(sample.name, value); },
+            Metric::GaugeDelta(delta) => *self.gauges.entry(sample.name).or_insert(0.0) += delta,
+            Metric::Timer(value, count) => {
+                let timer = self.timers.entry(sample.name).or_insert_with(|| (vec![], 0.0));
+                timer.0.push(value);
+                timer.1 += count;
+            },
+            Metric::Set(value) => { self.sets.entry(sample.name).or_insert_with(BTreeSet::new).insert(value); }
+        }
+    }
+
+    // Everything gathered since the last flush, stamped with `timestamp`
+    pub fn flush(&mut self, timestamp: u32) -> Vec<NamedPoint> {
+        let interval = self.config.flush_interval as f64;
+        let mut points = vec![];
+        {
+            let mut point = |name: String, value: f64| points.push(NamedPoint::new(name, timestamp, value));
+
+            for (name, count) in &mut self.counters {
+                point(format!("stats.{}", name), *count / interval);
+                point(format!("stats_counts.{}", name), *count);
+                *count = 0.0;
+            }
+
+            for (name, value) in &self.gauges {
+                point(format!("stats.gauges.{}", name), *value);
+            }
+
+            for (name, timer) in &mut self.timers {
+                for (stat, value) in timer_stats(&mut timer.0, timer.1, interval, &self.config.percent_thresholds) {
+                    point(format!("stats.timers.{}.{}", name, stat), value);
+                }
+                *timer = (vec![], 0.0);
+            }
+
+            for (name, set) in &mut self.sets {
+                point(format!("stats.sets.{}.count", name), set.len() as f64);
+                set.clear();
+            }
+        }
+        points
+    }
+}
+
+// statsd's processedMetrics for one timer
+fn timer_stats(values: &mut [f64], count: f64, interval: f64, thresholds: &[f64]) -> Vec<(String, f64)> {
+    let mut stats = vec![("count".to_string(), count), ("count_ps".to_string(), count / interval)];
+    if values.is_empty() {
+        return stats;
+    }
+
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(::std::cmp::Ordering::Equal));
+    let len = values.len();
+    let cumulative: Vec<f64> = values.iter().scan(0.0, |sum, value| { *sum += *value; Some(*sum) }).collect();
+    let (lower, upper, sum) = (values[0], values[len - 1], cumulative[len - 1]);
+    let mean = sum / len as f64;
+
+    for threshold in thresholds {
+        let in_threshold = if len > 1 { (threshold / 100.0 * len as f64).round() as usize } else { len };
+        if in_threshold == 0 {
+            continue;
+        }
+        let threshold_sum = cumulative[in_threshold - 1];
+        let suffix = threshold.to_string().replace('.', "_");
+        stats.push((format!("count_{}", suffix), in_threshold as f64));
+        stats.push((format!("mean_{}", suffix), threshold_sum / in_threshold as f64));
+        stats.push((format!("upper_{}", suffix), values[in_threshold - 1]));
+        stats.push((format!("sum_{}", suffix), threshold_sum));
+    }
+
+    let sum_squares: f64 = values.iter().map(|value| value * value).sum();
+    let variance = values.iter().map(|value| (value - mean) * (value - mean)).sum::<f64>() / len as f64;
+    let median = if len % 2 == 1 { values[len / 2] } else { (values[len / 2 - 1] + values[len / 2]) / 2.0 };
+
+    stats.push(("std".to_string(), variance.sqrt()));
+    stats.push(("upper".to_string(), upper));
+    stats.push(("lower".to_string(), lower));
+    stats.push(("sum".to_string(), sum));
+    stats.push(("sum_squares".to_string(), sum_squares));
+    stats.push(("mean".to_string(), mean));
+    stats.push(("median".to_string(), median));
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value_of(points: &[NamedPoint], name: &str) -> Option<f64> {
+        points.iter().find(|point| point.name() == name).map(|point| point.point().1)
+    }
+
+    #[test]
+    fn test_counters_and_gauges() {
+        let mut aggregator = Aggregator::new(StatsdConfig::default());
+        aggregator.add_packet("hits:5|c\nhits:1|c|@0.1\nload:3|g\nload:+2|g\nnonsense\n");
+
+        let points = aggregator.flush(1434598520);
+        assert!(points.iter().all(|point| point.point().0 == 1434598520));
+        assert_eq!(value_of(&points, "stats_counts.hits"), Some(15.0));
+        assert_eq!(value_of(&points, "stats.hits"), Some(1.5));
+        assert_eq!(value_of(&points, "stats.gauges.load"), Some(5.0));
+        assert_eq!(value_of(&points, "stats_counts.statsd.bad_lines_seen"), Some(1.0));
+        assert_eq!(value_of(&points, "stats_counts.statsd.packets_received"), Some(1.0));
+
+        // Counters reset and gauges hold between flushes
+        aggregator.add_packet("load:-1|g");
+        let points = aggregator.flush(1434598530);
+        assert_eq!(value_of(&points, "stats_counts.hits"), Some(0.0));
+        assert_eq!(value_of(&points, "stats.gauges.load"), Some(4.0));
+    }
+
+    #[test]
+    fn test_timers() {
+        let config = StatsdConfig { flush_interval: 10, percent_thresholds: vec![90.0, 50.0] };
+        let mut aggregator = Aggregator::new(config);
+        for value in 1..11 {
+            aggregator.add_packet(&format!("query:{}|ms", value));
+        }
+
+        let points = aggregator.flush(1434598520);
+        assert_eq!(value_of(&points, "stats.timers.query.count"), Some(10.0));
+        assert_eq!(value_of(&points, "stats.timers.query.count_ps"), Some(1.0));
+        assert_eq!(value_of(&points, "stats.timers.query.upper"), Some(10.0));
+        assert_eq!(value_of(&points, "stats.timers.query.lower"), Some(1.0));
+        assert_eq!(value_of(&points, "stats.timers.query.mean"), Some(5.5));
+        assert_eq!(value_of(&points, "stats.timers.query.median"), Some(5.5));
+        assert_eq!(value_of(&points, "stats.timers.query.sum"), Some(55.0));
+        assert_eq!(value_of(&points, "stats.timers.query.upper_90"), Some(9.0));
+        assert_eq!(value_of(&points, "stats.timers.query.mean_90"), Some(5.0));
+        assert_eq!(value_of(&points, "stats.timers.query.sum_50"), Some(15.0));
+        assert!((value_of(&points, "stats.timers.query.std").unwrap() - 2.8722813232690143).abs() < 1e-12);
+
+        // A quiet timer only reports its count
+        let points = aggregator.flush(1434598530);
+        assert_eq!(value_of(&points, "stats.timers.query.count"), Some(0.0));
+        assert_eq!(value_of(&points, "stats.timers.query.upper"), None);
+    }
+
+    #[test]
+    fn test_sets() {
+        let mut aggregator = Aggregator::new(StatsdConfig::default());
+        aggregator.add_packet("users:alice|s\nusers:bob|s\nusers:alice|s");
+        assert_eq!(value_of(&aggregator.flush(1434598520), "stats.sets.users.count"), Some(2.0));
+        assert_eq!(value_of(&aggregator.flush(1434598530), "stats.sets.users.count"), Some(0.0));
+    }
+
+    #[test]
+    fn test_percent_thresholds() {
+        assert_eq!(parse_percent_thresholds("90, 99.9"), Ok(vec![90.0, 99.9]));
+        assert!(parse_percent_thresholds("0").is_err());
+        assert!(parse_percent_thresholds("90,x").is_err());
+    }
+}
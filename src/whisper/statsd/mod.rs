@@ -0,0 +1,82 @@
+// A StatsD server: aggregates counters, gauges, timers and sets received
+// over UDP and sends the results on every flush interval, so they reach the
+// whisper files the same way carbon points do.
+
+mod aggregator;
+mod sample;
+
+pub use self::aggregator::{ Aggregator, StatsdConfig, parse_percent_thresholds };
+pub use self::sample::{ Sample, Metric, parse_line };
+
+use whisper::NamedPoint;
+use whisper::carbon::Shutdown;
+
+use std::io::{ self, ErrorKind };
+use std::net::UdpSocket;
+use std::str;
+use std::sync::mpsc::Sender;
+use std::thread::{ self, JoinHandle };
+use std::time::{ Duration, Instant };
+use time;
+
+// How long a blocked socket waits before checking for a flush or shutdown
+const POLL_INTERVAL_MS : u64 = 250;
+
+const MAX_DATAGRAM_SIZE : usize = 65535;
+
+// Receives statsd datagrams until shutdown is requested, sending the
+// aggregated points every flush interval and once more on the way out
+pub fn spawn_udp_listener(socket: UdpSocket, config: StatsdConfig, sink: Sender<Vec<NamedPoint>>, shutdown: Shutdown) -> io::Result<JoinHandle<()>> {
+    try!(socket.set_read_timeout(Some(Duration::from_millis(POLL_INTERVAL_MS))));
+    let flush_interval = Duration::from_secs(config.flush_interval as u64);
+
+    Ok(thread::spawn(move || {
+        let mut aggregator = Aggregator::new(config);
+        let mut last_flush = Instant::now();
+        let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+        while !shutdown.is_requested() {
+            match socket.recv_from(&mut buf) {
+                Ok((len, peer)) => match str::from_utf8(&buf[..len]) {
+                    Ok(packet) => aggregator.add_packet(packet),
+                    Err(_) => warn!("dropping statsd datagram with invalid utf8 from {}", peer)
+                },
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => (),
+                Err(e) => warn!("unable to receive datagram: {}", e)
+            }
+
+            if last_flush.elapsed() >= flush_interval {
+                last_flush = Instant::now();
+                let _ = sink.send(aggregator.flush(time::get_time().sec as u32));
+            }
+        }
+        let _ = sink.send(aggregator.flush(time::get_time().sec as u32));
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::UdpSocket;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_udp_listener() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let (sink, points) = mpsc::channel();
+        let shutdown = Shutdown::new();
+        let config = StatsdConfig { flush_interval: 1, percent_thresholds: vec![90.0] };
+        let handle = spawn_udp_listener(socket, config, sink, shutdown.clone()).unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.send_to(b"hits:2|c\nhits:3|c", addr).unwrap();
+
+        let flushed = points.recv().unwrap();
+        let hits = flushed.iter().find(|point| point.name() == "stats_counts.hits").unwrap();
+        assert_eq!(hits.point().1, 5.0);
+
+        shutdown.request();
+        handle.join().unwrap();
+    }
+}
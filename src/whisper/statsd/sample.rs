@@ -0,0 +1,106 @@
+// StatsD's `name:value|type[|@rate]` lines. Several values for one name
+// can share a line as `name:1|c:250|ms`.
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Metric {
+    // `c`, counted as value / sample rate
+    Counter(f64),
+    // `g` with a plain value
+    Gauge(f64),
+    // `g` with a leading `+` or `-`, added to the current value
+    GaugeDelta(f64),
+    // `ms` (or `h`), and how many samples this one stands for
+    Timer(f64, f64),
+    // `s`, unique values counted per flush
+    Set(String)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Sample {
+    pub name: String,
+    pub metric: Metric
+}
+
+pub fn parse_line(line: &str) -> Result<Vec<Sample>, String> {
+    let mut bits = line.split(':');
+    let name = sanitize_name(bits.next().unwrap_or(""));
+    if name.is_empty() {
+        return Err(format!("`{}` has no metric name", line));
+    }
+
+    let mut samples = vec![];
+    for bit in bits {
+        let fields: Vec<&str> = bit.split('|').collect();
+        if fields.len() < 2 {
+            return Err(format!("`{}` in `{}` is not a value|type pair", bit, line));
+        }
+        let (value, kind) = (fields[0], fields[1]);
+
+        // Anything after the type other than a sample rate is ignored
+        let mut rate = 1.0;
+        for field in &fields[2..] {
+            if field.starts_with('@') {
+                rate = match field[1..].parse::<f64>() {
+                    Ok(rate) if rate > 0.0 && rate <= 1.0 => rate,
+                    _ => return Err(format!("sample rate `{}` in `{}` is not between 0 and 1", field, line))
+                };
+            }
+        }
+
+        let number = || value.parse::<f64>().map_err(|_| format!("value `{}` in `{}` is not a number", value, line));
+        let metric = match kind {
+            "c" => Metric::Counter(try!(number()) / rate),
+            "g" if value.starts_with('+') || value.starts_with('-') => Metric::GaugeDelta(try!(number())),
+            "g" => Metric::Gauge(try!(number())),
+            "ms" | "h" => Metric::Timer(try!(number()), 1.0 / rate),
+            "s" => Metric::Set(value.to_string()),
+            _ => return Err(format!("unknown metric type `{}` in `{}`", kind, line))
+        };
+        samples.push(Sample { name: name.clone(), metric: metric });
+    }
+
+    if samples.is_empty() {
+        return Err(format!("`{}` has no values", line));
+    }
+    Ok(samples)
+}
+
+// What statsd keeps of a name: whitespace becomes `_`, `/` becomes `-` and
+// anything else outside `[a-zA-Z0-9_.-]` is dropped
+fn sanitize_name(name: &str) -> String {
+    name.chars().filter_map(|c| match c {
+        _ if c.is_whitespace() => Some('_'),
+        '/' => Some('-'),
+        _ if c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-' => Some(c),
+        _ => None
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(name: &str, metric: Metric) -> Sample {
+        Sample { name: name.to_string(), metric: metric }
+    }
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(parse_line("gorets:1|c|@0.1"), Ok(vec![sample("gorets", Metric::Counter(10.0))]));
+        assert_eq!(parse_line("gaugor:-10|g"), Ok(vec![sample("gaugor", Metric::GaugeDelta(-10.0))]));
+        assert_eq!(parse_line("gaugor:333|g"), Ok(vec![sample("gaugor", Metric::Gauge(333.0))]));
+        assert_eq!(parse_line("uniques:765|s"), Ok(vec![sample("uniques", Metric::Set("765".to_string()))]));
+        assert_eq!(parse_line("my app/glork:320|ms|@0.5"), Ok(vec![sample("my_app-glork", Metric::Timer(320.0, 2.0))]));
+        assert_eq!(parse_line("multi:1|c:250|ms"), Ok(vec![sample("multi", Metric::Counter(1.0)), sample("multi", Metric::Timer(250.0, 1.0))]));
+    }
+
+    #[test]
+    fn test_parse_line_errors() {
+        assert!(parse_line("gorets").is_err());
+        assert!(parse_line(":1|c").is_err());
+        assert!(parse_line("gorets:1").is_err());
+        assert!(parse_line("gorets:x|c").is_err());
+        assert!(parse_line("gorets:1|q").is_err());
+        assert!(parse_line("gorets:1|c|@2").is_err());
+    }
+}
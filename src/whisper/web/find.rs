@@ -0,0 +1,127 @@
+use rustc_serialize::json::{ Json, ToJson };
+
+use std::collections::BTreeMap;
+
+use whisper::finder::{ MetricFinder, MetricNode };
+use super::http::{ Request, Response };
+
+pub fn find(finder: &MetricFinder, request: &Request) -> Response {
+    let query = match request.param("query") {
+        Some(query) if !query.is_empty() => query,
+        _ => return Response::text(400, "missing parameter `query`\n".to_string())
+    };
+
+    let format = request.param("format").unwrap_or("treejson");
+    // The completer is sent what has been typed so far
+    let query = if format == "completer" { completer_query(query) } else { query.to_string() };
+
+    let nodes = match finder.find(&query) {
+        Ok(nodes) => nodes,
+        Err(e) => return Response::text(400, format!("{}\n", e))
+    };
+
+    match format {
+        "treejson" => Response::json(tree_json(&query, &nodes).to_string()),
+        "json" => Response::json(Json::Array(nodes.iter().map(node_json).collect()).to_string()),
+        "completer" => Response::json(completer_json(&nodes).to_string()),
+        format => Response::text(400, format!("unsupported format `{}`\n", format))
+    }
+}
+
+fn node_json(node: &MetricNode) -> Json {
+    let mut object = BTreeMap::new();
+    object.insert("path".to_string(), Json::String(node.path.clone()));
+    object.insert("is_leaf".to_string(), Json::Boolean(node.is_leaf));
+    Json::Object(object)
+}
+
+// Same as graphite-web: `a..b` completes the middle node and the last
+// node is always a prefix
+fn completer_query(query: &str) -> String {
+    let mut query = query.replace("..", "*.");
+    if !query.ends_with('*') {
+        query.push('*');
+    }
+    query
+}
+
+fn completer_json(nodes: &[MetricNode]) -> Json {
+    let metrics = nodes.iter().map(|node| {
+        let mut object = BTreeMap::new();
+        let path = if node.is_leaf { node.path.clone() } else { format!("{}.", node.path) };
+        object.insert("path".to_string(), Json::String(path));
+        object.insert("name".to_string(), node.name().to_json());
+        object.insert("is_leaf".to_string(), Json::String(if node.is_leaf { "1" } else { "0" }.to_string()));
+        Json::Object(object)
+    }).collect();
+
+    let mut object = BTreeMap::new();
+    object.insert("metrics".to_string(), Json::Array(metrics));
+    Json::Object(object)
+}
+
+// graphite-web's tree format: ids are the query's parent plus the node name,
+// and a name which is both a branch and a leaf is only listed once.
+fn tree_json(query: &str, nodes: &[MetricNode]) -> Json {
+    let parent = match query.rfind('.') {
+        Some(split) => &query[..split + 1],
+        None => ""
+    };
+
+    let mut seen = vec![];
+    let mut tree = vec![];
+    for node in nodes {
+        if seen.contains(&node.name()) {
+            continue;
+        }
+        seen.push(node.name());
+
+        let flag = if node.is_leaf { 0 } else { 1 };
+        let mut object = BTreeMap::new();
+        object.insert("text".to_string(), node.name().to_json());
+        object.insert("id".to_string(), format!("{}{}", parent, node.name()).to_json());
+        object.insert("leaf".to_string(), Json::U64(1 - flag));
+        object.insert("expandable".to_string(), Json::U64(flag));
+        object.insert("allowChildren".to_string(), Json::U64(flag));
+        object.insert("context".to_string(), Json::Object(BTreeMap::new()));
+        tree.push(Json::Object(object));
+    }
+    Json::Array(tree)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tree_json() {
+        let nodes = vec![
+            MetricNode { path: "servers.web01.cpu".to_string(), is_leaf: false },
+            MetricNode { path: "servers.web01.load".to_string(), is_leaf: false },
+            MetricNode { path: "servers.web01.load".to_string(), is_leaf: true }
+        ];
+        assert_eq!(tree_json("servers.web01.*", &nodes).to_string(), concat!(
+            r#"[{"allowChildren":1,"context":{},"expandable":1,"id":"servers.web01.cpu","leaf":0,"text":"cpu"},"#,
+            r#"{"allowChildren":1,"context":{},"expandable":1,"id":"servers.web01.load","leaf":0,"text":"load"}]"#));
+    }
+
+    #[test]
+    fn test_completer() {
+        assert_eq!(completer_query("servers.we"), "servers.we*");
+        assert_eq!(completer_query("servers..load*"), "servers*.load*");
+
+        let nodes = vec![
+            MetricNode { path: "servers.web01".to_string(), is_leaf: false },
+            MetricNode { path: "servers.web02".to_string(), is_leaf: true }
+        ];
+        assert_eq!(completer_json(&nodes).to_string(), concat!(
+            r#"{"metrics":[{"is_leaf":"0","name":"web01","path":"servers.web01."},"#,
+            r#"{"is_leaf":"1","name":"web02","path":"servers.web02"}]}"#));
+    }
+
+    #[test]
+    fn test_node_json() {
+        let node = MetricNode { path: "a.b".to_string(), is_leaf: true };
+        assert_eq!(node_json(&node).to_string(), r#"{"is_leaf":true,"path":"a.b"}"#);
+    }
+}
@@ -0,0 +1,367 @@
+// Just enough HTTP/1.1 to answer Grafana and graphite-web style clients:
+// one request per connection, GET query strings and urlencoded POST bodies.
+
+use whisper::carbon::Shutdown;
+
+use std::io::{ self, Read, Write, ErrorKind };
+use std::net::{ TcpListener, TcpStream };
+use std::str;
+use std::sync::Arc;
+use std::thread::{ self, JoinHandle };
+use std::time::Duration;
+
+const POLL_INTERVAL_MS : u64 = 250;
+const READ_TIMEOUT_SECS : u64 = 30;
+const MAX_HEAD_SIZE : usize = 64 * 1024;
+const MAX_BODY_SIZE : usize = 1024 * 1024;
+// Requests handled at once, each on its own thread. Connections past
+// this are answered with a 503 straight away.
+const MAX_CONNECTIONS : usize = 64;
+
+#[derive(Debug, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    // Query string and form parameters, in the order they were given
+    pub params: Vec<(String, String)>
+}
+
+impl Request {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|&&(ref key, _)| key == name).map(|&(_, ref value)| value.as_str())
+    }
+
+    // Every value for a repeated parameter, like graphite's `target`
+    pub fn param_all(&self, name: &str) -> Vec<&str> {
+        self.params.iter().filter(|&&(ref key, _)| key == name).map(|&(_, ref value)| value.as_str()).collect()
+    }
+
+    pub fn parse(raw: &[u8]) -> Result<Request, String> {
+        let head_end = try!(find(raw, b"\r\n\r\n").ok_or_else(|| "request head is incomplete".to_string()));
+        let head = try!(str::from_utf8(&raw[..head_end]).map_err(|_| "request head is not utf8".to_string()));
+        let body = &raw[head_end + 4 ..];
+
+        let mut lines = head.split("\r\n");
+        let request_line = lines.next().unwrap_or("");
+        let parts: Vec<&str> = request_line.split(' ').collect();
+        if parts.len() != 3 || !parts[2].starts_with("HTTP/") {
+            return Err(format!("malformed request line `{}`", request_line));
+        }
+
+        let mut is_form = false;
+        for header in lines {
+            if let Some(split) = header.find(':') {
+                let name = header[..split].trim().to_lowercase();
+                let value = header[split + 1 ..].trim();
+                if name == "content-type" && value.starts_with("application/x-www-form-urlencoded") {
+                    is_form = true;
+                }
+            }
+        }
+
+        let (path, query) = match parts[1].find('?') {
+            Some(split) => (&parts[1][..split], &parts[1][split + 1 ..]),
+            None => (parts[1], "")
+        };
+
+        let mut params = parse_urlencoded(query);
+        if is_form {
+            let form = try!(str::from_utf8(body).map_err(|_| "request body is not utf8".to_string()));
+            params.extend(parse_urlencoded(form));
+        }
+
+        Ok(Request {
+            method: parts[0].to_string(),
+            path: percent_decode(path),
+            params: params
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>
+}
+
+impl Response {
+    pub fn json(body: String) -> Response {
+        Response { status: 200, content_type: "application/json", body: body.into_bytes() }
+    }
+
+    pub fn text(status: u16, body: String) -> Response {
+        Response { status: status, content_type: "text/plain", body: body.into_bytes() }
+    }
+
+    pub fn not_found() -> Response {
+        Response::text(404, "Not Found\n".to_string())
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            500 => "Internal Server Error",
+            503 => "Service Unavailable",
+            _ => "Unknown"
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        try!(write!(writer, "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status, self.reason(), self.content_type, self.body.len()));
+        try!(writer.write_all(&self.body));
+        writer.flush()
+    }
+}
+
+pub type Handler = Arc<dyn Fn(&Request) -> Response + Send + Sync>;
+
+// Serve requests until shutdown is requested. Like the carbon listeners,
+// joining the returned thread waits for in-flight requests.
+pub fn serve(listener: TcpListener, handler: Handler, shutdown: Shutdown) -> io::Result<JoinHandle<()>> {
+    try!(listener.set_nonblocking(true));
+
+    Ok(thread::spawn(move || {
+        let mut connections: Vec<JoinHandle<()>> = vec![];
+        while !shutdown.is_requested() {
+            match listener.accept() {
+                Ok((mut stream, peer)) => {
+                    // Don't let finished connections pile up
+                    connections.retain(|connection| !connection.is_finished());
+                    if connections.len() >= MAX_CONNECTIONS {
+                        warn!("turning away {}, {} requests are already in flight", peer, connections.len());
+                        turn_away(&mut stream).unwrap_or_else(|e| warn!("unable to turn away {}: {}", peer, e));
+                        continue;
+                    }
+
+                    let handler = handler.clone();
+                    connections.push(thread::spawn(move || {
+                        handle_connection(stream, handler)
+                            .unwrap_or_else(|e| warn!("request from {} failed: {}", peer, e));
+                    }));
+                },
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+                },
+                Err(e) => warn!("unable to accept connection: {}", e)
+            }
+        }
+
+        for connection in connections {
+            connection.join().unwrap_or_else(|_| warn!("connection thread panicked"));
+        }
+    }))
+}
+
+// Answers 503 without waiting on the client. Whatever of the request has
+// already arrived is read first, since closing a socket with unread data
+// resets it and the client would never see the response.
+fn turn_away(stream: &mut TcpStream) -> io::Result<()> {
+    try!(stream.set_nonblocking(true));
+    let mut buf = [0u8; 4096];
+    let mut drained = 0;
+    while drained < MAX_HEAD_SIZE {
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => drained = drained + len,
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => return Err(e)
+        }
+    }
+    try!(stream.set_nonblocking(false));
+    Response::text(503, "Too many requests in flight\n".to_string()).write_to(stream)
+}
+
+fn handle_connection(mut stream: TcpStream, handler: Handler) -> io::Result<()> {
+    try!(stream.set_nonblocking(false));
+    try!(stream.set_read_timeout(Some(Duration::from_secs(READ_TIMEOUT_SECS))));
+
+    let response = match try!(read_request(&mut stream)) {
+        Ok(raw) => match Request::parse(&raw) {
+            Ok(request) => {
+                debug!("{} {} {:?}", request.method, request.path, request.params);
+                handler(&request)
+            },
+            Err(why) => Response::text(400, format!("{}\n", why))
+        },
+        Err(refusal) => refusal
+    };
+    response.write_to(&mut stream)
+}
+
+// Reads the head and, if there is a Content-Length, the body. A body
+// over the size limit is refused before any of it is read.
+fn read_request<R: Read>(stream: &mut R) -> io::Result<Result<Vec<u8>, Response>> {
+    let mut raw = vec![];
+    let mut buf = [0u8; 4096];
+    loop {
+        if let Some(head_end) = find(&raw, b"\r\n\r\n") {
+            let body_len = content_length(&raw[..head_end]);
+            if body_len > MAX_BODY_SIZE {
+                return Ok(Err(Response::text(413, format!("request body is over the {} byte limit\n", MAX_BODY_SIZE))));
+            }
+            if raw.len() >= head_end + 4 + body_len {
+                return Ok(Ok(raw));
+            }
+        } else if raw.len() > MAX_HEAD_SIZE {
+            return Err(io::Error::new(ErrorKind::InvalidData, "request head is too large"));
+        }
+
+        let len = try!(stream.read(&mut buf));
+        if len == 0 {
+            return Ok(Ok(raw));
+        }
+        raw.extend_from_slice(&buf[..len]);
+    }
+}
+
+fn content_length(head: &[u8]) -> usize {
+    str::from_utf8(head).ok().and_then(|head| {
+        head.split("\r\n")
+            .filter_map(|line| line.find(':').map(|split| (&line[..split], &line[split + 1 ..])))
+            .find(|&(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+            .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+    }).unwrap_or(0)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+pub fn parse_urlencoded(encoded: &str) -> Vec<(String, String)> {
+    encoded.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.find('=') {
+            Some(split) => (percent_decode(&pair[..split]), percent_decode(&pair[split + 1 ..])),
+            None => (percent_decode(pair), String::new())
+        })
+        .collect()
+}
+
+pub fn percent_decode(encoded: &str) -> String {
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                match str::from_utf8(&bytes[i + 1 .. i + 3]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i = i + 2;
+                    },
+                    None => decoded.push(b'%')
+                }
+            },
+            byte => decoded.push(byte)
+        }
+        i = i + 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use whisper::carbon::Shutdown;
+
+    use std::io::{ Read, Write };
+    use std::net::{ TcpListener, TcpStream };
+    use std::sync::Arc;
+    use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_get() {
+        let raw = b"GET /render?target=a.b.c&target=sumSeries(a.*.c)&from=-1h&format=json HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let request = Request::parse(raw).unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/render");
+        assert_eq!(request.param_all("target"), vec!["a.b.c", "sumSeries(a.*.c)"]);
+        assert_eq!(request.param("from"), Some("-1h"));
+        assert_eq!(request.param("until"), None);
+    }
+
+    #[test]
+    fn test_parse_form_post() {
+        let raw = b"POST /render HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 43\r\n\r\ntarget=a.%7Bb%2Cc%7D&from=-6h&until=now+1";
+        let request = Request::parse(raw).unwrap();
+        assert_eq!(request.param("target"), Some("a.{b,c}"));
+        assert_eq!(request.param("until"), Some("now 1"));
+    }
+
+    #[test]
+    fn test_body_too_large() {
+        let raw = format!("POST /render HTTP/1.1\r\nContent-Length: {}\r\n\r\ntarget=", MAX_BODY_SIZE + 1);
+        let refusal = read_request(&mut raw.as_bytes()).unwrap().unwrap_err();
+        assert_eq!(refusal.status, 413);
+
+        let raw = b"POST /render HTTP/1.1\r\nContent-Length: 8\r\n\r\ntarget=a";
+        assert_eq!(read_request(&mut &raw[..]).unwrap(), Ok(raw.to_vec()));
+    }
+
+    #[test]
+    fn test_connection_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let released = Arc::new(AtomicBool::new(false));
+        let started = Arc::new(AtomicUsize::new(0));
+        let handler: Handler = {
+            let (released, started) = (released.clone(), started.clone());
+            Arc::new(move |_: &Request| {
+                started.fetch_add(1, Ordering::SeqCst);
+                while !released.load(Ordering::SeqCst) {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Response::json("[]".to_string())
+            })
+        };
+        let shutdown = Shutdown::new();
+        let server = serve(listener, handler, shutdown.clone()).unwrap();
+
+        let request = |stream: &mut TcpStream| stream.write_all(b"GET /metrics/find HTTP/1.1\r\n\r\n").unwrap();
+        let mut busy: Vec<TcpStream> = (0..MAX_CONNECTIONS).map(|_| TcpStream::connect(addr).unwrap()).collect();
+        for stream in &mut busy {
+            request(stream);
+        }
+        while started.load(Ordering::SeqCst) < MAX_CONNECTIONS {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let mut turned_away = TcpStream::connect(addr).unwrap();
+        request(&mut turned_away);
+        let mut response = String::new();
+        turned_away.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"), "{}", response);
+
+        released.store(true, Ordering::SeqCst);
+        let mut response = String::new();
+        busy[0].read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+
+        shutdown.request();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%2Eb"), "a.b");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+
+    #[test]
+    fn test_write_response() {
+        let mut out = vec![];
+        Response::json("[]".to_string()).write_to(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 2\r\nConnection: close\r\n\r\n[]");
+    }
+}
@@ -0,0 +1,46 @@
+// Graphite-web compatible HTTP API serving straight from a whisper tree,
+// enough for Grafana's Graphite datasource.
+
+pub mod http;
+mod find;
+mod render;
+
+use self::http::{ Request, Response };
+use whisper::finder::{ MetricFinder, MetricIndex };
+
+use std::path::PathBuf;
+
+pub use self::render::metric_path;
+
+pub struct GraphiteWeb {
+    base_path: PathBuf,
+    finder: MetricFinder
+}
+
+impl GraphiteWeb {
+    // Globs are expanded by reading the directories under `base_path`
+    pub fn new<P>(base_path: P) -> GraphiteWeb where P: Into<PathBuf> {
+        let base_path = base_path.into();
+        GraphiteWeb {
+            finder: MetricFinder::new(base_path.clone()),
+            base_path: base_path
+        }
+    }
+
+    // Globs are expanded against `index`, which must cover `base_path`
+    pub fn with_index<P>(base_path: P, index: MetricIndex) -> GraphiteWeb where P: Into<PathBuf> {
+        GraphiteWeb {
+            finder: MetricFinder::with_index(index),
+            base_path: base_path.into()
+        }
+    }
+
+    pub fn handle(&self, request: &Request) -> Response {
+        match (request.method.as_str(), request.path.trim_end_matches('/')) {
+            ("GET", "/render") | ("POST", "/render") => render::render(&self.base_path, &self.finder, request),
+            ("GET", "/metrics/find") | ("POST", "/metrics/find") => find::find(&self.finder, request),
+            (_, "/render") | (_, "/metrics/find") => Response::text(405, "Method Not Allowed\n".to_string()),
+            _ => Response::not_found()
+        }
+    }
+}
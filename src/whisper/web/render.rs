@@ -0,0 +1,135 @@
+use rustc_serialize::json::{ Json, ToJson };
+use time;
+
+use std::collections::BTreeMap;
+use std::path::{ Path, PathBuf };
+
+use whisper::NamedPoint;
+use whisper::finder::MetricFinder;
+use whisper::query::{ Context, TimeSeries, WhisperFetcher, parse_time };
+use super::http::{ Request, Response };
+
+// graphite-web's default window when no `from` is given
+const DEFAULT_FROM : &'static str = "-24h";
+
+impl ToJson for TimeSeries {
+    fn to_json(&self) -> Json {
+        let datapoints = self.values.iter().zip(self.timestamps()).map(|(value, timestamp)| {
+            let value = match *value {
+                Some(value) if value.is_finite() => Json::F64(value),
+                _ => Json::Null
+            };
+            Json::Array(vec![value, Json::U64(timestamp as u64)])
+        }).collect();
+
+        let mut object = BTreeMap::new();
+        object.insert("target".to_string(), Json::String(self.name.clone()));
+        object.insert("datapoints".to_string(), Json::Array(datapoints));
+        Json::Object(object)
+    }
+}
+
+pub fn render(base_path: &Path, finder: &MetricFinder, request: &Request) -> Response {
+    let now = time::get_time().sec as u32;
+    match _render(base_path, finder, request, now) {
+        Ok(series) => Response::json(series.to_json().to_string()),
+        Err(why) => Response::text(400, format!("{}\n", why))
+    }
+}
+
+fn _render(base_path: &Path, finder: &MetricFinder, request: &Request, now: u32) -> Result<Vec<TimeSeries>, String> {
+    match request.param("format") {
+        None | Some("json") => (),
+        Some(format) => return Err(format!("unsupported format `{}`", format))
+    }
+
+    let from = try!(parse_time(request.param("from").unwrap_or(DEFAULT_FROM), now));
+    let until = try!(parse_time(request.param("until").unwrap_or("now"), now));
+    if from > until {
+        return Err(format!("from time `{}` is after until time `{}`", from, until));
+    }
+
+    let fetcher = WhisperFetcher { base_path: base_path.to_path_buf(), finder: finder };
+    let context = Context { from: from, until: until, now: now, fetcher: &fetcher };
+
+    let max_points = match request.param("maxDataPoints") {
+        Some(max_points) => Some(try!(max_points.parse::<usize>().map_err(|_| format!("invalid maxDataPoints `{}`", max_points)))),
+        None => None
+    };
+
+    let mut series = vec![];
+    for target in request.param_all("target") {
+        let evaluated = try!(context.evaluate_target(target));
+        match max_points {
+            Some(max_points) => series.extend(evaluated.iter().map(|series| series.consolidate_to(max_points))),
+            None => series.extend(evaluated)
+        }
+    }
+    Ok(series)
+}
+
+// The inverse of `NamedPoint::rel_path`. Names which would climb out of
+// the base path, or which aren't metric names at all, resolve to nothing.
+pub fn metric_path(base_path: &Path, metric_name: &str) -> Option<PathBuf> {
+    if NamedPoint::is_valid_name(metric_name) {
+        Some(base_path.join(NamedPoint::metric_rel_path(metric_name)))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use whisper::{ Schema, WhisperFile, Point, AggregationType };
+    use whisper::query::TimeSeries;
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_metric_path() {
+        let base = Path::new("/whisper");
+        assert_eq!(metric_path(base, "a.b.c"), Some(Path::new("/whisper/a/b/c.wsp").to_path_buf()));
+        assert_eq!(metric_path(base, "a..b"), None);
+        assert_eq!(metric_path(base, "/etc/passwd"), None);
+    }
+
+    #[test]
+    fn test_series_json() {
+        let series = TimeSeries::new("a.b".to_string(), 60, 60, vec![Some(1.5), None, Some(::std::f64::NAN)]);
+        assert_eq!(vec![series].to_json().to_string(),
+            r#"[{"datapoints":[[1.5,60],[null,120],[null,180]],"target":"a.b"}]"#);
+    }
+
+    #[test]
+    fn test_render_skips_missing_targets() {
+        let base = env::temp_dir().join("whisper-render-test");
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(base.join("servers")).unwrap();
+
+        let now = time::get_time().sec as u32;
+        let schema = Schema::new_from_retention_specs(vec!["60s:1d".to_string()]).unwrap();
+        let mut file = WhisperFile::new(base.join("servers/load.wsp"), &schema, AggregationType::Average, 0.5).unwrap();
+        file.write(&Point(now - 120, 4.0));
+
+        let finder = MetricFinder::new(&base);
+        let request = Request::parse(b"GET /render?target=servers.load&target=servers.missing&from=-5min HTTP/1.1\r\n\r\n").unwrap();
+        let series = _render(&base, &finder, &request, now).unwrap();
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0].name, "servers.load");
+        assert_eq!(series[0].step, 60);
+        assert!(series[0].values.contains(&Some(4.0)));
+
+        let request = Request::parse(b"GET /render?target=servers.*&from=-5min HTTP/1.1\r\n\r\n").unwrap();
+        let series = _render(&base, &finder, &request, now).unwrap();
+        assert_eq!(series.iter().map(|series| series.name.as_str()).collect::<Vec<&str>>(), vec!["servers.load"]);
+
+        let request = Request::parse(b"GET /render?target=sumSeries(servers.*)&from=-5min HTTP/1.1\r\n\r\n").unwrap();
+        let series = _render(&base, &finder, &request, now).unwrap();
+        assert_eq!(series[0].name, "sumSeries(servers.*)");
+        assert!(series[0].values.contains(&Some(4.0)));
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}